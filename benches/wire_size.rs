@@ -0,0 +1,63 @@
+// Compares the size of the binary wire format with JSON
+//
+// Run with `cargo bench --features wire --bench wire_size`
+
+use sfi_core::{
+    events::{store::Store, Ean, Item},
+    wire,
+};
+use std::sync::Arc;
+use uuid::Uuid;
+
+fn main() {
+    let mut store = Store::new();
+    let inventory_uuid = store.make_inventory("Pantry".to_string(), Uuid::new_v4());
+    let inventory = Arc::new((**store.inventory(&inventory_uuid).unwrap()).clone());
+
+    let handle = store.inventory_mut(&inventory_uuid).unwrap();
+    for i in 0..1000 {
+        let item = Item::new(&inventory, format!("Item number {}", i), Some(make_ean(i)));
+        handle.create_item(item).unwrap();
+    }
+
+    // The creation of the inventory is left out
+    let events = handle.get_projector().get_events()[1..].to_vec();
+
+    // Single events
+    let json = serde_json::to_vec(&events[0]).unwrap();
+    let binary = wire::encode_event(&events[0]).unwrap();
+    report("single event", json.len(), binary.len());
+
+    // A framed stream of events
+    let json: usize = events
+        .iter()
+        .map(|e| serde_json::to_vec(e).unwrap().len() + 1)
+        .sum();
+    let binary: usize = events
+        .iter()
+        .map(|e| wire::frame(&wire::encode_event(e).unwrap()).len())
+        .sum();
+    report("1000 framed events", json, binary);
+
+    // A whole store (with the 1000 items)
+    let json = serde_json::to_vec(&store).unwrap();
+    let binary = wire::encode_store(&store).unwrap();
+    report("store", json.len(), binary.len());
+}
+
+/// Makes a valid EAN-13 from a number (by trying all check digits)
+fn make_ean(i: usize) -> Ean {
+    (0..10)
+        .find_map(|check| Ean::parse(&format!("400638{:06}{}", i, check)).ok())
+        .unwrap()
+}
+
+fn report(name: &str, json: usize, binary: usize) {
+    println!(
+        "{:<20} json: {:>8} bytes, wire: {:>8} bytes ({:.1}% of json)",
+        name,
+        json,
+        binary,
+        binary as f64 * 100.0 / json as f64
+    );
+}
@@ -0,0 +1,667 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{json, Value};
+use sfi_core::{
+    events::{
+        fsck,
+        store::{InventoryHandle, Role, Store},
+        Ean, Item, Unit,
+    },
+    transfer::{
+        archive::{self, ImportMode},
+        csv,
+    },
+    Utc,
+};
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use structopt::StructOpt;
+use uuid::Uuid;
+
+#[derive(StructOpt)]
+#[structopt(name = "sfi", about = "Manage shared food inventories")]
+struct Opt {
+    /// The store file (created if it doesn't exist)
+    #[structopt(short, long, parse(from_os_str), default_value = "sfi-store.json")]
+    store: PathBuf,
+
+    /// Print machine-readable JSON
+    #[structopt(long)]
+    json: bool,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Creates a new inventory
+    CreateInventory {
+        #[structopt(long)]
+        name: String,
+
+        /// The UUID of the owner
+        #[structopt(long)]
+        owner: Uuid,
+    },
+
+    /// Lists the inventories
+    Inventories,
+
+    /// Lists the items of an inventory (with their units)
+    Items {
+        #[structopt(long)]
+        inventory: Uuid,
+    },
+
+    /// Adds an item to an inventory
+    AddItem {
+        #[structopt(long)]
+        inventory: Uuid,
+
+        #[structopt(long)]
+        name: String,
+
+        #[structopt(long)]
+        ean: Option<Ean>,
+    },
+
+    /// Adds a unit to an item
+    AddUnit {
+        #[structopt(long)]
+        inventory: Uuid,
+
+        #[structopt(long)]
+        item: Uuid,
+
+        /// The name of the unit (defaults to the name of the item)
+        #[structopt(long)]
+        name: Option<String>,
+
+        #[structopt(long, default_value = "100")]
+        percent_left: f64,
+
+        /// How many days the unit keeps after opening
+        #[structopt(long)]
+        use_up_after_days: Option<f64>,
+    },
+
+    /// Lists the units which expire soon
+    Expiring {
+        /// Only look at one inventory
+        #[structopt(long)]
+        inventory: Option<Uuid>,
+
+        #[structopt(long, default_value = "3")]
+        days: i64,
+    },
+
+    /// Uses up some of a unit (opening it if needed)
+    Consume {
+        #[structopt(long)]
+        inventory: Uuid,
+
+        #[structopt(long)]
+        unit: Uuid,
+
+        /// How much to use (in percent of the whole unit)
+        #[structopt(long, default_value = "100")]
+        percent: f64,
+    },
+
+    /// Throws a unit away
+    Discard {
+        #[structopt(long)]
+        inventory: Uuid,
+
+        #[structopt(long)]
+        unit: Uuid,
+    },
+
+    /// Lists the members of an inventory
+    Members {
+        #[structopt(long)]
+        inventory: Uuid,
+    },
+
+    /// Gives a user access to an inventory, or removes it
+    SetRole {
+        #[structopt(long)]
+        inventory: Uuid,
+
+        #[structopt(long)]
+        user: Uuid,
+
+        /// admin, write, read or none
+        #[structopt(long)]
+        role: String,
+    },
+
+    /// Exports the whole store as an archive, or the units of one inventory as CSV
+    Export {
+        /// archive or csv
+        #[structopt(long, default_value = "archive")]
+        format: String,
+
+        /// The inventory to export (CSV only)
+        #[structopt(long)]
+        inventory: Option<Uuid>,
+
+        /// The file to write (defaults to stdout)
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    /// Imports an archive into the store, or CSV into an inventory
+    Import {
+        /// archive or csv
+        #[structopt(long, default_value = "archive")]
+        format: String,
+
+        /// The inventory to import into (CSV only)
+        #[structopt(long)]
+        inventory: Option<Uuid>,
+
+        /// What to do with inventories which exist already: reject, merge or rename (archives only)
+        #[structopt(long, default_value = "reject", parse(try_from_str = parse_mode))]
+        mode: ImportMode,
+
+        /// Only validate the rows (CSV only)
+        #[structopt(long)]
+        dry_run: bool,
+
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+    },
+
+    /// Loads the store and shows what is in it (for debugging)
+    Check,
+
+    /// Finds inconsistencies in a store which can't be loaded, and repairs it by quarantining the offending events
+    Repair {
+        /// Write the repaired store (the quarantined events are saved next to it)
+        #[structopt(long)]
+        write: bool,
+    },
+}
+
+fn parse_role(role: &str) -> Result<Option<Role>> {
+    match role {
+        "admin" => Ok(Some(Role::Admin)),
+        "write" => Ok(Some(Role::Write)),
+        "read" => Ok(Some(Role::Read)),
+        "none" => Ok(None),
+        _ => bail!("Unknown role: {}", role),
+    }
+}
+
+fn parse_mode(mode: &str) -> Result<ImportMode> {
+    match mode {
+        "reject" => Ok(ImportMode::Reject),
+        "merge" => Ok(ImportMode::Merge),
+        "rename" => Ok(ImportMode::Rename),
+        _ => bail!("Unknown import mode: {}", mode),
+    }
+}
+
+fn main() {
+    let opt = Opt::from_args();
+
+    if let Err(e) = run(&opt) {
+        if opt.json {
+            println!("{}", json!({ "error": format!("{:#}", e) }));
+        } else {
+            eprintln!("Error: {:#}", e);
+        }
+
+        std::process::exit(1);
+    }
+}
+
+fn run(opt: &Opt) -> Result<()> {
+    // Broken stores can't be loaded as usual (the repair reads the file on its own)
+    let mut store = match &opt.command {
+        Command::Repair { .. } => Store::new(),
+        _ => load(&opt.store)?,
+    };
+
+    // Every command yields its output both as JSON and as text (and if the store changed)
+    let (output, text, changed): (Value, String, bool) = match &opt.command {
+        Command::CreateInventory { name, owner } => {
+            let uuid = store.make_inventory(name.clone(), *owner);
+            (json!({ "uuid": uuid }), uuid.to_string(), true)
+        }
+        Command::Inventories => {
+            let inventories: Vec<_> = store
+                .iter()
+                .map(|h| json!({ "uuid": h.uuid(), "name": h.name(), "owner": h.owner() }))
+                .collect();
+            let text = store
+                .iter()
+                .map(|h| format!("{}  {}", h.uuid(), h.name()))
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            (json!(inventories), text, false)
+        }
+        Command::Items { inventory } => {
+            let handle = handle(&store, inventory)?;
+            let mut text = vec![];
+
+            for item in handle.items() {
+                text.push(format!("{}  {}", item.uuid(), item.name()));
+
+                for unit in item.units() {
+                    text.push(format!(
+                        "  {}  {}  {:.0}%{}",
+                        unit.uuid(),
+                        unit.name(),
+                        unit.percent_left(),
+                        unit.expires_on()
+                            .map(|e| format!("  expires {}", e.naive_utc().date()))
+                            .unwrap_or_default()
+                    ));
+                }
+            }
+
+            (json!(handle.items()), text.join("\n"), false)
+        }
+        Command::AddItem {
+            inventory,
+            name,
+            ean,
+        } => {
+            let handle = handle_mut(&mut store, inventory)?;
+            let item = Item::new(&Arc::new((**handle).clone()), name.clone(), ean.clone());
+            let uuid = *item.uuid();
+
+            for conflict in handle.create_item(item)? {
+                eprintln!("Warning: the item {} has the same barcode", conflict);
+            }
+
+            (json!({ "uuid": uuid }), uuid.to_string(), true)
+        }
+        Command::AddUnit {
+            inventory,
+            item,
+            name,
+            percent_left,
+            use_up_after_days,
+        } => {
+            let handle = handle_mut(&mut store, inventory)?;
+            let item = handle
+                .item(item)
+                .ok_or_else(|| anyhow!("Item not found"))?
+                .clone();
+            let unit = Unit::new(
+                &Arc::new(item.clone()),
+                use_up_after_days.map(csv::duration_from_days).transpose()?,
+                name.clone().unwrap_or_else(|| item.name().clone()),
+                *percent_left,
+            );
+            let uuid = *unit.uuid();
+            handle.create_unit(unit)?;
+
+            (json!({ "uuid": uuid }), uuid.to_string(), true)
+        }
+        Command::Expiring { inventory, days } => {
+            // A `chrono::Duration` counts milliseconds in an `i64`
+            let before = days
+                .checked_mul(24 * 60 * 60)
+                .filter(|seconds| seconds.abs() <= i64::MAX / 1000)
+                .and_then(|seconds| {
+                    Utc::now().checked_add_signed(chrono::Duration::seconds(seconds))
+                })
+                .ok_or_else(|| anyhow!("The number of days is out of range"))?;
+            let mut expiring = vec![];
+
+            for handle in store
+                .iter()
+                .filter(|h| inventory.map_or(true, |i| *h.uuid() == i))
+            {
+                for unit in handle.items().iter().flat_map(|i| i.units()) {
+                    match unit.expires_on() {
+                        Some(expires_on) if expires_on <= before && *unit.percent_left() > 0.0 => {
+                            expiring.push((expires_on, *handle.uuid(), unit.clone()))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            expiring.sort_by_key(|(expires_on, _, _)| *expires_on);
+
+            let output = expiring
+                .iter()
+                .map(|(expires_on, inventory, unit)| {
+                    json!({
+                        "inventory": inventory,
+                        "unit": unit.uuid(),
+                        "name": unit.name(),
+                        "percent_left": unit.percent_left(),
+                        "expires_on": expires_on,
+                    })
+                })
+                .collect::<Vec<_>>();
+            let text = expiring
+                .iter()
+                .map(|(expires_on, _, unit)| {
+                    format!(
+                        "{}  {}  {}  {:.0}%",
+                        expires_on.naive_utc().date(),
+                        unit.uuid(),
+                        unit.name(),
+                        unit.percent_left()
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            (json!(output), text, false)
+        }
+        Command::Consume {
+            inventory,
+            unit,
+            percent,
+        } => {
+            handle_mut(&mut store, inventory)?.consume_unit(unit, *percent)?;
+
+            (
+                json!({ "consumed": unit }),
+                format!("Consumed {}", unit),
+                true,
+            )
+        }
+        Command::Discard { inventory, unit } => {
+            let handle = handle_mut(&mut store, inventory)?;
+            let target = handle
+                .unit(unit)
+                .ok_or_else(|| anyhow!("Unit not found"))?
+                .clone();
+            handle.delete_unit(target)?;
+
+            (
+                json!({ "discarded": unit }),
+                format!("Discarded {}", unit),
+                true,
+            )
+        }
+        Command::Members { inventory } => {
+            let handle = handle(&store, inventory)?;
+            let members: Vec<_> = handle
+                .members()
+                .into_iter()
+                .map(|user| {
+                    let role = match handle.role_of(&user) {
+                        _ if handle.is_owned_by(&user) => "owner",
+                        Some(Role::Admin) => "admin",
+                        Some(Role::Write) => "write",
+                        Some(Role::Read) => "read",
+                        None => "none",
+                    };
+
+                    (user, role)
+                })
+                .collect();
+            let text = members
+                .iter()
+                .map(|(user, role)| format!("{}  {}", user, role))
+                .collect::<Vec<_>>()
+                .join("\n");
+            let output: Vec<_> = members
+                .iter()
+                .map(|(user, role)| json!({ "user": user, "role": role }))
+                .collect();
+
+            (json!(output), text, false)
+        }
+        Command::SetRole {
+            inventory,
+            user,
+            role,
+        } => {
+            let role = parse_role(role)?;
+            handle_mut(&mut store, inventory)?.set_role(*user, role)?;
+
+            (
+                json!({ "user": user, "role": role }),
+                format!("Updated the access of {}", user),
+                true,
+            )
+        }
+        Command::Export {
+            format,
+            inventory,
+            output,
+        } => {
+            let mut writer: Box<dyn Write> = match output {
+                Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+                None => Box::new(io::stdout()),
+            };
+
+            match format.as_str() {
+                "archive" => archive::export_archive(&store, &mut writer)?,
+                "csv" => {
+                    let inventory = inventory
+                        .as_ref()
+                        .ok_or_else(|| anyhow!("CSV exports need an inventory"))?;
+                    csv::export_csv(handle(&store, inventory)?, &mut writer)?;
+                }
+                _ => bail!("Unknown format: {}", format),
+            }
+
+            writer.flush()?;
+
+            match output {
+                Some(path) => (
+                    json!({ "written": path }),
+                    format!("Wrote {}", path.display()),
+                    false,
+                ),
+                // The exported data is the output (no matter if `--json` is set)
+                None => return Ok(()),
+            }
+        }
+        Command::Import {
+            format,
+            inventory,
+            mode,
+            dry_run,
+            input,
+        } => {
+            let reader = BufReader::new(File::open(input)?);
+
+            match format.as_str() {
+                "archive" => {
+                    let summary = archive::read_archive(reader)?.import_into(&mut store, *mode)?;
+                    let text = format!(
+                        "Created {}, merged {} and renamed {} inventories",
+                        summary.created.len(),
+                        summary.merged.len(),
+                        summary.renamed.len()
+                    );
+                    let output = json!({
+                        "created": summary.created,
+                        "merged": summary.merged,
+                        "renamed": summary.renamed,
+                    });
+
+                    (output, text, true)
+                }
+                "csv" => {
+                    let inventory = inventory
+                        .as_ref()
+                        .ok_or_else(|| anyhow!("CSV imports need an inventory"))?;
+                    let report =
+                        csv::import_csv(handle_mut(&mut store, inventory)?, reader, *dry_run)?;
+
+                    let mut text = vec![format!(
+                        "{} new items, {} new units ({} added to existing items)",
+                        report.items_created, report.units_created, report.units_merged
+                    )];
+                    for error in &report.errors {
+                        text.push(format!("Line {}: {}", error.line, error.message));
+                    }
+
+                    (json!(report), text.join("\n"), !*dry_run)
+                }
+                _ => bail!("Unknown format: {}", format),
+            }
+        }
+        Command::Check => {
+            let inventories: Vec<_> = store
+                .iter()
+                .map(|h| {
+                    json!({
+                        "uuid": h.uuid(),
+                        "name": h.name(),
+                        "events": h.get_projector().get_events().len(),
+                        "items": h.items().len(),
+                        "units": h.items().iter().map(|i| i.units().len()).sum::<usize>(),
+                    })
+                })
+                .collect();
+            let text = inventories
+                .iter()
+                .map(|i| {
+                    format!(
+                        "{}  {}  {} events, {} items, {} units",
+                        i["uuid"].as_str().unwrap_or_default(),
+                        i["name"].as_str().unwrap_or_default(),
+                        i["events"],
+                        i["items"],
+                        i["units"]
+                    )
+                })
+                .chain(
+                    store
+                        .broken()
+                        .iter()
+                        .map(|b| format!("broken inventory: {}", b.error())),
+                )
+                .collect::<Vec<_>>()
+                .join("\n");
+            let broken: Vec<_> = store.broken().iter().map(|b| b.error()).collect();
+
+            (
+                json!({ "inventories": inventories, "broken": broken }),
+                text,
+                false,
+            )
+        }
+        Command::Repair { write } => {
+            let (output, text) = repair(opt, *write)?;
+            (output, text, false)
+        }
+    };
+
+    // The output is only printed after saving, so it never reports a change which was lost
+    if changed {
+        save(&store, &opt.store)?;
+    }
+
+    if opt.json {
+        println!("{}", output);
+    } else if !text.is_empty() {
+        println!("{}", text);
+    }
+
+    Ok(())
+}
+
+/// Inspects the store file and (if asked to) replaces it with the repaired store
+fn repair(opt: &Opt, write: bool) -> Result<(Value, String)> {
+    let value: Value = serde_json::from_reader(BufReader::new(File::open(&opt.store)?))?;
+    let inspection = fsck::inspect(value)?;
+
+    if write && !inspection.is_clean() {
+        let quarantine = opt.store.with_extension("quarantine.json");
+        serde_json::to_writer_pretty(File::create(&quarantine)?, &inspection.issues)?;
+
+        save(&inspection.store, &opt.store)?;
+    }
+
+    let text = if inspection.is_clean() {
+        "No issues found".to_string()
+    } else {
+        inspection
+            .issues
+            .iter()
+            .map(|issue| {
+                format!(
+                    "{}{}: {}",
+                    issue
+                        .inventory_uuid
+                        .map(|uuid| uuid.to_string())
+                        .unwrap_or_else(|| "unknown inventory".to_string()),
+                    issue
+                        .position
+                        .map(|position| format!(", event #{}", position))
+                        .unwrap_or_default(),
+                    issue.message
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    Ok((
+        json!({ "issues": inspection.issues, "repaired": write }),
+        text,
+    ))
+}
+
+/// Loads the store file (or starts with an empty store if there is none)
+fn load(path: &Path) -> Result<Store<'static>> {
+    if !path.exists() {
+        return Ok(Store::new());
+    }
+
+    let file = BufReader::new(File::open(path)?);
+    let value = serde_json::from_reader(file)
+        .with_context(|| format!("The store {} can't be read", path.display()))?;
+
+    // Broken inventories are set aside (and written back when saving), so the others stay usable
+    let store = Store::from_value_lenient(value)?;
+    for broken in store.broken() {
+        eprintln!("Warning: an inventory can't be loaded: {}", broken.error());
+    }
+
+    Ok(store)
+}
+
+/// Saves the store file atomically (by writing a temporary file and renaming it)
+fn save(store: &Store<'static>, path: &Path) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("Invalid store path"))?
+        .to_string_lossy();
+    let temporary = path.with_file_name(format!(".{}.tmp", file_name));
+
+    let mut file = BufWriter::new(File::create(&temporary)?);
+    serde_json::to_writer(&mut file, store)?;
+    file.flush()?;
+    file.get_ref().sync_all()?;
+
+    fs::rename(&temporary, path)?;
+
+    Ok(())
+}
+
+fn handle<'s>(store: &'s Store<'static>, uuid: &Uuid) -> Result<&'s InventoryHandle<'static>> {
+    store
+        .inventory(uuid)
+        .ok_or_else(|| anyhow!("Inventory not found"))
+}
+
+fn handle_mut<'s>(
+    store: &'s mut Store<'static>,
+    uuid: &Uuid,
+) -> Result<&'s mut InventoryHandle<'static>> {
+    store
+        .inventory_mut(uuid)
+        .ok_or_else(|| anyhow!("Inventory not found"))
+}
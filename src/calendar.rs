@@ -0,0 +1,269 @@
+use crate::{events::store::InventoryHandle, Timestamp};
+use anyhow::Result;
+use libocc::events::Utc;
+use std::{io::Write, time::Duration};
+
+/// The longest a content line may be (in octets, without the line break)
+const MAX_LINE_LEN: usize = 75;
+
+/// The options of a calendar export
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CalendarOptions {
+    /// The name of the calendar shown by calendar apps (if any)
+    pub name: Option<String>,
+
+    /// How long before the expiry date to remind the user (one alarm per entry)
+    pub alarms: Vec<Duration>,
+}
+
+/// Writes the expiry dates of the units of some inventories as an iCalendar (RFC 5545) feed
+///
+/// Every unit with an expiry date becomes an all-day event on that date. The UID of the event
+/// is derived from the unit's UUID, so importing a newer export updates the existing events.
+pub fn write_ics<'h, 'a: 'h>(
+    handles: impl IntoIterator<Item = &'h InventoryHandle<'a>>,
+    options: &CalendarOptions,
+    writer: impl Write,
+) -> Result<()> {
+    let mut writer = LineWriter { writer };
+    let stamp = format_timestamp(&Utc::now());
+
+    writer.line("BEGIN:VCALENDAR")?;
+    writer.line("VERSION:2.0")?;
+    writer.line("PRODID:-//sfi//sfi-core//EN")?;
+    writer.line("CALSCALE:GREGORIAN")?;
+    writer.line("METHOD:PUBLISH")?;
+
+    if let Some(name) = &options.name {
+        writer.line(&format!("X-WR-CALNAME:{}", escape_text(name)))?;
+    }
+
+    for handle in handles {
+        for item in handle.items() {
+            for unit in item.units() {
+                let expires_on = match unit.expires_on() {
+                    Some(expires_on) if *unit.percent_left() > 0.0 => expires_on,
+                    _ => continue,
+                };
+
+                let date = expires_on.naive_utc().date();
+                let summary = format!("{} expires", item.name());
+                let description = format!(
+                    "{:.0}% left of {} in {}",
+                    unit.percent_left(),
+                    unit.name(),
+                    handle.name()
+                );
+
+                writer.line("BEGIN:VEVENT")?;
+                writer.line(&format!("UID:{}@sfi", unit.uuid()))?;
+                writer.line(&format!("DTSTAMP:{}", stamp))?;
+                writer.line(&format!("DTSTART;VALUE=DATE:{}", date.format("%Y%m%d")))?;
+
+                // Without an end, an all-day event lasts one day anyway
+                if let Some(end) = date.succ_opt() {
+                    writer.line(&format!("DTEND;VALUE=DATE:{}", end.format("%Y%m%d")))?;
+                }
+
+                writer.line(&format!("SUMMARY:{}", escape_text(&summary)))?;
+                writer.line(&format!("DESCRIPTION:{}", escape_text(&description)))?;
+                writer.line("TRANSP:TRANSPARENT")?;
+
+                for before in &options.alarms {
+                    writer.line("BEGIN:VALARM")?;
+                    writer.line("ACTION:DISPLAY")?;
+                    writer.line(&format!("DESCRIPTION:{}", escape_text(&summary)))?;
+                    writer.line(&format!("TRIGGER:-{}", format_duration(before)))?;
+                    writer.line("END:VALARM")?;
+                }
+
+                writer.line("END:VEVENT")?;
+            }
+        }
+    }
+
+    writer.line("END:VCALENDAR")?;
+
+    Ok(())
+}
+
+/// Writes content lines (folded, with CRLF line breaks)
+struct LineWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> LineWriter<W> {
+    /// Writes a content line, folding it into lines of at most 75 octets
+    ///
+    /// Lines are only split between characters, so multi-byte UTF-8 sequences stay intact.
+    fn line(&mut self, line: &str) -> Result<()> {
+        let mut rest = line;
+        // Continuation lines start with a space, which counts towards their length
+        let mut max_len = MAX_LINE_LEN;
+
+        while rest.len() > max_len {
+            let mut split = max_len;
+            while !rest.is_char_boundary(split) {
+                split -= 1;
+            }
+
+            self.writer.write_all(rest[..split].as_bytes())?;
+            self.writer.write_all(b"\r\n ")?;
+
+            rest = &rest[split..];
+            max_len = MAX_LINE_LEN - 1;
+        }
+
+        self.writer.write_all(rest.as_bytes())?;
+        self.writer.write_all(b"\r\n")?;
+
+        Ok(())
+    }
+}
+
+/// Escapes a TEXT value
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Formats a timestamp as a DATE-TIME in UTC
+fn format_timestamp(timestamp: &Timestamp) -> String {
+    timestamp.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Formats a DURATION value (e.g. `P2D` or `PT1H30M`)
+fn format_duration(duration: &Duration) -> String {
+    let seconds = duration.as_secs();
+    let (days, hours, minutes, seconds) = (
+        seconds / 86_400,
+        seconds % 86_400 / 3_600,
+        seconds % 3_600 / 60,
+        seconds % 60,
+    );
+
+    let mut formatted = "P".to_string();
+
+    if days > 0 {
+        formatted.push_str(&format!("{}D", days));
+    }
+
+    if hours > 0 || minutes > 0 || seconds > 0 || days == 0 {
+        formatted.push('T');
+
+        if hours > 0 {
+            formatted.push_str(&format!("{}H", hours));
+        }
+        if minutes > 0 {
+            formatted.push_str(&format!("{}M", minutes));
+        }
+        if seconds > 0 || (hours == 0 && minutes == 0) {
+            formatted.push_str(&format!("{}S", seconds));
+        }
+    }
+
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{Item, Unit};
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    /// Makes an inventory with an opened unit, which expires in three days
+    fn fridge() -> (InventoryHandle<'static>, Unit) {
+        let mut handle = InventoryHandle::new("Fridge".to_string(), Uuid::new_v4());
+        let item = Item::new(&Arc::new((*handle).clone()), "Milk".to_string(), None);
+        handle.create_item(item.clone()).unwrap();
+
+        let unit = Unit::new(
+            &Arc::new(item),
+            Some(Duration::from_secs(3 * DAY)),
+            "1 l".to_string(),
+            100.0,
+        );
+        handle.create_unit(unit.clone()).unwrap();
+        handle.consume_unit(unit.uuid(), 25.0).unwrap();
+
+        (handle, unit)
+    }
+
+    fn export(handle: &InventoryHandle<'_>, options: &CalendarOptions) -> String {
+        let mut buffer = vec![];
+        write_ics(std::iter::once(handle), options, &mut buffer).unwrap();
+
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn folds_long_lines_between_characters() {
+        let line = format!("DESCRIPTION:{}", "ä€x".repeat(40));
+        let mut writer = LineWriter { writer: vec![] };
+        writer.line(&line).unwrap();
+
+        let written = String::from_utf8(writer.writer).unwrap();
+        let lines: Vec<&str> = written.trim_end_matches("\r\n").split("\r\n").collect();
+
+        assert!(lines.len() > 1);
+        assert!(lines.iter().all(|l| l.len() <= MAX_LINE_LEN));
+        assert!(lines[1..].iter().all(|l| l.starts_with(' ')));
+        assert_eq!(written.replace("\r\n ", "").trim_end(), line);
+    }
+
+    #[test]
+    fn escapes_text_values() {
+        assert_eq!(
+            escape_text("Salt, pepper; oil\\vinegar\r\nto taste"),
+            "Salt\\, pepper\\; oil\\\\vinegar\\nto taste"
+        );
+    }
+
+    #[test]
+    fn keeps_the_uids_of_units_across_exports() {
+        let (handle, unit) = fridge();
+        let uid = format!("UID:{}@sfi\r\n", unit.uuid());
+
+        let first = export(&handle, &CalendarOptions::default());
+        let second = export(&handle, &CalendarOptions::default());
+
+        assert_eq!(first.matches(&uid).count(), 1);
+        assert_eq!(second.matches(&uid).count(), 1);
+        assert!(first.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(first.ends_with("END:VCALENDAR\r\n"));
+    }
+
+    #[test]
+    fn reminds_before_the_expiry() {
+        let (handle, _) = fridge();
+        let options = CalendarOptions {
+            name: Some("Fridge".to_string()),
+            alarms: vec![Duration::from_secs(2 * DAY)],
+        };
+
+        let ics = export(&handle, &options);
+        assert!(ics.contains(
+            "BEGIN:VALARM\r\n\
+             ACTION:DISPLAY\r\n\
+             DESCRIPTION:Milk expires\r\n\
+             TRIGGER:-P2D\r\n\
+             END:VALARM\r\n"
+        ));
+        assert!(ics.contains("X-WR-CALNAME:Fridge\r\n"));
+    }
+}
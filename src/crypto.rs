@@ -0,0 +1,746 @@
+use crate::{
+    events::{
+        store::{InventoryHandle, ProjectionEvent},
+        Inventory,
+    },
+    Timestamp, Utc,
+};
+use anyhow::{anyhow, bail, Result};
+use chacha20poly1305::{
+    aead::{Aead, NewAead, Payload},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use ed25519_dalek::{Keypair, Signature, Signer, Verifier};
+use hkdf::Hkdf;
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{collections::BTreeMap, convert::TryFrom, fmt};
+use uuid::Uuid;
+use zeroize::Zeroize;
+
+pub use x25519_dalek::{PublicKey, StaticSecret};
+
+/// The context string mixed into the derivation of key wrapping keys
+const WRAP_INFO: &[u8] = b"sfi inventory key wrap v1";
+
+/// The context string mixed into the signatures of key epochs
+const EPOCH_SIGNING_CONTEXT: &[u8] = b"sfi key epoch signature v1";
+
+/// Generates a new key pair for a member (the secret key never leaves the member's devices)
+pub fn generate_member_keys() -> (StaticSecret, PublicKey) {
+    let secret = StaticSecret::new(OsRng);
+    let public = PublicKey::from(&secret);
+
+    (secret, public)
+}
+
+/// The symmetric key encrypting the events of one inventory during one key epoch
+#[derive(Clone)]
+pub struct InventoryKey {
+    epoch: u32,
+    key: [u8; 32],
+}
+
+impl InventoryKey {
+    fn generate(epoch: u32) -> Self {
+        let mut key = [0; 32];
+        OsRng.fill_bytes(&mut key);
+
+        Self { epoch, key }
+    }
+
+    /// The epoch this key belongs to
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+}
+
+impl Drop for InventoryKey {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+impl fmt::Debug for InventoryKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print the key itself
+        f.debug_struct("InventoryKey")
+            .field("epoch", &self.epoch)
+            .finish()
+    }
+}
+
+/// An inventory key encrypted for one member
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct WrappedKey {
+    /// The public half of the ephemeral key used for wrapping
+    ephemeral_public: [u8; 32],
+
+    nonce: [u8; 24],
+    ciphertext: Vec<u8>,
+}
+
+/// The owner or an admin of an inventory, who signs the key epochs they make with a device key
+///
+/// The public half of the key pair must be registered for them in the inventory (see `DeviceKey`).
+#[derive(Clone, Copy)]
+pub struct EpochSigner<'k> {
+    /// The UUID of the owner or admin
+    pub user_uuid: Uuid,
+
+    /// The key pair of their device
+    pub keypair: &'k Keypair,
+}
+
+/// The wrapped keys of one key epoch
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct KeyEpoch {
+    /// The number of the epoch (increased with every rotation)
+    epoch: u32,
+
+    /// The timestamp of the creation of the epoch
+    created_on: Timestamp,
+
+    /// The key of the epoch wrapped for each member
+    wrapped_keys: BTreeMap<Uuid, WrappedKey>,
+
+    /// The UUID of the owner or admin who signed the epoch
+    signer_uuid: Uuid,
+
+    /// The public key of the device which signed the epoch
+    signer_key: [u8; 32],
+
+    /// The signature over all of the above (and the inventory)
+    signature: Vec<u8>,
+}
+
+impl KeyEpoch {
+    /// The number of the epoch (increased with every rotation)
+    pub fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
+    /// The timestamp of the creation of the epoch
+    pub fn created_on(&self) -> &Timestamp {
+        &self.created_on
+    }
+
+    /// The UUIDs of the members who can unwrap the key of this epoch
+    pub fn members(&self) -> impl Iterator<Item = &Uuid> {
+        self.wrapped_keys.keys()
+    }
+
+    /// The UUID of the owner or admin who signed the epoch
+    pub fn signer_uuid(&self) -> &Uuid {
+        &self.signer_uuid
+    }
+
+    /// The message which gets signed (binding the wrapped keys to their inventory, epoch and signer)
+    fn message(&self, inventory_uuid: &Uuid) -> Result<Vec<u8>> {
+        let mut message = EPOCH_SIGNING_CONTEXT.to_vec();
+        message.extend_from_slice(inventory_uuid.as_bytes());
+        message.extend_from_slice(self.signer_uuid.as_bytes());
+        message.extend_from_slice(&self.signer_key);
+        message.extend_from_slice(&serde_json::to_vec(&(
+            self.epoch,
+            &self.created_on,
+            &self.wrapped_keys,
+        ))?);
+
+        Ok(message)
+    }
+
+    /// Signs the epoch (again, after its wrapped keys changed)
+    fn sign(&mut self, inventory_uuid: &Uuid, signer: EpochSigner<'_>) -> Result<()> {
+        self.signer_uuid = signer.user_uuid;
+        self.signer_key = signer.keypair.public.to_bytes();
+        self.signature = signer
+            .keypair
+            .sign(&self.message(inventory_uuid)?)
+            .to_bytes()
+            .to_vec();
+
+        Ok(())
+    }
+
+    /// Checks that the epoch was signed by the owner or an admin of the inventory, with a registered device key
+    ///
+    /// Servers relay keyrings, so an epoch which doesn't pass this check may wrap a key they know.
+    /// The roles are checked against the current state of the inventory: epochs signed by a former
+    /// admin are only accepted until they lose their role.
+    pub fn verify(&self, inventory: &Inventory) -> Result<()> {
+        let is_admin = inventory.owner() == &self.signer_uuid
+            || inventory.admins().contains(&self.signer_uuid);
+
+        if !is_admin {
+            bail!(
+                "The key epoch {} was not signed by the owner or an admin",
+                self.epoch
+            );
+        }
+
+        if !inventory
+            .device_keys()
+            .iter()
+            .any(|k| k.user_uuid() == &self.signer_uuid && k.public_key() == &self.signer_key)
+        {
+            bail!(
+                "The key epoch {} was signed with a key which is not registered for its signer",
+                self.epoch
+            );
+        }
+
+        let public_key = ed25519_dalek::PublicKey::from_bytes(&self.signer_key)
+            .map_err(|_| anyhow!("Invalid public key"))?;
+        let signature = Signature::try_from(self.signature.as_slice())
+            .map_err(|_| anyhow!("Invalid signature"))?;
+
+        public_key
+            .verify(&self.message(inventory.uuid())?, &signature)
+            .map_err(|_| anyhow!("The signature of the key epoch {} is not valid", self.epoch))
+    }
+}
+
+/// The key history of an inventory
+///
+/// It only contains wrapped keys, so it can be stored and relayed by servers together with the sealed events.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Keyring {
+    /// The UUID of the inventory
+    inventory_uuid: Uuid,
+
+    /// All key epochs (the last one is used for new events)
+    epochs: Vec<KeyEpoch>,
+}
+
+impl Keyring {
+    /// Creates the keyring of an inventory with a first key for the given members
+    pub fn new(
+        inventory_uuid: Uuid,
+        members: &BTreeMap<Uuid, PublicKey>,
+        signer: EpochSigner<'_>,
+    ) -> Result<(Self, InventoryKey)> {
+        let mut keyring = Self {
+            inventory_uuid,
+            epochs: vec![],
+        };
+
+        let key = keyring.push_epoch(members, signer)?;
+
+        Ok((keyring, key))
+    }
+
+    /// The UUID of the inventory
+    pub fn inventory_uuid(&self) -> &Uuid {
+        &self.inventory_uuid
+    }
+
+    /// All key epochs (the last one is used for new events)
+    pub fn epochs(&self) -> &Vec<KeyEpoch> {
+        &self.epochs
+    }
+
+    /// Checks the signatures of all epochs (see `KeyEpoch::verify`) and that they are numbered in order
+    pub fn verify(&self, inventory: &Inventory) -> Result<()> {
+        if inventory.uuid() != &self.inventory_uuid {
+            bail!("The keyring belongs to another inventory");
+        }
+
+        for (position, epoch) in self.epochs.iter().enumerate() {
+            if position > 0 && epoch.epoch <= self.epochs[position - 1].epoch {
+                bail!("The key epochs are out of order");
+            }
+
+            epoch.verify(inventory)?;
+        }
+
+        Ok(())
+    }
+
+    /// Finds an epoch by its number and checks its signature
+    fn verified(&self, epoch: u32, inventory: &Inventory) -> Result<&KeyEpoch> {
+        if inventory.uuid() != &self.inventory_uuid {
+            bail!("The keyring belongs to another inventory");
+        }
+
+        let epoch = self
+            .epochs
+            .iter()
+            .find(|e| e.epoch == epoch)
+            .ok_or_else(|| anyhow!("No such key epoch"))?;
+
+        epoch.verify(inventory)?;
+
+        Ok(epoch)
+    }
+
+    /// The current epoch (with its signature checked)
+    fn current(&self, inventory: &Inventory) -> Result<&KeyEpoch> {
+        let epoch = self
+            .epochs
+            .last()
+            .ok_or_else(|| anyhow!("The keyring has no keys"))?
+            .epoch;
+
+        self.verified(epoch, inventory)
+    }
+
+    /// Gives a new member access to the current key (signing the current epoch again)
+    pub fn add_member(
+        &mut self,
+        inventory: &Inventory,
+        key: &InventoryKey,
+        member_uuid: Uuid,
+        public_key: &PublicKey,
+        signer: EpochSigner<'_>,
+    ) -> Result<()> {
+        if key.epoch != self.current(inventory)?.epoch {
+            bail!("Only the current key can be shared with new members");
+        }
+
+        let wrapped = wrap_key(&self.inventory_uuid, &member_uuid, key, public_key)?;
+        let inventory_uuid = self.inventory_uuid;
+
+        let current = self
+            .epochs
+            .last_mut()
+            .ok_or_else(|| anyhow!("The keyring has no keys"))?;
+        current.wrapped_keys.insert(member_uuid, wrapped);
+        current.sign(&inventory_uuid, signer)
+    }
+
+    /// Replaces the key for new events with a fresh one, which only the given members can unwrap
+    ///
+    /// This must happen whenever someone loses access to the inventory.
+    /// Old events stay readable with the keys of the old epochs.
+    pub fn rotate(
+        &mut self,
+        members: &BTreeMap<Uuid, PublicKey>,
+        signer: EpochSigner<'_>,
+    ) -> Result<InventoryKey> {
+        self.push_epoch(members, signer)
+    }
+
+    /// Brings the keyring in line with the members of the inventory
+    ///
+    /// The members are everyone who can read the inventory (see `InventoryHandle::members`), whose
+    /// public keys are looked up in `public_keys` (which may contain keys of other users as well).
+    /// New members get the current key, while removed members cause a rotation (returning the new key).
+    pub fn sync_members(
+        &mut self,
+        handle: &InventoryHandle<'_>,
+        key: &InventoryKey,
+        public_keys: &BTreeMap<Uuid, PublicKey>,
+        signer: EpochSigner<'_>,
+    ) -> Result<Option<InventoryKey>> {
+        let members = handle
+            .members()
+            .into_iter()
+            .map(|member_uuid| {
+                public_keys
+                    .get(&member_uuid)
+                    .map(|public_key| (member_uuid, *public_key))
+                    .ok_or_else(|| anyhow!("The member {} has no public key", member_uuid))
+            })
+            .collect::<Result<BTreeMap<_, _>>>()?;
+
+        let inventory: &Inventory = handle;
+        let current = self.current(inventory)?;
+
+        if current.members().any(|m| !members.contains_key(m)) {
+            return self.rotate(&members, signer).map(Some);
+        }
+
+        let added: Vec<_> = members
+            .iter()
+            .filter(|(m, _)| !current.wrapped_keys.contains_key(m))
+            .map(|(m, public_key)| (*m, *public_key))
+            .collect();
+
+        for (member_uuid, public_key) in added {
+            self.add_member(inventory, key, member_uuid, &public_key, signer)?;
+        }
+
+        Ok(None)
+    }
+
+    /// Unwraps the key of an epoch with a member's secret key (after checking the signature of the epoch)
+    pub fn unlock(
+        &self,
+        inventory: &Inventory,
+        epoch: u32,
+        member_uuid: &Uuid,
+        secret: &StaticSecret,
+    ) -> Result<InventoryKey> {
+        let wrapped = self
+            .verified(epoch, inventory)?
+            .wrapped_keys
+            .get(member_uuid)
+            .ok_or_else(|| anyhow!("The member has no access to this key epoch"))?;
+
+        unwrap_key(&self.inventory_uuid, member_uuid, epoch, wrapped, secret)
+    }
+
+    /// Unwraps the current key with a member's secret key (after checking the signature of the epoch)
+    pub fn unlock_current(
+        &self,
+        inventory: &Inventory,
+        member_uuid: &Uuid,
+        secret: &StaticSecret,
+    ) -> Result<InventoryKey> {
+        let epoch = self.current(inventory)?.epoch;
+
+        self.unlock(inventory, epoch, member_uuid, secret)
+    }
+
+    fn push_epoch(
+        &mut self,
+        members: &BTreeMap<Uuid, PublicKey>,
+        signer: EpochSigner<'_>,
+    ) -> Result<InventoryKey> {
+        let epoch = self.epochs.last().map_or(0, |e| e.epoch + 1);
+        let key = InventoryKey::generate(epoch);
+
+        let mut wrapped_keys = BTreeMap::new();
+        for (member_uuid, public_key) in members {
+            wrapped_keys.insert(
+                *member_uuid,
+                wrap_key(&self.inventory_uuid, member_uuid, &key, public_key)?,
+            );
+        }
+
+        let mut key_epoch = KeyEpoch {
+            epoch,
+            created_on: Utc::now(),
+            wrapped_keys,
+            signer_uuid: signer.user_uuid,
+            signer_key: [0; 32],
+            signature: vec![],
+        };
+        key_epoch.sign(&self.inventory_uuid, signer)?;
+        self.epochs.push(key_epoch);
+
+        Ok(key)
+    }
+}
+
+/// Derives the key encrypting an inventory key for one member
+fn wrapping_key(
+    shared_secret: &[u8],
+    inventory_uuid: &Uuid,
+    member_uuid: &Uuid,
+    epoch: u32,
+) -> Result<[u8; 32]> {
+    let mut info = WRAP_INFO.to_vec();
+    info.extend_from_slice(inventory_uuid.as_bytes());
+    info.extend_from_slice(member_uuid.as_bytes());
+    info.extend_from_slice(&epoch.to_le_bytes());
+
+    let mut wrapping_key = [0; 32];
+    Hkdf::<Sha256>::new(None, shared_secret)
+        .expand(&info, &mut wrapping_key)
+        .map_err(|_| anyhow!("Cannot derive the wrapping key"))?;
+
+    Ok(wrapping_key)
+}
+
+fn wrap_key(
+    inventory_uuid: &Uuid,
+    member_uuid: &Uuid,
+    key: &InventoryKey,
+    public_key: &PublicKey,
+) -> Result<WrappedKey> {
+    let ephemeral_secret = StaticSecret::new(OsRng);
+    let ephemeral_public = PublicKey::from(&ephemeral_secret);
+    let shared_secret = ephemeral_secret.diffie_hellman(public_key);
+
+    let wrapping_key = wrapping_key(
+        shared_secret.as_bytes(),
+        inventory_uuid,
+        member_uuid,
+        key.epoch,
+    )?;
+
+    let mut nonce = [0; 24];
+    OsRng.fill_bytes(&mut nonce);
+
+    let ciphertext = XChaCha20Poly1305::new(Key::from_slice(&wrapping_key))
+        .encrypt(XNonce::from_slice(&nonce), &key.key[..])
+        .map_err(|_| anyhow!("Cannot wrap the inventory key"))?;
+
+    Ok(WrappedKey {
+        ephemeral_public: *ephemeral_public.as_bytes(),
+        nonce,
+        ciphertext,
+    })
+}
+
+fn unwrap_key(
+    inventory_uuid: &Uuid,
+    member_uuid: &Uuid,
+    epoch: u32,
+    wrapped: &WrappedKey,
+    secret: &StaticSecret,
+) -> Result<InventoryKey> {
+    let shared_secret = secret.diffie_hellman(&PublicKey::from(wrapped.ephemeral_public));
+    let wrapping_key = wrapping_key(shared_secret.as_bytes(), inventory_uuid, member_uuid, epoch)?;
+
+    let mut plaintext = XChaCha20Poly1305::new(Key::from_slice(&wrapping_key))
+        .decrypt(
+            XNonce::from_slice(&wrapped.nonce),
+            wrapped.ciphertext.as_slice(),
+        )
+        .map_err(|_| anyhow!("Cannot unwrap the inventory key (wrong secret key?)"))?;
+
+    let mut key = InventoryKey {
+        epoch,
+        key: [0; 32],
+    };
+    if plaintext.len() != key.key.len() {
+        bail!("The unwrapped inventory key has an invalid length");
+    }
+    key.key.copy_from_slice(&plaintext);
+    plaintext.zeroize();
+
+    Ok(key)
+}
+
+/// An encrypted event
+///
+/// Everything a server needs to order, store and relay the event is kept in the clear
+/// (and authenticated), while the payload can only be read by members of the inventory.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SealedEvent {
+    /// The UUID of the inventory the event belongs to
+    pub inventory_uuid: Uuid,
+
+    /// The timestamp of the event
+    pub timestamp: Timestamp,
+
+    /// The key epoch the event was encrypted in
+    pub epoch: u32,
+
+    nonce: [u8; 24],
+    ciphertext: Vec<u8>,
+}
+
+impl SealedEvent {
+    /// The data authenticated together with the payload (so servers cannot tamper with it)
+    fn associated_data(inventory_uuid: &Uuid, timestamp: &Timestamp, epoch: u32) -> Vec<u8> {
+        let mut aad = inventory_uuid.as_bytes().to_vec();
+        aad.extend_from_slice(timestamp.to_rfc3339().as_bytes());
+        aad.extend_from_slice(&epoch.to_le_bytes());
+        aad
+    }
+}
+
+/// Encrypts an event of an inventory
+pub fn seal(
+    inventory_uuid: &Uuid,
+    event: &ProjectionEvent<'_>,
+    key: &InventoryKey,
+) -> Result<SealedEvent> {
+    let timestamp = *event.get_timestamp();
+    let aad = SealedEvent::associated_data(inventory_uuid, &timestamp, key.epoch);
+    let plaintext = serde_json::to_vec(event)?;
+
+    let mut nonce = [0; 24];
+    OsRng.fill_bytes(&mut nonce);
+
+    let ciphertext = XChaCha20Poly1305::new(Key::from_slice(&key.key))
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: &plaintext,
+                aad: &aad,
+            },
+        )
+        .map_err(|_| anyhow!("Cannot encrypt the event"))?;
+
+    Ok(SealedEvent {
+        inventory_uuid: *inventory_uuid,
+        timestamp,
+        epoch: key.epoch,
+        nonce,
+        ciphertext,
+    })
+}
+
+/// Decrypts an event (the key must be the one of the event's epoch)
+pub fn open<'a>(sealed: &SealedEvent, key: &InventoryKey) -> Result<ProjectionEvent<'a>> {
+    if sealed.epoch != key.epoch {
+        bail!(
+            "The event was sealed in key epoch {}, not {}",
+            sealed.epoch,
+            key.epoch
+        );
+    }
+
+    let aad = SealedEvent::associated_data(&sealed.inventory_uuid, &sealed.timestamp, sealed.epoch);
+
+    let plaintext = XChaCha20Poly1305::new(Key::from_slice(&key.key))
+        .decrypt(
+            XNonce::from_slice(&sealed.nonce),
+            Payload {
+                msg: &sealed.ciphertext,
+                aad: &aad,
+            },
+        )
+        .map_err(|_| anyhow!("Cannot decrypt the event (wrong key or tampered data)"))?;
+
+    Ok(serde_json::from_slice(&plaintext)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{store::Role, DeviceKey};
+
+    /// Makes an inventory whose owner has a registered device key and a key pair for unwrapping
+    fn inventory() -> (InventoryHandle<'static>, Keypair, StaticSecret, PublicKey) {
+        let owner = Uuid::new_v4();
+        let mut handle = InventoryHandle::new("Pantry".to_string(), owner);
+        let keypair = Keypair::generate(&mut OsRng);
+        handle
+            .add_device_key(DeviceKey::new(owner, keypair.public.to_bytes()))
+            .unwrap();
+        let (secret, public) = generate_member_keys();
+
+        (handle, keypair, secret, public)
+    }
+
+    #[test]
+    fn unlocks_signed_epochs() {
+        let (handle, keypair, secret, public) = inventory();
+        let owner = *handle.owner();
+        let signer = EpochSigner {
+            user_uuid: owner,
+            keypair: &keypair,
+        };
+
+        let members = vec![(owner, public)].into_iter().collect();
+        let (keyring, key) = Keyring::new(*handle.uuid(), &members, signer).unwrap();
+        keyring.verify(&handle).unwrap();
+
+        let unlocked = keyring.unlock_current(&handle, &owner, &secret).unwrap();
+        let event = &handle.get_projector().get_events()[0];
+        let sealed = seal(handle.uuid(), event, &key).unwrap();
+        assert!(open(&sealed, &unlocked).is_ok());
+    }
+
+    #[test]
+    fn rejects_epochs_added_by_others() {
+        let (handle, keypair, secret, public) = inventory();
+        let owner = *handle.owner();
+        let members: BTreeMap<_, _> = vec![(owner, public)].into_iter().collect();
+        let (mut keyring, _) = Keyring::new(
+            *handle.uuid(),
+            &members,
+            EpochSigner {
+                user_uuid: owner,
+                keypair: &keypair,
+            },
+        )
+        .unwrap();
+
+        // A relaying server claims to be the owner, but its device key isn't registered
+        let forged = Keypair::generate(&mut OsRng);
+        keyring
+            .rotate(
+                &members,
+                EpochSigner {
+                    user_uuid: owner,
+                    keypair: &forged,
+                },
+            )
+            .unwrap();
+
+        assert!(keyring.verify(&handle).is_err());
+        assert!(keyring.unlock_current(&handle, &owner, &secret).is_err());
+        assert!(keyring.unlock(&handle, 0, &owner, &secret).is_ok());
+    }
+
+    #[test]
+    fn rotates_when_a_reader_is_removed() {
+        let (mut handle, keypair, owner_secret, owner_public) = inventory();
+        let owner = *handle.owner();
+        let signer = EpochSigner {
+            user_uuid: owner,
+            keypair: &keypair,
+        };
+
+        let reader = Uuid::new_v4();
+        let (reader_secret, reader_public) = generate_member_keys();
+        let public_keys: BTreeMap<_, _> = vec![(owner, owner_public), (reader, reader_public)]
+            .into_iter()
+            .collect();
+
+        let owner_only = vec![(owner, owner_public)].into_iter().collect();
+        let (mut keyring, key) = Keyring::new(*handle.uuid(), &owner_only, signer).unwrap();
+
+        // A new reader gets the current key
+        handle.set_role(reader, Some(Role::Read)).unwrap();
+        assert!(keyring
+            .sync_members(&handle, &key, &public_keys, signer)
+            .unwrap()
+            .is_none());
+        assert_eq!(keyring.epochs().len(), 1);
+        let unlocked = keyring
+            .unlock_current(&handle, &reader, &reader_secret)
+            .unwrap();
+
+        let event = &handle.get_projector().get_events()[0];
+        let old_sealed = seal(handle.uuid(), event, &key).unwrap();
+        assert!(open(&old_sealed, &unlocked).is_ok());
+
+        // Removing them rotates the key
+        handle.set_role(reader, None).unwrap();
+        let new_key = keyring
+            .sync_members(&handle, &key, &public_keys, signer)
+            .unwrap()
+            .unwrap();
+        assert_eq!(new_key.epoch(), 1);
+        keyring.verify(&handle).unwrap();
+
+        let new_sealed = seal(handle.uuid(), event, &new_key).unwrap();
+        let owner_key = keyring
+            .unlock_current(&handle, &owner, &owner_secret)
+            .unwrap();
+        assert!(open(&new_sealed, &owner_key).is_ok());
+
+        // The removed reader can't unlock the new epoch, but can still read the old events
+        assert!(keyring
+            .unlock_current(&handle, &reader, &reader_secret)
+            .is_err());
+        let old_key = keyring.unlock(&handle, 0, &reader, &reader_secret).unwrap();
+        assert!(open(&old_sealed, &old_key).is_ok());
+        assert!(open(&new_sealed, &old_key).is_err());
+
+        // Nothing changes when the members are in sync
+        assert!(keyring
+            .sync_members(&handle, &new_key, &public_keys, signer)
+            .unwrap()
+            .is_none());
+        assert_eq!(keyring.epochs().len(), 2);
+    }
+
+    #[test]
+    fn needs_the_public_keys_of_all_members() {
+        let (mut handle, keypair, _, owner_public) = inventory();
+        let owner = *handle.owner();
+        let signer = EpochSigner {
+            user_uuid: owner,
+            keypair: &keypair,
+        };
+
+        let public_keys = vec![(owner, owner_public)].into_iter().collect();
+        let (mut keyring, key) = Keyring::new(*handle.uuid(), &public_keys, signer).unwrap();
+
+        handle.set_role(Uuid::new_v4(), Some(Role::Write)).unwrap();
+        assert!(keyring
+            .sync_members(&handle, &key, &public_keys, signer)
+            .is_err());
+        assert_eq!(keyring.epochs().len(), 1);
+    }
+}
@@ -0,0 +1,252 @@
+use crate::events::store::Role;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+};
+use uuid::Uuid;
+
+/// A change of an inventory, after it was applied (no matter if it was made locally or merged)
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Change {
+    /// The inventory which changed
+    pub inventory_uuid: Uuid,
+
+    /// What changed
+    pub kind: ChangeKind,
+}
+
+/// What changed in an inventory
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum ChangeKind {
+    /// The inventory was added to a store
+    InventoryAdded,
+
+    /// The name, the owner or the device keys of the inventory changed
+    InventoryUpdated,
+
+    InventoryDeleted,
+
+    MemberAdded {
+        user_uuid: Uuid,
+        role: Role,
+    },
+
+    MemberRoleChanged {
+        user_uuid: Uuid,
+        role: Role,
+    },
+
+    MemberRemoved {
+        user_uuid: Uuid,
+    },
+
+    ItemAdded {
+        item_uuid: Uuid,
+    },
+
+    ItemUpdated {
+        item_uuid: Uuid,
+    },
+
+    ItemRemoved {
+        item_uuid: Uuid,
+    },
+
+    UnitAdded {
+        item_uuid: Uuid,
+        unit_uuid: Uuid,
+    },
+
+    /// A unit changed in another way than being consumed (e.g. it was renamed or moved to another item)
+    UnitUpdated {
+        item_uuid: Uuid,
+        unit_uuid: Uuid,
+    },
+
+    /// Some of a unit was used up (`percent` of the whole unit)
+    UnitConsumed {
+        item_uuid: Uuid,
+        unit_uuid: Uuid,
+        percent: f64,
+    },
+
+    UnitRemoved {
+        item_uuid: Uuid,
+        unit_uuid: Uuid,
+    },
+
+    ShoppingEntryAdded {
+        entry_uuid: Uuid,
+    },
+
+    ShoppingEntryUpdated {
+        entry_uuid: Uuid,
+    },
+
+    ShoppingEntryRemoved {
+        entry_uuid: Uuid,
+    },
+
+    RecipeAdded {
+        recipe_uuid: Uuid,
+    },
+
+    RecipeUpdated {
+        recipe_uuid: Uuid,
+    },
+
+    RecipeRemoved {
+        recipe_uuid: Uuid,
+    },
+
+    RuleAdded {
+        rule_uuid: Uuid,
+    },
+
+    RuleUpdated {
+        rule_uuid: Uuid,
+    },
+
+    RuleRemoved {
+        rule_uuid: Uuid,
+    },
+
+    /// A notification was sent (on any device)
+    NotificationSent {
+        receipt_uuid: Uuid,
+    },
+}
+
+/// A callback which isn't `Send` (e.g. a JavaScript function)
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+struct LocalCallback(Box<dyn FnMut(&Change)>);
+
+// Without atomics, wasm has a single thread, so the callback never actually leaves it
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+unsafe impl Send for LocalCallback {}
+
+#[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+impl LocalCallback {
+    fn call(&mut self, change: &Change) {
+        (self.0)(change)
+    }
+}
+
+/// Identifies a subscription (to end it again)
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+type Callback = Arc<Mutex<dyn FnMut(&Change) + Send>>;
+
+#[derive(Clone)]
+enum Subscriber {
+    Callback(Callback),
+    Channel(Arc<Mutex<Sender<Change>>>),
+}
+
+/// The subscribers to the changes of an inventory (or of every inventory of a store)
+///
+/// Callbacks are called right after a change was applied, on the thread which applied it.
+/// Channels suit clients which rather poll for changes (e.g. from an event loop). A channel
+/// subscription ends by itself once its receiver is dropped. In wasm, callbacks which can't be
+/// sent between threads (like JavaScript functions) are taken by `subscribe_local`.
+///
+/// A copy starts without subscribers, so changes made to a copy (e.g. to try them out) aren't
+/// reported to the subscribers of the original.
+#[derive(Default)]
+pub struct Observers {
+    next_id: u64,
+    subscribers: Vec<(SubscriptionId, Subscriber)>,
+}
+
+impl Clone for Observers {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Observers")
+            .field("subscribers", &self.subscribers.len())
+            .finish()
+    }
+}
+
+impl Observers {
+    /// Calls a function with every change
+    pub fn subscribe(&mut self, callback: impl FnMut(&Change) + Send + 'static) -> SubscriptionId {
+        self.add(Subscriber::Callback(Arc::new(Mutex::new(callback))))
+    }
+
+    /// Calls a function which can't be sent between threads with every change
+    #[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+    pub fn subscribe_local(&mut self, callback: impl FnMut(&Change) + 'static) -> SubscriptionId {
+        let mut callback = LocalCallback(Box::new(callback));
+
+        self.subscribe(move |change| callback.call(change))
+    }
+
+    /// Sends every change into a channel
+    pub fn subscribe_channel(&mut self) -> (SubscriptionId, Receiver<Change>) {
+        let (sender, receiver) = mpsc::channel();
+
+        (
+            self.add(Subscriber::Channel(Arc::new(Mutex::new(sender)))),
+            receiver,
+        )
+    }
+
+    /// Ends a subscription (returns if it existed)
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let before = self.subscribers.len();
+        self.subscribers.retain(|(i, _)| *i != id);
+
+        self.subscribers.len() != before
+    }
+
+    /// If there are no subscribers (so the changes don't have to be described at all)
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.is_empty()
+    }
+
+    /// Delivers changes to every subscriber
+    pub fn notify(&mut self, changes: &[Change]) {
+        if changes.is_empty() {
+            return;
+        }
+
+        self.subscribers.retain(|(_, subscriber)| match subscriber {
+            Subscriber::Callback(callback) => {
+                // A callback which panicked before is still called
+                let mut callback = callback.lock().unwrap_or_else(|e| e.into_inner());
+
+                for change in changes {
+                    (*callback)(change);
+                }
+
+                true
+            }
+            Subscriber::Channel(sender) => {
+                let sender = sender.lock().unwrap_or_else(|e| e.into_inner());
+
+                // Drop the subscription if nobody is listening anymore
+                changes
+                    .iter()
+                    .all(|change| sender.send(change.clone()).is_ok())
+            }
+        });
+    }
+
+    fn add(&mut self, subscriber: Subscriber) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        self.subscribers.push((id, subscriber));
+
+        id
+    }
+}
@@ -0,0 +1,201 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::{convert::TryFrom, fmt, str::FromStr};
+
+/// A validated GTIN barcode (GTIN-8, GTIN-12/UPC-A, GTIN-13/EAN-13 or GTIN-14)
+///
+/// The code is kept in its canonical form: 14 digits, padded with leading zeros.
+#[serde(try_from = "String")]
+#[serde(into = "String")]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Ean(String);
+
+impl Ean {
+    /// Validates and normalizes a barcode (spaces and hyphens are ignored)
+    pub fn parse(code: &str) -> Result<Self> {
+        let digits: String = code
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '-')
+            .collect();
+
+        if !digits.chars().all(|c| c.is_ascii_digit()) {
+            bail!("A GTIN may only contain digits: {}", code);
+        }
+
+        if ![8, 12, 13, 14].contains(&digits.len()) {
+            bail!("A GTIN must have 8, 12, 13 or 14 digits: {}", code);
+        }
+
+        let (payload, check) = digits.split_at(digits.len() - 1);
+        if check_digit(payload) != check.as_bytes()[0] - b'0' {
+            bail!("The check digit of the GTIN is wrong: {}", code);
+        }
+
+        Ok(Self(format!("{:0>14}", digits)))
+    }
+
+    /// The canonical form (14 digits)
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The shortest form of the code (as printed below most barcodes)
+    pub fn short_form(&self) -> &str {
+        if self.0.starts_with("000000") {
+            &self.0[6..]
+        } else if self.0.starts_with("00") {
+            &self.0[2..]
+        } else if self.0.starts_with('0') {
+            &self.0[1..]
+        } else {
+            &self.0
+        }
+    }
+}
+
+/// Calculates the check digit for the digits of a GTIN (without its check digit)
+fn check_digit(payload: &str) -> u8 {
+    // Starting at the right, the digits are weighted 3, 1, 3, 1, ...
+    let sum: u32 = payload
+        .bytes()
+        .rev()
+        .enumerate()
+        .map(|(i, d)| u32::from(d - b'0') * if i % 2 == 0 { 3 } else { 1 })
+        .sum();
+
+    ((10 - sum % 10) % 10) as u8
+}
+
+impl fmt::Display for Ean {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.short_form())
+    }
+}
+
+impl FromStr for Ean {
+    type Err = anyhow::Error;
+
+    fn from_str(code: &str) -> Result<Self> {
+        Self::parse(code)
+    }
+}
+
+impl TryFrom<String> for Ean {
+    type Error = anyhow::Error;
+
+    fn try_from(code: String) -> Result<Self> {
+        Self::parse(&code)
+    }
+}
+
+impl Into<String> for Ean {
+    fn into(self) -> String {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{store::ProjectionEntry, Item};
+    use serde_json::{json, Value};
+
+    #[test]
+    fn validates_the_check_digits_of_all_lengths() {
+        for code in &[
+            "96385074",
+            "036000291452",
+            "4006381333931",
+            "10012345678902",
+        ] {
+            assert!(Ean::parse(code).is_ok(), "{} is valid", code);
+        }
+
+        assert!(Ean::parse("96385075").is_err());
+        assert!(Ean::parse("036000291453").is_err());
+        assert!(Ean::parse("4006381333932").is_err());
+        assert!(Ean::parse("10012345678903").is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_codes() {
+        assert!(Ean::parse("").is_err());
+        assert!(Ean::parse("12345").is_err());
+        assert!(Ean::parse("40063813339310").is_err());
+        assert!(Ean::parse("400638133393100").is_err());
+        assert!(Ean::parse("40063813339x1").is_err());
+    }
+
+    #[test]
+    fn normalizes_to_14_digits() {
+        let ean = Ean::parse("400-6381 333931").unwrap();
+        assert_eq!(ean.as_str(), "04006381333931");
+        assert_eq!(ean.short_form(), "4006381333931");
+        assert_eq!(ean.to_string(), "4006381333931");
+
+        assert_eq!(Ean::parse("96385074").unwrap().as_str(), "00000096385074");
+        assert_eq!(Ean::parse("96385074").unwrap().short_form(), "96385074");
+        assert_eq!(
+            Ean::parse("036000291452").unwrap().short_form(),
+            "36000291452"
+        );
+        assert_eq!(
+            Ean::parse("10012345678902").unwrap().short_form(),
+            "10012345678902"
+        );
+
+        // Every form of a code is the same code
+        assert_eq!(
+            Ean::parse("0036000291452").unwrap(),
+            Ean::parse("036000291452").unwrap()
+        );
+    }
+
+    #[test]
+    fn deserializes_plain_strings() {
+        let ean: Ean = serde_json::from_value(json!("4006381333931")).unwrap();
+        assert_eq!(ean.as_str(), "04006381333931");
+        assert_eq!(serde_json::to_value(&ean).unwrap(), json!("04006381333931"));
+
+        assert!(serde_json::from_value::<Ean>(json!("12345")).is_err());
+    }
+
+    /// Reads an item written before EAN codes were validated
+    fn legacy_item(ean: Value) -> Item {
+        let payload = json!({
+            "Item": {
+                "uuid": "6c3f1a52-8f0e-4a2b-9d55-0a1b2c3d4e03",
+                "inventory_uuid": "6c3f1a52-8f0e-4a2b-9d55-0a1b2c3d4e01",
+                "name": "Oat milk",
+                "units": [],
+                "created_on": "2021-02-01T12:01:00Z",
+                "ean": ean,
+            }
+        });
+
+        match serde_json::from_value(payload).unwrap() {
+            ProjectionEntry::Item(item) => item,
+            _ => panic!("The payload is not an item"),
+        }
+    }
+
+    #[test]
+    fn reads_legacy_barcodes() {
+        let item = legacy_item(json!("4006381333931"));
+        assert_eq!(item.ean(), &Some(Ean::parse("4006381333931").unwrap()));
+        assert!(item.legacy_ean().is_none());
+
+        // Invalid codes are kept as they were
+        let item = legacy_item(json!("12345"));
+        assert!(item.ean().is_none());
+        assert_eq!(item.legacy_ean().as_deref(), Some("12345"));
+
+        let item = legacy_item(json!(4006381333932u64));
+        assert!(item.ean().is_none());
+        assert_eq!(item.legacy_ean().as_deref(), Some("4006381333932"));
+
+        let item = legacy_item(Value::Null);
+        assert!(item.ean().is_none());
+        assert!(item.legacy_ean().is_none());
+    }
+}
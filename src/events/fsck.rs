@@ -0,0 +1,234 @@
+use crate::events::{
+    schema,
+    store::{InventoryHandle, ProjectionEntry, ProjectionEvent, Store},
+};
+use anyhow::{anyhow, Result};
+use libocc::events::Projector;
+use serde::Serialize;
+use serde_json::Value;
+use std::convert::TryInto;
+use uuid::Uuid;
+
+/// An inconsistency found while inspecting a serialized store
+#[derive(Serialize, Clone, Debug)]
+pub struct Issue {
+    /// The inventory with the inconsistency (if it is known)
+    pub inventory_uuid: Option<Uuid>,
+
+    /// The position of the offending event in the log of its inventory (if the issue is about an event)
+    pub position: Option<usize>,
+
+    /// The offending event, which was quarantined (if the issue is about an event)
+    pub event: Option<ProjectionEvent<'static>>,
+
+    /// The offending event as it was serialized (if it couldn't even be read)
+    pub raw_event: Option<Value>,
+
+    /// What is wrong
+    pub message: String,
+}
+
+/// The result of inspecting a serialized store
+#[derive(Clone)]
+pub struct Inspection {
+    /// The repaired store, containing every event which could be applied
+    pub store: Store<'static>,
+
+    /// Everything which is wrong with the serialized store
+    pub issues: Vec<Issue>,
+}
+
+impl Inspection {
+    /// If no inconsistencies were found
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// The events which were left out of the repaired store
+    pub fn quarantined(&self) -> impl Iterator<Item = &ProjectionEvent<'static>> {
+        self.issues.iter().filter_map(|i| i.event.as_ref())
+    }
+}
+
+/// Loads as much of a serialized store as possible and reports everything which is wrong with it
+///
+/// The events of every inventory are read and replayed one after another. An event which can't be
+/// read or applied, or which makes the projection inconsistent, is quarantined and reported instead
+/// of failing the whole inventory.
+///
+/// The inventories which were set aside as broken get another chance. The ones which still can't be
+/// loaded are set aside in the repaired store (unchanged), so a repair never loses an inventory.
+pub fn inspect(value: Value) -> Result<Inspection> {
+    let value = schema::migrate_store(value)?;
+    let projectors = value
+        .get("inventory_projectors")
+        .and_then(Value::as_array)
+        .ok_or_else(|| anyhow!("The store contains no inventories"))?;
+    let broken = value
+        .get("broken_projectors")
+        .and_then(Value::as_array)
+        .map_or(&[][..], Vec::as_slice);
+
+    let mut inspection = Inspection {
+        store: Store::new(),
+        issues: vec![],
+    };
+
+    for (index, raw) in projectors.iter().chain(broken).enumerate() {
+        let result = raw_events(raw)
+            .and_then(|events| {
+                replay(&events, &mut inspection.issues)?
+                    .ok_or_else(|| anyhow!("The inventory has no usable events"))
+            })
+            .and_then(|handle| inspection.store.add_inventory(handle));
+
+        if let Err(e) = result {
+            inspection.store.set_aside(raw.clone(), e.to_string());
+            inspection.issues.push(Issue {
+                inventory_uuid: None,
+                position: None,
+                event: None,
+                raw_event: None,
+                message: format!(
+                    "The inventory #{} can't be loaded and was set aside: {}",
+                    index, e
+                ),
+            });
+        }
+    }
+
+    Ok(inspection)
+}
+
+/// The serialized events of a projector
+///
+/// The events are left serialized, so each of them can be read on its own. Only the log is needed,
+/// the projection is worked out again anyway.
+fn raw_events(raw: &Value) -> Result<Vec<Value>> {
+    if let Some(events) = raw.get("events").and_then(Value::as_array) {
+        return Ok(events.clone());
+    }
+
+    // Fall back to reading the projector as a whole
+    let projector: Projector<'static, ProjectionEntry> = serde_json::from_value(raw.clone())?;
+
+    Ok(projector
+        .get_events()
+        .iter()
+        .map(serde_json::to_value)
+        .collect::<Result<_, _>>()?)
+}
+
+/// Replays the events of an inventory, leaving out (and reporting) the ones which break the projection
+///
+/// The events are applied one after another, like merging them from a peer.
+/// There is no inventory if none of the events could be used.
+fn replay(events: &[Value], issues: &mut Vec<Issue>) -> Result<Option<InventoryHandle<'static>>> {
+    let mut handle: Option<InventoryHandle<'static>> = None;
+
+    for (position, raw) in events.iter().enumerate() {
+        let inventory_uuid = handle.as_ref().map(|h| *h.uuid());
+
+        let event: ProjectionEvent<'static> = match serde_json::from_value(raw.clone()) {
+            Ok(event) => event,
+            Err(e) => {
+                issues.push(Issue {
+                    inventory_uuid,
+                    position: Some(position),
+                    event: None,
+                    raw_event: Some(raw.clone()),
+                    message: format!("The event can't be read: {}", e),
+                });
+                continue;
+            }
+        };
+
+        // The first usable event has to create the inventory
+        let result = match handle.take() {
+            Some(mut current) => {
+                let result = current.merge(event.clone());
+                handle = Some(current);
+                result
+            }
+            None => InventoryHandle::from_events(vec![event.clone()]).map(|h| handle = Some(h)),
+        };
+
+        if let Err(e) = result {
+            issues.push(Issue {
+                inventory_uuid,
+                position: Some(position),
+                event: Some(event),
+                raw_event: None,
+                message: e.to_string(),
+            });
+        }
+    }
+
+    // Start over from the accepted log, so nothing counts as unsaved
+    handle
+        .map(|h| h.get_projector().clone().try_into())
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{schema::STORE_VERSION, Item};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    /// Serializes the log of an inventory with an item, with an unreadable event in the middle
+    fn damaged_store() -> (Value, Uuid) {
+        let mut handle = InventoryHandle::new("Pantry".to_string(), Uuid::new_v4());
+        let item = Item::new(&Arc::new((*handle).clone()), "Rice".to_string(), None);
+        handle.create_item(item).unwrap();
+
+        let mut events: Vec<Value> = handle
+            .get_projector()
+            .get_events()
+            .iter()
+            .map(|e| serde_json::to_value(e).unwrap())
+            .collect();
+        events.insert(1, json!({ "garbage": true }));
+
+        let store = json!({
+            "version": STORE_VERSION,
+            "inventory_projectors": [{ "events": events }],
+        });
+
+        (store, *handle.uuid())
+    }
+
+    #[test]
+    fn quarantines_unreadable_events_on_their_own() {
+        let (store, inventory_uuid) = damaged_store();
+        let inspection = inspect(store).unwrap();
+
+        assert_eq!(inspection.issues.len(), 1);
+        assert_eq!(inspection.issues[0].position, Some(1));
+        assert_eq!(
+            inspection.issues[0].raw_event,
+            Some(json!({ "garbage": true }))
+        );
+
+        let handle = inspection.store.inventory(&inventory_uuid).unwrap();
+        assert_eq!(handle.items().len(), 1);
+        handle.verify_projection().unwrap();
+    }
+
+    #[test]
+    fn keeps_inventories_which_cannot_be_loaded() {
+        let (mut store, inventory_uuid) = damaged_store();
+        let unreadable = json!({ "events": "not a log" });
+        store["broken_projectors"] = json!([unreadable]);
+
+        let inspection = inspect(store).unwrap();
+        assert!(inspection.store.inventory(&inventory_uuid).is_some());
+        assert_eq!(inspection.store.broken().len(), 1);
+        assert_eq!(inspection.store.broken()[0].raw(), &unreadable);
+
+        // The repaired store still has the broken inventory when it is saved
+        let saved = serde_json::to_value(&inspection.store).unwrap();
+        assert_eq!(saved["broken_projectors"], json!([unreadable]));
+    }
+}
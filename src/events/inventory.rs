@@ -3,7 +3,7 @@ use std::borrow::Cow;
 use crate::{
     events::{
         store::{ProjectionEntry, ProjectionEvent},
-        Item,
+        EntryMap, Item, NotificationReceipt, Recipe, Rule, ShoppingEntry,
     },
     Timestamp,
 };
@@ -17,7 +17,7 @@ pub struct Inventory {
     uuid: Uuid,
 
     /// The items in the inventory
-    items: Vec<Item>,
+    items: EntryMap<Item>,
 
     /// The name of the inventory
     name: String,
@@ -36,6 +36,60 @@ pub struct Inventory {
 
     /// A list of UUIDs of users who have administrative read-only to this inventory
     readables: Vec<Uuid>,
+
+    /// The public keys of the devices of the members (used to check the signatures of events)
+    device_keys: Vec<DeviceKey>,
+
+    /// The entries of the shopping list of this inventory
+    shopping_list: EntryMap<ShoppingEntry>,
+
+    /// The recipes of this inventory
+    recipes: EntryMap<Recipe>,
+
+    /// The notification rules of the members of this inventory
+    rules: EntryMap<Rule>,
+
+    /// The receipts of the notifications sent by the rules
+    notification_receipts: EntryMap<NotificationReceipt>,
+}
+
+/// The public signing key of one device of a member
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct DeviceKey {
+    /// The UUID of the member owning the device
+    user_uuid: Uuid,
+
+    /// The Ed25519 public key of the device
+    public_key: [u8; 32],
+
+    /// The timestamp of the registration of the key
+    added_on: Timestamp,
+}
+
+impl DeviceKey {
+    /// Generates a new device key record
+    pub fn new(user_uuid: Uuid, public_key: [u8; 32]) -> Self {
+        Self {
+            user_uuid,
+            public_key,
+            added_on: Utc::now(),
+        }
+    }
+
+    /// The UUID of the member owning the device
+    pub fn user_uuid(&self) -> &Uuid {
+        &self.user_uuid
+    }
+
+    /// The Ed25519 public key of the device
+    pub fn public_key(&self) -> &[u8; 32] {
+        &self.public_key
+    }
+
+    /// The timestamp of the registration of the key
+    pub fn added_on(&self) -> &Timestamp {
+        &self.added_on
+    }
 }
 
 impl Inventory {
@@ -43,13 +97,18 @@ impl Inventory {
     pub(super) fn new(name: String, owner: Uuid, uuid: Uuid, created_on: Timestamp) -> Self {
         Self {
             uuid,
-            items: vec![],
+            items: EntryMap::new(),
             name,
             created_on,
             owner,
             admins: vec![],
             writables: vec![],
             readables: vec![],
+            device_keys: vec![],
+            shopping_list: EntryMap::new(),
+            recipes: EntryMap::new(),
+            rules: EntryMap::new(),
+            notification_receipts: EntryMap::new(),
         }
     }
 }
@@ -68,15 +127,20 @@ impl Inventory {
     }
 
     /// The items in the inventory
-    pub fn items(&self) -> &Vec<Item> {
+    pub fn items(&self) -> &EntryMap<Item> {
         &self.items
     }
 
     /// The items in the inventory
-    pub(super) fn items_mut(&mut self) -> &mut Vec<Item> {
+    pub(super) fn items_mut(&mut self) -> &mut EntryMap<Item> {
         &mut self.items
     }
 
+    /// Finds an item of the inventory by its UUID
+    pub fn item(&self, uuid: &Uuid) -> Option<&Item> {
+        self.items.get(uuid)
+    }
+
     /// The name of the inventory
     pub fn name(&self) -> &String {
         &self.name
@@ -97,15 +161,80 @@ impl Inventory {
         &self.admins
     }
 
+    /// A list of UUIDs of users who have administrative access to this inventory
+    pub(super) fn admins_mut(&mut self) -> &mut Vec<Uuid> {
+        &mut self.admins
+    }
+
     /// A list of UUIDs of users who have administrative write to this inventory
     pub fn writables(&self) -> &Vec<Uuid> {
         &self.writables
     }
 
+    /// A list of UUIDs of users who have administrative write to this inventory
+    pub(super) fn writables_mut(&mut self) -> &mut Vec<Uuid> {
+        &mut self.writables
+    }
+
     /// A list of UUIDs of users who have administrative read-only to this inventory
     pub fn readables(&self) -> &Vec<Uuid> {
         &self.readables
     }
+
+    /// A list of UUIDs of users who have administrative read-only to this inventory
+    pub(super) fn readables_mut(&mut self) -> &mut Vec<Uuid> {
+        &mut self.readables
+    }
+
+    /// The public keys of the devices of the members (used to check the signatures of events)
+    pub fn device_keys(&self) -> &Vec<DeviceKey> {
+        &self.device_keys
+    }
+
+    /// The public keys of the devices of the members (used to check the signatures of events)
+    pub(super) fn device_keys_mut(&mut self) -> &mut Vec<DeviceKey> {
+        &mut self.device_keys
+    }
+
+    /// The entries of the shopping list of this inventory
+    pub fn shopping_list(&self) -> &EntryMap<ShoppingEntry> {
+        &self.shopping_list
+    }
+
+    /// The entries of the shopping list of this inventory
+    pub(super) fn shopping_list_mut(&mut self) -> &mut EntryMap<ShoppingEntry> {
+        &mut self.shopping_list
+    }
+
+    /// The recipes of this inventory
+    pub fn recipes(&self) -> &EntryMap<Recipe> {
+        &self.recipes
+    }
+
+    /// The recipes of this inventory
+    pub(super) fn recipes_mut(&mut self) -> &mut EntryMap<Recipe> {
+        &mut self.recipes
+    }
+
+    /// The notification rules of the members of this inventory
+    pub fn rules(&self) -> &EntryMap<Rule> {
+        &self.rules
+    }
+
+    /// The notification rules of the members of this inventory
+    pub(super) fn rules_mut(&mut self) -> &mut EntryMap<Rule> {
+        &mut self.rules
+    }
+
+    /// The receipts of the notifications sent by the rules
+    pub fn notification_receipts(&self) -> &EntryMap<NotificationReceipt> {
+        &self.notification_receipts
+    }
+
+    /// The receipts of the notifications sent by the rules
+    pub(super) fn notification_receipts_mut(&mut self) -> &mut EntryMap<NotificationReceipt> {
+        &mut self.notification_receipts
+    }
 }
 
 // Changers
@@ -118,13 +247,18 @@ impl<'a> Inventory {
         (
             Event::create(Cow::Owned(ProjectionEntry::Inventory(Self {
                 uuid,
-                items: vec![],
+                items: EntryMap::new(),
                 name,
                 created_on,
                 owner,
                 admins: vec![],
                 writables: vec![],
                 readables: vec![],
+                device_keys: vec![],
+                shopping_list: EntryMap::new(),
+                recipes: EntryMap::new(),
+                rules: EntryMap::new(),
+                notification_receipts: EntryMap::new(),
             }))),
             uuid,
             created_on,
@@ -133,14 +267,22 @@ impl<'a> Inventory {
 
     pub fn delete(self) -> ProjectionEvent<'a> {
         Event::delete(Cow::Owned(ProjectionEntry::Inventory(Self {
-            items: vec![],
+            items: EntryMap::new(),
+            shopping_list: EntryMap::new(),
+            recipes: EntryMap::new(),
+            rules: EntryMap::new(),
+            notification_receipts: EntryMap::new(),
             ..self
         })))
     }
 
     pub fn update_name(self, name: String) -> ProjectionEvent<'a> {
         Event::update(Cow::Owned(ProjectionEntry::Inventory(Self {
-            items: vec![],
+            items: EntryMap::new(),
+            shopping_list: EntryMap::new(),
+            recipes: EntryMap::new(),
+            rules: EntryMap::new(),
+            notification_receipts: EntryMap::new(),
             name,
             ..self
         })))
@@ -148,7 +290,11 @@ impl<'a> Inventory {
 
     pub fn update_owner(self, owner: Uuid) -> ProjectionEvent<'a> {
         Event::update(Cow::Owned(ProjectionEntry::Inventory(Self {
-            items: vec![],
+            items: EntryMap::new(),
+            shopping_list: EntryMap::new(),
+            recipes: EntryMap::new(),
+            rules: EntryMap::new(),
+            notification_receipts: EntryMap::new(),
             owner,
             ..self
         })))
@@ -156,7 +302,11 @@ impl<'a> Inventory {
 
     pub fn update_admins(self, admins: Vec<Uuid>) -> ProjectionEvent<'a> {
         Event::update(Cow::Owned(ProjectionEntry::Inventory(Self {
-            items: vec![],
+            items: EntryMap::new(),
+            shopping_list: EntryMap::new(),
+            recipes: EntryMap::new(),
+            rules: EntryMap::new(),
+            notification_receipts: EntryMap::new(),
             admins,
             ..self
         })))
@@ -164,7 +314,11 @@ impl<'a> Inventory {
 
     pub fn update_writables(self, writables: Vec<Uuid>) -> ProjectionEvent<'a> {
         Event::update(Cow::Owned(ProjectionEntry::Inventory(Self {
-            items: vec![],
+            items: EntryMap::new(),
+            shopping_list: EntryMap::new(),
+            recipes: EntryMap::new(),
+            rules: EntryMap::new(),
+            notification_receipts: EntryMap::new(),
             writables,
             ..self
         })))
@@ -172,9 +326,25 @@ impl<'a> Inventory {
 
     pub fn update_readables(self, readables: Vec<Uuid>) -> ProjectionEvent<'a> {
         Event::update(Cow::Owned(ProjectionEntry::Inventory(Self {
-            items: vec![],
+            items: EntryMap::new(),
+            shopping_list: EntryMap::new(),
+            recipes: EntryMap::new(),
+            rules: EntryMap::new(),
+            notification_receipts: EntryMap::new(),
             readables,
             ..self
         })))
     }
+
+    pub fn update_device_keys(self, device_keys: Vec<DeviceKey>) -> ProjectionEvent<'a> {
+        Event::update(Cow::Owned(ProjectionEntry::Inventory(Self {
+            items: EntryMap::new(),
+            shopping_list: EntryMap::new(),
+            recipes: EntryMap::new(),
+            rules: EntryMap::new(),
+            notification_receipts: EntryMap::new(),
+            device_keys,
+            ..self
+        })))
+    }
 }
@@ -1,7 +1,7 @@
 use crate::{
     events::{
         store::{ProjectionEntry, ProjectionEvent},
-        Inventory, Unit,
+        Ean, EntryMap, Inventory, Unit,
     },
     Timestamp,
 };
@@ -29,27 +29,63 @@ pub struct Item {
     name: String,
 
     /// The units ot the item
-    units: Vec<Unit>,
+    units: EntryMap<Unit>,
 
     /// The timestamp of the creation of the item
     created_on: Timestamp,
 
     /// The EAN code of the item
-    ean: Option<String>,
+    ean: Option<Ean>,
+
+    /// A barcode from an earlier version which isn't a valid GTIN (kept as it was, so it isn't lost)
+    legacy_ean: Option<String>,
+
+    /// The stock below which the item should be bought again (if any)
+    min_stock: Option<MinStock>,
+
+    /// Free-form labels of the item (e.g. "dairy" or "breakfast")
+    tags: Vec<String>,
     // TODO categories
 }
 
+/// The minimum stock of an item
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum MinStock {
+    /// A minimum number of units which aren't used up (no matter how much is left of them)
+    Units(u32),
+
+    /// A minimum of the sum of `percent_left` over all units (e.g. 150 for one and a half units)
+    PercentLeft(f64),
+}
+
+impl MinStock {
+    /// Checks if a stock of units satisfies the minimum
+    pub fn is_satisfied_by<'u>(&self, units: impl IntoIterator<Item = &'u Unit>) -> bool {
+        let units = units.into_iter();
+
+        match self {
+            MinStock::Units(min) => {
+                units.filter(|u| *u.percent_left() > 0.0).count() >= *min as usize
+            }
+            MinStock::PercentLeft(min) => units.map(|u| *u.percent_left()).sum::<f64>() >= *min,
+        }
+    }
+}
+
 impl Item {
     /// Generates a new item
-    pub(super) fn new(inventory: &Arc<Inventory>, name: String, ean: Option<String>) -> Self {
+    pub fn new(inventory: &Arc<Inventory>, name: String, ean: Option<Ean>) -> Self {
         Self {
             uuid: Uuid::new_v4(),
             inventory: Arc::downgrade(inventory),
             inventory_uuid: inventory.uuid().clone(),
             name,
-            units: vec![],
+            units: EntryMap::new(),
             created_on: Utc::now(),
             ean,
+            legacy_ean: None,
+            min_stock: None,
+            tags: vec![],
         }
     }
 }
@@ -72,8 +108,8 @@ impl Item {
         &self.inventory
     }
 
-    /// The inventory which this item belongs to
-    pub(super) fn inventory_uuid(&self) -> &Uuid {
+    /// The UUID of the inventory which this item belongs to
+    pub fn inventory_uuid(&self) -> &Uuid {
         &self.inventory_uuid
     }
 
@@ -83,19 +119,61 @@ impl Item {
     }
 
     /// The units ot the item
-    pub fn units(&self) -> &Vec<Unit> {
+    pub fn units(&self) -> &EntryMap<Unit> {
         &self.units
     }
 
     /// The units ot the item
-    pub(super) fn units_mut(&mut self) -> &mut Vec<Unit> {
+    pub(super) fn units_mut(&mut self) -> &mut EntryMap<Unit> {
         &mut self.units
     }
 
+    /// Finds a unit of the item by its UUID
+    pub fn unit(&self, uuid: &Uuid) -> Option<&Unit> {
+        self.units.get(uuid)
+    }
+
     /// The timestamp of the creation of the item
     pub fn created_on(&self) -> &Timestamp {
         &self.created_on
     }
+
+    /// The EAN code of the item
+    pub fn ean(&self) -> &Option<Ean> {
+        &self.ean
+    }
+
+    /// A barcode from an earlier version which isn't a valid GTIN (kept as it was, so it isn't lost)
+    pub fn legacy_ean(&self) -> &Option<String> {
+        &self.legacy_ean
+    }
+
+    /// The stock below which the item should be bought again (if any)
+    pub fn min_stock(&self) -> &Option<MinStock> {
+        &self.min_stock
+    }
+
+    /// The stock below which the item should be bought again (if any)
+    pub(super) fn min_stock_mut(&mut self) -> &mut Option<MinStock> {
+        &mut self.min_stock
+    }
+
+    /// Free-form labels of the item (e.g. "dairy" or "breakfast")
+    pub fn tags(&self) -> &Vec<String> {
+        &self.tags
+    }
+
+    /// Free-form labels of the item (e.g. "dairy" or "breakfast")
+    pub(super) fn tags_mut(&mut self) -> &mut Vec<String> {
+        &mut self.tags
+    }
+
+    /// If the item has a minimum stock which its units don't satisfy
+    pub fn is_below_min_stock(&self) -> bool {
+        self.min_stock
+            .as_ref()
+            .map_or(false, |m| !m.is_satisfied_by(&self.units))
+    }
 }
 
 impl<'a> Item {
@@ -103,7 +181,7 @@ impl<'a> Item {
     pub fn create(
         inventory: &Arc<Inventory>,
         name: String,
-        ean: Option<String>,
+        ean: Option<Ean>,
     ) -> ProjectionEvent<'a> {
         let uuid = Uuid::new_v4();
         let created_on = Utc::now();
@@ -113,16 +191,19 @@ impl<'a> Item {
             inventory: Arc::downgrade(inventory),
             inventory_uuid: inventory.uuid().clone(),
             name,
-            units: vec![],
+            units: EntryMap::new(),
             created_on,
             ean,
+            legacy_ean: None,
+            min_stock: None,
+            tags: vec![],
         })))
     }
 
     pub fn delete(self) -> ProjectionEvent<'a> {
         // TODO notify the inventory of the deletion of this item
         Event::delete(Cow::Owned(ProjectionEntry::Item(Self {
-            units: vec![],
+            units: EntryMap::new(),
             inventory: Weak::new(),
             ..self
         })))
@@ -148,7 +229,23 @@ impl<'a> Item {
     //     })))
     // }
 
-    pub fn update_ean(self, ean: Option<String>) -> ProjectionEvent<'a> {
-        Event::update(Cow::Owned(ProjectionEntry::Item(Self { ean, ..self })))
+    /// Sets the EAN code of the item (which replaces a legacy barcode)
+    pub fn update_ean(self, ean: Option<Ean>) -> ProjectionEvent<'a> {
+        Event::update(Cow::Owned(ProjectionEntry::Item(Self {
+            ean,
+            legacy_ean: None,
+            ..self
+        })))
+    }
+
+    pub fn update_min_stock(self, min_stock: Option<MinStock>) -> ProjectionEvent<'a> {
+        Event::update(Cow::Owned(ProjectionEntry::Item(Self {
+            min_stock,
+            ..self
+        })))
+    }
+
+    pub fn update_tags(self, tags: Vec<String>) -> ProjectionEvent<'a> {
+        Event::update(Cow::Owned(ProjectionEntry::Item(Self { tags, ..self })))
     }
 }
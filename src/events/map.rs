@@ -0,0 +1,123 @@
+use crate::events::{Item, Unit};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{btree_map, BTreeMap};
+use uuid::Uuid;
+
+/// An entry which is identified by its UUID
+pub trait Keyed {
+    /// What the entry is called in error messages
+    const NAME: &'static str;
+
+    /// The UUID of the entry
+    fn key(&self) -> &Uuid;
+}
+
+/// A collection of entries ordered by their UUIDs (serialized as a sequence, like a `Vec`)
+#[derive(Clone, Debug)]
+pub struct EntryMap<T>(BTreeMap<Uuid, T>);
+
+impl<T> Default for EntryMap<T> {
+    fn default() -> Self {
+        Self(BTreeMap::new())
+    }
+}
+
+impl<T: Keyed> EntryMap<T> {
+    /// Makes an empty collection
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Finds an entry by its UUID
+    pub fn get(&self, uuid: &Uuid) -> Option<&T> {
+        self.0.get(uuid)
+    }
+
+    /// Checks if there is an entry with a UUID
+    pub fn contains(&self, uuid: &Uuid) -> bool {
+        self.0.contains_key(uuid)
+    }
+
+    /// The entries, ordered by their UUIDs
+    pub fn iter(&self) -> btree_map::Values<'_, Uuid, T> {
+        self.0.values()
+    }
+
+    /// The number of entries
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// If there are no entries
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn get_mut(&mut self, uuid: &Uuid) -> Option<&mut T> {
+        self.0.get_mut(uuid)
+    }
+
+    /// Adds an entry, returning the one it replaced (if there was one with the same UUID)
+    pub(crate) fn insert(&mut self, entry: T) -> Option<T> {
+        self.0.insert(*entry.key(), entry)
+    }
+
+    pub(crate) fn remove(&mut self, uuid: &Uuid) -> Option<T> {
+        self.0.remove(uuid)
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.0.clear()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a EntryMap<T> {
+    type Item = &'a T;
+    type IntoIter = btree_map::Values<'a, Uuid, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.values()
+    }
+}
+
+impl<T: Serialize> Serialize for EntryMap<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.values())
+    }
+}
+
+impl<'de, T: Deserialize<'de> + Keyed> Deserialize<'de> for EntryMap<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut map = Self::new();
+
+        for entry in Vec::<T>::deserialize(deserializer)? {
+            let uuid = *entry.key();
+
+            if map.insert(entry).is_some() {
+                return Err(de::Error::custom(format!(
+                    "The {} {} appears twice",
+                    T::NAME,
+                    uuid
+                )));
+            }
+        }
+
+        Ok(map)
+    }
+}
+
+impl Keyed for Item {
+    const NAME: &'static str = "item";
+
+    fn key(&self) -> &Uuid {
+        self.uuid()
+    }
+}
+
+impl Keyed for Unit {
+    const NAME: &'static str = "unit";
+
+    fn key(&self) -> &Uuid {
+        self.uuid()
+    }
+}
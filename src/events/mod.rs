@@ -1,12 +1,28 @@
+pub mod fsck;
+pub mod schema;
+#[cfg(feature = "async")]
+pub mod shared;
 pub mod store;
 pub mod users;
 
+mod changes;
+mod ean;
 mod inventory;
 mod item;
+mod map;
 mod quantity;
+mod recipe;
+mod rule;
+mod shopping;
 mod unit;
 
+pub use changes::*;
+pub use ean::*;
 pub use inventory::*;
 pub use item::*;
+pub use map::*;
 pub use quantity::*;
+pub use recipe::*;
+pub use rule::*;
+pub use shopping::*;
 pub use unit::*;
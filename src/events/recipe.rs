@@ -0,0 +1,444 @@
+use crate::{
+    events::{store::InventoryHandle, Quantity, Unit},
+    Timestamp,
+};
+use anyhow::{anyhow, bail, Result};
+use libocc::events::Utc;
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, collections::BTreeMap};
+use uuid::Uuid;
+
+/// Amounts smaller than this (in percent of a unit) are treated as nothing
+const EPSILON: f64 = 1e-9;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Ingredient {
+    /// The item needed for the recipe
+    item_uuid: Uuid,
+
+    /// How much of the item is needed
+    quantity: Quantity,
+}
+
+impl Ingredient {
+    pub fn new(item_uuid: Uuid, quantity: Quantity) -> Self {
+        Self {
+            item_uuid,
+            quantity,
+        }
+    }
+
+    /// The item needed for the recipe
+    pub fn item_uuid(&self) -> &Uuid {
+        &self.item_uuid
+    }
+
+    /// How much of the item is needed
+    pub fn quantity(&self) -> &Quantity {
+        &self.quantity
+    }
+
+    /// How much of the item is needed, as a sum of `percent_left` over its units
+    ///
+    /// Counted quantities need as many whole units. For weights (and other quantities) one unit
+    /// is assumed to be enough, as the size of the units isn't known.
+    pub fn required_percent(&self) -> f64 {
+        match self.quantity {
+            Quantity::Pieces(n) | Quantity::Cans(n) | Quantity::Packs(n) => n as f64 * 100.0,
+            Quantity::Kg(_) | Quantity::G(_) | Quantity::Other(_) => 100.0,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Recipe {
+    /// The UUID of the recipe
+    uuid: Uuid,
+
+    /// The inventory which this recipe belongs to
+    inventory_uuid: Uuid,
+
+    /// The name of the recipe
+    name: String,
+
+    /// The items needed for the recipe
+    ingredients: Vec<Ingredient>,
+
+    /// The timestamp of the creation of the recipe
+    created_on: Timestamp,
+}
+
+impl Recipe {
+    /// Generates a new recipe
+    pub fn new(inventory_uuid: Uuid, name: String, ingredients: Vec<Ingredient>) -> Self {
+        Self {
+            uuid: Uuid::new_v4(),
+            inventory_uuid,
+            name,
+            ingredients,
+            created_on: Utc::now(),
+        }
+    }
+}
+
+impl PartialEq for Recipe {
+    fn eq(&self, other: &Self) -> bool {
+        self.uuid == other.uuid
+    }
+}
+
+// Getters
+impl Recipe {
+    /// The UUID of the recipe
+    pub fn uuid(&self) -> &Uuid {
+        &self.uuid
+    }
+
+    /// The inventory which this recipe belongs to
+    pub fn inventory_uuid(&self) -> &Uuid {
+        &self.inventory_uuid
+    }
+
+    /// The name of the recipe
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    /// The items needed for the recipe
+    pub fn ingredients(&self) -> &Vec<Ingredient> {
+        &self.ingredients
+    }
+
+    /// The timestamp of the creation of the recipe
+    pub fn created_on(&self) -> &Timestamp {
+        &self.created_on
+    }
+
+    /// How much of each item is needed (ingredients using the same item are added up)
+    fn required_percents(&self) -> BTreeMap<Uuid, f64> {
+        let mut required = BTreeMap::new();
+
+        for ingredient in &self.ingredients {
+            *required.entry(ingredient.item_uuid).or_insert(0.0) += ingredient.required_percent();
+        }
+
+        required
+    }
+}
+
+/// How well a recipe can be cooked from the current stock
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct RecipeMatch {
+    /// The UUID of the recipe
+    pub recipe_uuid: Uuid,
+
+    /// The ingredients which aren't in stock (or not in the needed amount)
+    pub missing: Vec<MissingIngredient>,
+
+    /// The earliest expiry of the units which cooking would use (if any of them expire)
+    pub uses_expiring_on: Option<Timestamp>,
+}
+
+impl RecipeMatch {
+    /// If the recipe can be cooked from the current stock
+    pub fn is_cookable(&self) -> bool {
+        self.missing.is_empty()
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct MissingIngredient {
+    /// The item which is missing
+    pub item_uuid: Uuid,
+
+    /// How much of the item is missing (in percent of a unit)
+    pub missing_percent: f64,
+}
+
+/// Orders units by how soon they should be used: expiring ones first (the earliest first),
+/// then opened ones, then the oldest ones
+pub(crate) fn by_expiry(a: &Unit, b: &Unit) -> Ordering {
+    let expiry = match (a.expires_on(), b.expires_on()) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    };
+
+    expiry
+        .then_with(|| b.opened_on().is_some().cmp(&a.opened_on().is_some()))
+        .then_with(|| a.created_on().cmp(b.created_on()))
+}
+
+/// Which units cooking a recipe would use (and how much of each), and what is missing
+struct CookingPlan {
+    consumed: Vec<(Unit, f64)>,
+    missing: Vec<MissingIngredient>,
+}
+
+// Cooking
+impl<'a> InventoryHandle<'a> {
+    /// Finds the recipes which can be cooked from the current stock, or which miss at most `max_missing` ingredients
+    ///
+    /// Cookable recipes come first, followed by the ones missing fewer ingredients.
+    /// Among these, recipes using units which expire soon are ranked higher.
+    pub fn match_recipes(&self, max_missing: usize) -> Vec<RecipeMatch> {
+        let mut matches: Vec<_> = self
+            .recipes()
+            .iter()
+            .map(|recipe| {
+                let plan = self.plan_cooking(recipe.uuid()).unwrap_or(CookingPlan {
+                    consumed: vec![],
+                    missing: vec![],
+                });
+
+                RecipeMatch {
+                    recipe_uuid: *recipe.uuid(),
+                    uses_expiring_on: plan
+                        .consumed
+                        .iter()
+                        .filter_map(|(unit, _)| unit.expires_on())
+                        .min(),
+                    missing: plan.missing,
+                }
+            })
+            .filter(|m| m.missing.len() <= max_missing)
+            .collect();
+
+        matches.sort_by(|a, b| {
+            a.missing.len().cmp(&b.missing.len()).then_with(|| {
+                match (a.uses_expiring_on, b.uses_expiring_on) {
+                    (Some(a), Some(b)) => a.cmp(&b),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => Ordering::Equal,
+                }
+            })
+        });
+
+        matches
+    }
+
+    /// Cooks a recipe, using up the units closest to expiry first
+    pub fn cook(&mut self, recipe_uuid: &Uuid) -> Result<()> {
+        let plan = self.plan_cooking(recipe_uuid)?;
+
+        if !plan.missing.is_empty() {
+            bail!("Some ingredients of the recipe are missing");
+        }
+
+        for (mut unit, take) in plan.consumed {
+            *unit.percent_left_mut() = (unit.percent_left() - take).max(0.0);
+
+            if unit.opened_on().is_none() {
+                *unit.opened_on_mut() = Some(Utc::now());
+            }
+
+            self.update_unit(unit)?;
+        }
+
+        Ok(())
+    }
+
+    fn plan_cooking(&self, recipe_uuid: &Uuid) -> Result<CookingPlan> {
+        let recipe = self
+            .recipes()
+            .get(recipe_uuid)
+            .ok_or(anyhow!("Recipe not found"))?;
+
+        let mut plan = CookingPlan {
+            consumed: vec![],
+            missing: vec![],
+        };
+
+        for (item_uuid, mut needed) in recipe.required_percents() {
+            let mut units: Vec<_> = self
+                .item(&item_uuid)
+                .map(|i| {
+                    i.units()
+                        .iter()
+                        .filter(|u| *u.percent_left() > EPSILON)
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            units.sort_by(|a, b| by_expiry(a, b));
+
+            for unit in units {
+                if needed <= EPSILON {
+                    break;
+                }
+
+                let take = unit.percent_left().min(needed);
+                needed -= take;
+                plan.consumed.push((unit.clone(), take));
+            }
+
+            if needed > EPSILON {
+                plan.missing.push(MissingIngredient {
+                    item_uuid,
+                    missing_percent: needed,
+                });
+            }
+        }
+
+        Ok(plan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Item;
+    use std::{sync::Arc, time::Duration};
+
+    /// Adds an item with units to an inventory (the units have the given `percent_left`)
+    fn add_item(handle: &mut InventoryHandle<'static>, name: &str, units: &[f64]) -> Uuid {
+        let item = Item::new(&Arc::new((**handle).clone()), name.to_string(), None);
+        let item_uuid = *item.uuid();
+        handle.create_item(item.clone()).unwrap();
+
+        let item = Arc::new(item);
+        for percent_left in units {
+            let unit = Unit::new(&item, None, name.to_string(), *percent_left);
+            handle.create_unit(unit).unwrap();
+        }
+
+        item_uuid
+    }
+
+    /// Adds an opened unit to an item, which has to be used up within a day
+    fn add_opened_unit(handle: &mut InventoryHandle<'static>, item_uuid: &Uuid) -> Uuid {
+        let item = Arc::new(handle.item(item_uuid).unwrap().clone());
+        let day = Some(Duration::from_secs(24 * 60 * 60));
+        let unit = Unit::new(&item, day, "Opened".to_string(), 100.0);
+        let unit_uuid = *unit.uuid();
+        handle.create_unit(unit).unwrap();
+        handle.consume_unit(&unit_uuid, 10.0).unwrap();
+
+        unit_uuid
+    }
+
+    fn add_recipe(handle: &mut InventoryHandle<'static>, name: &str, items: &[Uuid]) -> Uuid {
+        let ingredients = items
+            .iter()
+            .map(|item_uuid| Ingredient::new(*item_uuid, Quantity::Pieces(1)))
+            .collect();
+        let recipe = Recipe::new(*handle.uuid(), name.to_string(), ingredients);
+        let recipe_uuid = *recipe.uuid();
+        handle.create_recipe(recipe).unwrap();
+
+        recipe_uuid
+    }
+
+    fn percent_left(handle: &InventoryHandle<'_>, unit_uuid: &Uuid) -> f64 {
+        *handle.unit(unit_uuid).unwrap().percent_left()
+    }
+
+    #[test]
+    fn ranks_cookable_recipes_first() {
+        let mut handle = InventoryHandle::new("Kitchen".to_string(), Uuid::new_v4());
+        let eggs = add_item(&mut handle, "Eggs", &[100.0]);
+        let milk = add_item(&mut handle, "Milk", &[]);
+        let flour = add_item(&mut handle, "Flour", &[]);
+
+        let pancakes = add_recipe(&mut handle, "Pancakes", &[eggs, milk, flour]);
+        let omelette = add_recipe(&mut handle, "Omelette", &[eggs, milk]);
+        let boiled_eggs = add_recipe(&mut handle, "Boiled eggs", &[eggs]);
+
+        let matches = handle.match_recipes(2);
+        let order: Vec<Uuid> = matches.iter().map(|m| m.recipe_uuid).collect();
+        assert_eq!(order, vec![boiled_eggs, omelette, pancakes]);
+        assert!(matches[0].is_cookable());
+        assert_eq!(matches[1].missing.len(), 1);
+        assert_eq!(matches[1].missing[0].item_uuid, milk);
+        assert_eq!(matches[2].missing.len(), 2);
+
+        assert_eq!(handle.match_recipes(1).len(), 2);
+        assert_eq!(handle.match_recipes(0).len(), 1);
+    }
+
+    #[test]
+    fn ranks_recipes_using_expiring_units_first() {
+        let mut handle = InventoryHandle::new("Kitchen".to_string(), Uuid::new_v4());
+        let rice = add_item(&mut handle, "Rice", &[100.0]);
+        let milk = add_item(&mut handle, "Milk", &[100.0]);
+        add_opened_unit(&mut handle, &milk);
+
+        let fried_rice = add_recipe(&mut handle, "Fried rice", &[rice]);
+        let pudding = add_recipe(&mut handle, "Pudding", &[milk]);
+
+        let matches = handle.match_recipes(0);
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].recipe_uuid, pudding);
+        assert!(matches[0].uses_expiring_on.is_some());
+        assert_eq!(matches[1].recipe_uuid, fried_rice);
+        assert!(matches[1].uses_expiring_on.is_none());
+    }
+
+    #[test]
+    fn cooks_with_the_units_closest_to_expiry_first() {
+        let mut handle = InventoryHandle::new("Kitchen".to_string(), Uuid::new_v4());
+        let milk = add_item(&mut handle, "Milk", &[100.0]);
+        let sealed = *handle
+            .item(&milk)
+            .unwrap()
+            .units()
+            .iter()
+            .next()
+            .unwrap()
+            .uuid();
+        let opened = add_opened_unit(&mut handle, &milk);
+        let recipe = add_recipe(&mut handle, "Pudding", &[milk]);
+
+        handle.cook(&recipe).unwrap();
+
+        // The opened unit is used up, the rest comes from the sealed one (which is opened now)
+        assert_eq!(percent_left(&handle, &opened), 0.0);
+        assert!((percent_left(&handle, &sealed) - 90.0).abs() < 1e-9);
+        assert!(handle.unit(&sealed).unwrap().opened_on().is_some());
+    }
+
+    #[test]
+    fn refuses_to_cook_with_missing_ingredients() {
+        let mut handle = InventoryHandle::new("Kitchen".to_string(), Uuid::new_v4());
+        let eggs = add_item(&mut handle, "Eggs", &[100.0]);
+        let egg = *handle
+            .item(&eggs)
+            .unwrap()
+            .units()
+            .iter()
+            .next()
+            .unwrap()
+            .uuid();
+        let milk = add_item(&mut handle, "Milk", &[]);
+        let omelette = add_recipe(&mut handle, "Omelette", &[eggs, milk]);
+
+        assert!(handle.cook(&omelette).is_err());
+        assert_eq!(percent_left(&handle, &egg), 100.0);
+        assert!(handle.unit(&egg).unwrap().opened_on().is_none());
+    }
+
+    #[test]
+    fn adds_up_ingredients_of_the_same_item() {
+        let mut handle = InventoryHandle::new("Kitchen".to_string(), Uuid::new_v4());
+        let eggs = add_item(&mut handle, "Eggs", &[100.0]);
+        let recipe = Recipe::new(
+            *handle.uuid(),
+            "Cake".to_string(),
+            vec![
+                Ingredient::new(eggs, Quantity::Pieces(2)),
+                Ingredient::new(eggs, Quantity::G(50)),
+            ],
+        );
+        assert_eq!(recipe.required_percents().get(&eggs), Some(&300.0));
+
+        let recipe_uuid = *recipe.uuid();
+        handle.create_recipe(recipe).unwrap();
+
+        let matches = handle.match_recipes(1);
+        assert_eq!(matches[0].recipe_uuid, recipe_uuid);
+        assert_eq!(matches[0].missing.len(), 1);
+        assert!((matches[0].missing[0].missing_percent - 200.0).abs() < 1e-9);
+    }
+}
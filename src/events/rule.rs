@@ -0,0 +1,428 @@
+use crate::{
+    events::{store::InventoryHandle, MinStock},
+    Timestamp,
+};
+use anyhow::Result;
+use libocc::events::Utc;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    time::Duration,
+};
+use uuid::Uuid;
+
+/// The condition which makes a rule send a notification
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum RuleKind {
+    /// Notify a number of days before a unit expires
+    BeforeExpiry { days: u32 },
+
+    /// Notify when a unit was opened longer ago than a duration (and isn't used up yet)
+    OpenedOlderThan { duration: Duration },
+
+    /// Notify when the stock of an item drops below a minimum (again after it was restocked)
+    LowStock {
+        item_uuid: Uuid,
+        min_stock: MinStock,
+    },
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Rule {
+    /// The UUID of the rule
+    uuid: Uuid,
+
+    /// The inventory which this rule watches
+    inventory_uuid: Uuid,
+
+    /// The member who gets notified
+    user_uuid: Uuid,
+
+    /// The condition which makes the rule send a notification
+    kind: RuleKind,
+
+    /// The timestamp of the creation of the rule
+    created_on: Timestamp,
+}
+
+impl Rule {
+    /// Generates a new rule
+    pub fn new(inventory_uuid: Uuid, user_uuid: Uuid, kind: RuleKind) -> Self {
+        Self {
+            uuid: Uuid::new_v4(),
+            inventory_uuid,
+            user_uuid,
+            kind,
+            created_on: Utc::now(),
+        }
+    }
+}
+
+impl PartialEq for Rule {
+    fn eq(&self, other: &Self) -> bool {
+        self.uuid == other.uuid
+    }
+}
+
+// Getters
+impl Rule {
+    /// The UUID of the rule
+    pub fn uuid(&self) -> &Uuid {
+        &self.uuid
+    }
+
+    /// The inventory which this rule watches
+    pub fn inventory_uuid(&self) -> &Uuid {
+        &self.inventory_uuid
+    }
+
+    /// The member who gets notified
+    pub fn user_uuid(&self) -> &Uuid {
+        &self.user_uuid
+    }
+
+    /// The condition which makes the rule send a notification
+    pub fn kind(&self) -> &RuleKind {
+        &self.kind
+    }
+
+    /// The timestamp of the creation of the rule
+    pub fn created_on(&self) -> &Timestamp {
+        &self.created_on
+    }
+}
+
+/// The record of a sent notification (shared, so other devices don't send it again)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct NotificationReceipt {
+    /// The UUID of the receipt
+    uuid: Uuid,
+
+    /// The inventory which the notification was about
+    inventory_uuid: Uuid,
+
+    /// The rule which sent the notification
+    rule_uuid: Uuid,
+
+    /// The unit or item which the notification was about
+    subject_uuid: Uuid,
+
+    /// The timestamp the notification was sent on
+    sent_on: Timestamp,
+}
+
+impl NotificationReceipt {
+    /// Generates the receipt of a notification which was just sent
+    pub fn new(notification: &Notification, sent_on: Timestamp) -> Self {
+        Self {
+            uuid: Uuid::new_v4(),
+            inventory_uuid: notification.inventory_uuid,
+            rule_uuid: notification.rule_uuid,
+            subject_uuid: notification.subject_uuid,
+            sent_on,
+        }
+    }
+}
+
+impl PartialEq for NotificationReceipt {
+    fn eq(&self, other: &Self) -> bool {
+        self.uuid == other.uuid
+    }
+}
+
+// Getters
+impl NotificationReceipt {
+    /// The UUID of the receipt
+    pub fn uuid(&self) -> &Uuid {
+        &self.uuid
+    }
+
+    /// The inventory which the notification was about
+    pub fn inventory_uuid(&self) -> &Uuid {
+        &self.inventory_uuid
+    }
+
+    /// The rule which sent the notification
+    pub fn rule_uuid(&self) -> &Uuid {
+        &self.rule_uuid
+    }
+
+    /// The unit or item which the notification was about
+    pub fn subject_uuid(&self) -> &Uuid {
+        &self.subject_uuid
+    }
+
+    /// The timestamp the notification was sent on
+    pub fn sent_on(&self) -> &Timestamp {
+        &self.sent_on
+    }
+}
+
+/// A notification which is due to be sent
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Notification {
+    /// The inventory which the notification is about
+    pub inventory_uuid: Uuid,
+
+    /// The rule which sends the notification
+    pub rule_uuid: Uuid,
+
+    /// The member to notify
+    pub user_uuid: Uuid,
+
+    /// The unit (or for low stock, the item) which the notification is about
+    pub subject_uuid: Uuid,
+
+    /// When the notification became due
+    pub due_on: Timestamp,
+
+    /// A short text for the user
+    pub message: String,
+}
+
+// Notifications
+impl<'a> InventoryHandle<'a> {
+    /// Finds the notifications which are due, leaving out the ones which were sent already
+    pub fn due_notifications(&self, now: Timestamp) -> Vec<Notification> {
+        let mut due = vec![];
+
+        for rule in self.rules() {
+            let mut notify = |subject_uuid: Uuid, due_on: Timestamp, message: String| {
+                if due_on <= now && !self.was_sent(rule, &subject_uuid) {
+                    due.push(Notification {
+                        inventory_uuid: *self.uuid(),
+                        rule_uuid: *rule.uuid(),
+                        user_uuid: *rule.user_uuid(),
+                        subject_uuid,
+                        due_on,
+                        message,
+                    });
+                }
+            };
+
+            match rule.kind() {
+                RuleKind::BeforeExpiry { days } => {
+                    for unit in self.items().iter().flat_map(|i| i.units()) {
+                        if *unit.percent_left() <= 0.0 {
+                            continue;
+                        }
+
+                        let expires_on = match unit.expires_on() {
+                            Some(expires_on) => expires_on,
+                            None => continue,
+                        };
+
+                        // A synced rule may reach beyond the range of timestamps
+                        let due_on = match expires_on
+                            .checked_sub_signed(chrono::Duration::days(i64::from(*days)))
+                        {
+                            Some(due_on) => due_on,
+                            None => continue,
+                        };
+
+                        notify(
+                            *unit.uuid(),
+                            due_on,
+                            format!(
+                                "{} expires on {}",
+                                unit.name(),
+                                expires_on.naive_utc().date()
+                            ),
+                        );
+                    }
+                }
+                RuleKind::OpenedOlderThan { duration } => {
+                    let duration = match chrono::Duration::from_std(*duration) {
+                        Ok(duration) => duration,
+                        Err(_) => continue,
+                    };
+
+                    for unit in self.items().iter().flat_map(|i| i.units()) {
+                        if *unit.percent_left() <= 0.0 {
+                            continue;
+                        }
+
+                        let opened_on = match unit.opened_on() {
+                            Some(opened_on) => opened_on,
+                            None => continue,
+                        };
+                        let due_on = match opened_on.checked_add_signed(duration) {
+                            Some(due_on) => due_on,
+                            None => continue,
+                        };
+
+                        notify(
+                            *unit.uuid(),
+                            due_on,
+                            format!(
+                                "{} was opened on {}",
+                                unit.name(),
+                                opened_on.naive_utc().date()
+                            ),
+                        );
+                    }
+                }
+                RuleKind::LowStock {
+                    item_uuid,
+                    min_stock,
+                } => {
+                    if let Some(item) = self.item(item_uuid) {
+                        if !min_stock.is_satisfied_by(item.units()) {
+                            notify(*item_uuid, now, format!("{} is running low", item.name()));
+                        }
+                    }
+                }
+            }
+        }
+
+        due
+    }
+
+    /// Records that a notification was sent (on this device), so it won't be due again
+    ///
+    /// The receipts which can't matter anymore are deleted at the same time, so they don't pile up.
+    pub fn mark_sent(&mut self, notification: &Notification, sent_on: Timestamp) -> Result<()> {
+        self.create_notification_receipt(NotificationReceipt::new(notification, sent_on))?;
+        self.prune_notification_receipts()
+    }
+
+    /// Deletes the receipts which can't matter anymore
+    ///
+    /// These are the receipts of deleted rules, the ones about deleted units or items, and the ones
+    /// superseded by a newer receipt of the same rule about the same unit or item.
+    pub fn prune_notification_receipts(&mut self) -> Result<()> {
+        let mut newest: HashMap<(Uuid, Uuid), &NotificationReceipt> = HashMap::new();
+        let mut stale = vec![];
+
+        for receipt in self.notification_receipts() {
+            let rule_known = self.rules().contains(receipt.rule_uuid());
+            let subject_known = self.item(receipt.subject_uuid()).is_some()
+                || self.unit(receipt.subject_uuid()).is_some();
+
+            if !rule_known || !subject_known {
+                stale.push(receipt.clone());
+                continue;
+            }
+
+            match newest.entry((*receipt.rule_uuid(), *receipt.subject_uuid())) {
+                Entry::Vacant(entry) => {
+                    entry.insert(receipt);
+                }
+                Entry::Occupied(mut entry) => {
+                    if receipt.sent_on() > entry.get().sent_on() {
+                        stale.push(entry.insert(receipt).clone());
+                    } else {
+                        stale.push(receipt.clone());
+                    }
+                }
+            }
+        }
+
+        for receipt in stale {
+            self.delete_notification_receipt(receipt)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks for a receipt of a rule's notification about a unit or item
+    ///
+    /// Low stock receipts only count until the next unit of the item is added,
+    /// so the stock running low again gets notified again.
+    fn was_sent(&self, rule: &Rule, subject_uuid: &Uuid) -> bool {
+        self.notification_receipts()
+            .iter()
+            .filter(|r| r.rule_uuid() == rule.uuid() && r.subject_uuid() == subject_uuid)
+            .any(|receipt| match rule.kind() {
+                RuleKind::LowStock { item_uuid, .. } => !self.item(item_uuid).map_or(false, |i| {
+                    i.units().iter().any(|u| u.created_on() > receipt.sent_on())
+                }),
+                _ => true,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{Item, Unit};
+    use std::sync::Arc;
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    /// Makes an inventory with a unit which was just opened
+    fn opened_unit(use_up_after: Option<Duration>) -> (InventoryHandle<'static>, Uuid) {
+        let mut handle = InventoryHandle::new("Fridge".to_string(), Uuid::new_v4());
+        let item = Item::new(&Arc::new((*handle).clone()), "Milk".to_string(), None);
+        handle.create_item(item.clone()).unwrap();
+
+        let unit = Unit::new(&Arc::new(item), use_up_after, "1 l".to_string(), 100.0);
+        let unit_uuid = *unit.uuid();
+        handle.create_unit(unit).unwrap();
+        handle.consume_unit(&unit_uuid, 10.0).unwrap();
+
+        (handle, unit_uuid)
+    }
+
+    fn add_rule(handle: &mut InventoryHandle<'static>, kind: RuleKind) {
+        let rule = Rule::new(*handle.uuid(), *handle.owner(), kind);
+        handle.create_rule(rule).unwrap();
+    }
+
+    #[test]
+    fn notifies_days_before_expiry() {
+        let (mut handle, unit_uuid) = opened_unit(Some(Duration::from_secs(3 * DAY)));
+        add_rule(&mut handle, RuleKind::BeforeExpiry { days: 2 });
+
+        let now = Utc::now();
+        assert!(handle.due_notifications(now).is_empty());
+
+        let due = handle.due_notifications(now + chrono::Duration::days(2));
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].subject_uuid, unit_uuid);
+
+        // Sent once is enough
+        handle.mark_sent(&due[0], now).unwrap();
+        assert!(handle
+            .due_notifications(now + chrono::Duration::days(2))
+            .is_empty());
+    }
+
+    #[test]
+    fn notifies_about_units_opened_too_long_ago() {
+        let (mut handle, unit_uuid) = opened_unit(None);
+        add_rule(
+            &mut handle,
+            RuleKind::OpenedOlderThan {
+                duration: Duration::from_secs(DAY),
+            },
+        );
+
+        let now = Utc::now();
+        assert!(handle.due_notifications(now).is_empty());
+
+        let due = handle.due_notifications(now + chrono::Duration::days(2));
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].subject_uuid, unit_uuid);
+
+        // Units which are used up don't matter anymore
+        handle.consume_unit(&unit_uuid, 100.0).unwrap();
+        assert!(handle
+            .due_notifications(now + chrono::Duration::days(2))
+            .is_empty());
+    }
+
+    #[test]
+    fn skips_rules_beyond_the_range_of_timestamps() {
+        let (mut handle, _) = opened_unit(Some(Duration::from_secs(3 * DAY)));
+        add_rule(&mut handle, RuleKind::BeforeExpiry { days: u32::MAX });
+        add_rule(
+            &mut handle,
+            RuleKind::OpenedOlderThan {
+                duration: Duration::from_secs(i64::MAX as u64 / 1000),
+            },
+        );
+
+        assert!(handle.due_notifications(Utc::now()).is_empty());
+    }
+}
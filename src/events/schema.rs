@@ -0,0 +1,479 @@
+use crate::events::{
+    store::ProjectionEntry, Ean, Inventory, Item, NotificationReceipt, Recipe, Rule, ShoppingEntry,
+    Unit,
+};
+use anyhow::{anyhow, bail, Result};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{json, Map, Value};
+use std::convert::TryFrom;
+
+/// The current format version of serialized stores
+pub const STORE_VERSION: u32 = 1;
+
+/// The current format version of event payloads (projection entries)
+pub const ENTRY_VERSION: u32 = 8;
+
+/// A migration upgrades a payload to the next format version
+pub type Migration = fn(Value) -> Result<Value>;
+
+/// The migrations for serialized stores, keyed by the version they upgrade from
+const STORE_MIGRATIONS: &[(u32, Migration)] = &[(0, store_v0_to_v1)];
+
+/// The migrations for event payloads, keyed by the version they upgrade from
+const ENTRY_MIGRATIONS: &[(u32, Migration)] = &[
+    (0, entry_v0_to_v1),
+    (1, entry_v1_to_v2),
+    (2, entry_v2_to_v3),
+    (3, entry_v3_to_v4),
+    (4, entry_v4_to_v5),
+    (5, entry_v5_to_v6),
+    (6, entry_v6_to_v7),
+    (7, entry_v7_to_v8),
+];
+
+/// Upgrades a serialized store to the current format version
+pub fn migrate_store(value: Value) -> Result<Value> {
+    migrate(value, STORE_MIGRATIONS, STORE_VERSION, "store")
+}
+
+/// Upgrades an event payload to the current format version
+pub fn migrate_entry(value: Value) -> Result<Value> {
+    migrate(value, ENTRY_MIGRATIONS, ENTRY_VERSION, "event payload")
+}
+
+fn migrate(
+    mut value: Value,
+    migrations: &[(u32, Migration)],
+    current: u32,
+    kind: &str,
+) -> Result<Value> {
+    let mut version = version_of(&value)?;
+
+    if version > current {
+        bail!(
+            "The {} has format version {}, but only versions up to {} are supported",
+            kind,
+            version,
+            current
+        );
+    }
+
+    // Apply the migrations one after another until the payload is up to date
+    while version < current {
+        let (_, migration) = migrations
+            .iter()
+            .find(|(from, _)| *from == version)
+            .ok_or_else(|| anyhow!("No migration for {} format version {}", kind, version))?;
+
+        value = migration(value)?;
+
+        let next = version_of(&value)?;
+        if next <= version {
+            bail!(
+                "The migration of {} format version {} did not upgrade the payload",
+                kind,
+                version
+            );
+        }
+
+        version = next;
+    }
+
+    Ok(value)
+}
+
+/// Reads the format version of a payload (legacy payloads have no version marker and are version 0)
+fn version_of(value: &Value) -> Result<u32> {
+    match value.get("version") {
+        None => Ok(0),
+        Some(version) => version
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| anyhow!("Invalid format version: {}", version)),
+    }
+}
+
+/// Version 1 adds the version marker to the store
+fn store_v0_to_v1(mut value: Value) -> Result<Value> {
+    value
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("A serialized store must be a map"))?
+        .insert("version".to_string(), json!(1));
+
+    Ok(value)
+}
+
+/// Version 1 wraps the bare entry into a versioned envelope
+fn entry_v0_to_v1(value: Value) -> Result<Value> {
+    Ok(json!({ "version": 1, "entry": value }))
+}
+
+/// Version 2 adds the device keys of the members to inventories
+fn entry_v1_to_v2(mut value: Value) -> Result<Value> {
+    if let Some(inventory) = value
+        .pointer_mut("/entry/Inventory")
+        .and_then(Value::as_object_mut)
+    {
+        inventory.entry("device_keys").or_insert_with(|| json!([]));
+    }
+
+    value["version"] = json!(2);
+
+    Ok(value)
+}
+
+/// Version 3 adds the minimum stock to items and the shopping list to inventories
+fn entry_v2_to_v3(mut value: Value) -> Result<Value> {
+    for_each_item(&mut value, |item| {
+        item.entry("min_stock").or_insert(Value::Null);
+    });
+
+    if let Some(inventory) = value
+        .pointer_mut("/entry/Inventory")
+        .and_then(Value::as_object_mut)
+    {
+        inventory
+            .entry("shopping_list")
+            .or_insert_with(|| json!([]));
+    }
+
+    value["version"] = json!(3);
+
+    Ok(value)
+}
+
+/// Version 4 only allows valid GTINs as the EAN codes of items (and normalizes them)
+///
+/// Codes which aren't valid GTINs are moved to the legacy barcode of the item, so they aren't lost.
+fn entry_v3_to_v4(mut value: Value) -> Result<Value> {
+    for_each_item(&mut value, |item| {
+        // Codes which aren't strings are read from their JSON text (e.g. numbers)
+        let code = match item.get("ean") {
+            Some(Value::String(code)) => Some(code.clone()),
+            Some(Value::Null) | None => None,
+            Some(other) => Some(other.to_string()),
+        };
+
+        let (ean, legacy_ean) = match code {
+            None => (Value::Null, Value::Null),
+            Some(code) => match Ean::parse(&code) {
+                Ok(parsed) => (json!(parsed), Value::Null),
+                Err(_) => (Value::Null, json!(code)),
+            },
+        };
+
+        item.insert("ean".to_string(), ean);
+        item.insert("legacy_ean".to_string(), legacy_ean);
+    });
+
+    value["version"] = json!(4);
+
+    Ok(value)
+}
+
+/// Version 5 adds the recipes to inventories
+fn entry_v4_to_v5(mut value: Value) -> Result<Value> {
+    if let Some(inventory) = value
+        .pointer_mut("/entry/Inventory")
+        .and_then(Value::as_object_mut)
+    {
+        inventory.entry("recipes").or_insert_with(|| json!([]));
+    }
+
+    value["version"] = json!(5);
+
+    Ok(value)
+}
+
+/// Version 6 adds the notification rules and their receipts to inventories
+fn entry_v5_to_v6(mut value: Value) -> Result<Value> {
+    if let Some(inventory) = value
+        .pointer_mut("/entry/Inventory")
+        .and_then(Value::as_object_mut)
+    {
+        inventory.entry("rules").or_insert_with(|| json!([]));
+        inventory
+            .entry("notification_receipts")
+            .or_insert_with(|| json!([]));
+    }
+
+    value["version"] = json!(6);
+
+    Ok(value)
+}
+
+/// Version 7 adds the tags to items
+fn entry_v6_to_v7(mut value: Value) -> Result<Value> {
+    for_each_item(&mut value, |item| {
+        item.entry("tags").or_insert_with(|| json!([]));
+    });
+
+    value["version"] = json!(7);
+
+    Ok(value)
+}
+
+/// Version 8 adds the legacy barcode to items which were written by versions 4 to 7
+fn entry_v7_to_v8(mut value: Value) -> Result<Value> {
+    for_each_item(&mut value, |item| {
+        item.entry("legacy_ean").or_insert(Value::Null);
+    });
+
+    value["version"] = json!(8);
+
+    Ok(value)
+}
+
+/// Calls a function with the item of a payload and with every item embedded into the inventory of a payload
+///
+/// Updates of an inventory carry a copy of its items, so item migrations have to reach into them as well.
+fn for_each_item(value: &mut Value, mut migrate: impl FnMut(&mut Map<String, Value>)) {
+    if let Some(item) = value
+        .pointer_mut("/entry/Item")
+        .and_then(Value::as_object_mut)
+    {
+        migrate(item);
+    }
+
+    if let Some(items) = value
+        .pointer_mut("/entry/Inventory/items")
+        .and_then(Value::as_array_mut)
+    {
+        items
+            .iter_mut()
+            .filter_map(Value::as_object_mut)
+            .for_each(migrate);
+    }
+}
+
+/// The versioned envelope of an event payload (for serialization)
+#[derive(Serialize)]
+struct VersionedEntryRef<'e> {
+    version: u32,
+    entry: EntryRef<'e>,
+}
+
+#[derive(Serialize)]
+enum EntryRef<'e> {
+    Inventory(&'e Inventory),
+    Item(&'e Item),
+    Unit(&'e Unit),
+    ShoppingEntry(&'e ShoppingEntry),
+    Recipe(&'e Recipe),
+    Rule(&'e Rule),
+    NotificationReceipt(&'e NotificationReceipt),
+}
+
+/// The versioned envelope of an event payload (for deserialization, after migrating it)
+#[derive(Deserialize)]
+struct VersionedEntry {
+    entry: EntryOwned,
+}
+
+#[derive(Deserialize)]
+enum EntryOwned {
+    Inventory(Inventory),
+    Item(Item),
+    Unit(Unit),
+    ShoppingEntry(ShoppingEntry),
+    Recipe(Recipe),
+    Rule(Rule),
+    NotificationReceipt(NotificationReceipt),
+}
+
+impl Serialize for ProjectionEntry {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let entry = match self {
+            ProjectionEntry::Inventory(inventory) => EntryRef::Inventory(inventory),
+            ProjectionEntry::Item(item) => EntryRef::Item(item),
+            ProjectionEntry::Unit(unit) => EntryRef::Unit(unit),
+            ProjectionEntry::ShoppingEntry(entry) => EntryRef::ShoppingEntry(entry),
+            ProjectionEntry::Recipe(recipe) => EntryRef::Recipe(recipe),
+            ProjectionEntry::Rule(rule) => EntryRef::Rule(rule),
+            ProjectionEntry::NotificationReceipt(receipt) => EntryRef::NotificationReceipt(receipt),
+        };
+
+        VersionedEntryRef {
+            version: ENTRY_VERSION,
+            entry,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ProjectionEntry {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // Go through a self-describing intermediate, so old payloads can be upgraded first
+        let value = Value::deserialize(deserializer)?;
+        let value = migrate_entry(value).map_err(D::Error::custom)?;
+        let versioned: VersionedEntry = serde_json::from_value(value).map_err(D::Error::custom)?;
+
+        Ok(match versioned.entry {
+            EntryOwned::Inventory(inventory) => ProjectionEntry::Inventory(inventory),
+            EntryOwned::Item(item) => ProjectionEntry::Item(item),
+            EntryOwned::Unit(unit) => ProjectionEntry::Unit(unit),
+            EntryOwned::ShoppingEntry(entry) => ProjectionEntry::ShoppingEntry(entry),
+            EntryOwned::Recipe(recipe) => ProjectionEntry::Recipe(recipe),
+            EntryOwned::Rule(rule) => ProjectionEntry::Rule(rule),
+            EntryOwned::NotificationReceipt(receipt) => {
+                ProjectionEntry::NotificationReceipt(receipt)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::store::{InventoryHandle, Store};
+    use libocc::events::Event;
+    use std::borrow::Cow;
+    use uuid::Uuid;
+
+    /// Loads an inventory log written by an earlier version (stored as its operations and payloads)
+    fn load_fixture(fixture: &str) -> Result<InventoryHandle<'static>> {
+        #[derive(Deserialize)]
+        enum Operation {
+            Create,
+            Update,
+            Delete,
+        }
+
+        #[derive(Deserialize)]
+        struct FixtureEvent {
+            operation: Operation,
+            payload: ProjectionEntry,
+        }
+
+        let events: Vec<FixtureEvent> = serde_json::from_str(fixture)?;
+
+        InventoryHandle::from_events(
+            events
+                .into_iter()
+                .map(|e| match e.operation {
+                    Operation::Create => Event::create(Cow::Owned(e.payload)),
+                    Operation::Update => Event::update(Cow::Owned(e.payload)),
+                    Operation::Delete => Event::delete(Cow::Owned(e.payload)),
+                })
+                .collect(),
+        )
+    }
+
+    fn uuid(uuid: &str) -> Uuid {
+        Uuid::parse_str(uuid).unwrap()
+    }
+
+    #[test]
+    fn loads_unversioned_store() {
+        let handle = load_fixture(include_str!("../../tests/fixtures/store_v0.json")).unwrap();
+        handle.verify_projection().unwrap();
+
+        assert_eq!(handle.name(), "Kitchen pantry");
+        assert!(handle.device_keys().is_empty());
+        assert!(handle.shopping_list().is_empty());
+
+        let item = handle
+            .item(&uuid("6c3f1a52-8f0e-4a2b-9d55-0a1b2c3d4e03"))
+            .unwrap();
+        assert!(item.tags().is_empty());
+        assert!(item.min_stock().is_none());
+        assert_eq!(item.ean(), &Some(Ean::parse("4006381333931").unwrap()));
+        assert_eq!(item.units().len(), 1);
+    }
+
+    #[test]
+    fn loads_version_2_store() {
+        let handle = load_fixture(include_str!("../../tests/fixtures/store_v2.json")).unwrap();
+        handle.verify_projection().unwrap();
+
+        let member = uuid("0f8e4d2a-5b6c-4e1d-8a9b-7c6d5e4f3a05");
+        assert!(handle.allow_admin(&member));
+
+        let item = handle
+            .item(&uuid("0f8e4d2a-5b6c-4e1d-8a9b-7c6d5e4f3a03"))
+            .unwrap();
+        assert!(item.tags().is_empty());
+        assert!(item.ean().is_none());
+    }
+
+    #[test]
+    fn loads_unversioned_store_document() {
+        let store: Store =
+            serde_json::from_str(include_str!("../../tests/fixtures/store_v0_document.json"))
+                .unwrap();
+        assert!(store.broken().is_empty());
+
+        let handle = store
+            .inventory(&uuid("6c3f1a52-8f0e-4a2b-9d55-0a1b2c3d4e01"))
+            .unwrap();
+        handle.verify_projection().unwrap();
+        assert_eq!(handle.name(), "Kitchen pantry");
+        assert!(handle.device_keys().is_empty());
+
+        let item = handle
+            .item(&uuid("6c3f1a52-8f0e-4a2b-9d55-0a1b2c3d4e03"))
+            .unwrap();
+        assert_eq!(item.ean(), &Some(Ean::parse("4006381333931").unwrap()));
+        assert!(item.legacy_ean().is_none());
+        assert_eq!(item.units().len(), 1);
+
+        // The store is written back in the current format
+        let saved = serde_json::to_value(&store).unwrap();
+        assert_eq!(saved["version"], json!(STORE_VERSION));
+    }
+
+    #[test]
+    fn migrates_items_embedded_into_inventories() {
+        let fixture: Value =
+            serde_json::from_str(include_str!("../../tests/fixtures/store_v0.json")).unwrap();
+        let migrated = migrate_entry(fixture[3]["payload"].clone()).unwrap();
+        let item = &migrated["entry"]["Inventory"]["items"][0];
+
+        assert_eq!(migrated["version"], json!(ENTRY_VERSION));
+        assert_eq!(item["tags"], json!([]));
+        assert_eq!(item["min_stock"], Value::Null);
+        assert_eq!(item["ean"], json!("04006381333931"));
+        assert_eq!(item["legacy_ean"], Value::Null);
+    }
+
+    #[test]
+    fn keeps_invalid_legacy_barcodes() {
+        let item = json!({ "uuid": "6c3f1a52-8f0e-4a2b-9d55-0a1b2c3d4e03", "ean": "12345" });
+        let entry = json!({ "version": 3, "entry": { "Item": item.clone() } });
+        let inventory = json!({ "version": 3, "entry": { "Inventory": { "items": [item] } } });
+
+        let migrated = migrate_entry(entry).unwrap();
+        assert_eq!(migrated["entry"]["Item"]["ean"], Value::Null);
+        assert_eq!(migrated["entry"]["Item"]["legacy_ean"], json!("12345"));
+
+        let migrated = migrate_entry(inventory).unwrap();
+        assert_eq!(
+            migrated["entry"]["Inventory"]["items"][0]["legacy_ean"],
+            json!("12345")
+        );
+    }
+
+    #[test]
+    fn adds_legacy_barcodes_to_version_7_items() {
+        let entry = json!({ "version": 7, "entry": { "Item": { "ean": "04006381333931" } } });
+        let migrated = migrate_entry(entry).unwrap();
+
+        assert_eq!(migrated["entry"]["Item"]["ean"], json!("04006381333931"));
+        assert_eq!(migrated["entry"]["Item"]["legacy_ean"], Value::Null);
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let entry = json!({ "version": ENTRY_VERSION + 1, "entry": {} });
+        assert!(migrate_entry(entry).is_err());
+
+        let store = json!({ "version": STORE_VERSION + 1, "inventory_projectors": [] });
+        assert!(migrate_store(store).is_err());
+    }
+}
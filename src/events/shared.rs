@@ -0,0 +1,346 @@
+use crate::{
+    events::{
+        store::{self, BrokenInventory, InventoryHandle, Store},
+        Change, Observers, SubscriptionId,
+    },
+    storage::Storage,
+};
+use anyhow::{anyhow, bail, Result};
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    sync::{mpsc::Receiver, Arc, Mutex, RwLock as SyncRwLock},
+};
+use tokio::sync::{OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
+use uuid::Uuid;
+
+/// The lock of one inventory
+type SharedHandle = Arc<RwLock<InventoryHandle<'static>>>;
+
+/// A store which can be shared between threads and tasks, locking every inventory on its own
+///
+/// Readers of an inventory only wait for writers of the same inventory. The locks are
+/// async-aware, so a task waiting for an inventory doesn't block its executor thread.
+/// The list of inventories has a lock of its own, which is never held while waiting.
+pub struct SharedStore {
+    inventories: SyncRwLock<BTreeMap<Uuid, SharedHandle>>,
+
+    /// The inventories which couldn't be loaded (kept, so they aren't lost when saving the store)
+    broken: Mutex<Vec<BrokenInventory>>,
+
+    /// The subscribers to the changes of every inventory (shared with the handles, which forward their changes)
+    observers: Arc<Mutex<Observers>>,
+}
+
+impl From<Store<'static>> for SharedStore {
+    fn from(store: Store<'static>) -> Self {
+        let (handles, broken, observers) = store.into_parts();
+
+        Self {
+            inventories: SyncRwLock::new(
+                handles
+                    .into_iter()
+                    .map(|h| (*h.uuid(), Arc::new(RwLock::new(h))))
+                    .collect(),
+            ),
+            broken: Mutex::new(broken),
+            observers,
+        }
+    }
+}
+
+impl Default for SharedStore {
+    fn default() -> Self {
+        Store::new().into()
+    }
+}
+
+impl SharedStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Copies the current state of every inventory into a plain store (e.g. to serialize it)
+    ///
+    /// The copies start without subscribers, so their changes are only reported to the ones
+    /// subscribing to the returned store.
+    pub async fn snapshot(&self) -> Store<'static> {
+        let observers = Arc::default();
+        let mut handles = vec![];
+
+        for handle in self.handles() {
+            let mut handle = handle.read().await.clone();
+            store::forward_changes(&mut handle, &observers);
+            handles.push(handle);
+        }
+
+        let broken = self
+            .broken
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+
+        Store::from_parts(handles, broken, observers)
+    }
+
+    /// The UUIDs of the inventories
+    pub fn inventories(&self) -> Vec<Uuid> {
+        self.map().keys().copied().collect()
+    }
+
+    /// The inventories which couldn't be loaded
+    pub fn broken(&self) -> Vec<BrokenInventory> {
+        self.broken
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    pub fn make_inventory(&self, name: String, owner: Uuid) -> Uuid {
+        let mut handle = InventoryHandle::new(name, owner);
+        let uuid = *handle.uuid();
+
+        store::forward_changes(&mut handle, &self.observers);
+        self.map_mut().insert(uuid, Arc::new(RwLock::new(handle)));
+
+        // The subscribers are told without holding the list, so they may look at the store
+        store::announce_inventory(uuid, &self.observers);
+
+        uuid
+    }
+
+    /// Adds the handle of an existing inventory (e.g. one restored from a backup)
+    pub fn add_inventory(&self, mut handle: InventoryHandle<'static>) -> Result<()> {
+        let uuid = *handle.uuid();
+        store::forward_changes(&mut handle, &self.observers);
+
+        match self.map_mut().entry(uuid) {
+            Entry::Occupied(_) => bail!("The store already contains the inventory {}", uuid),
+            Entry::Vacant(entry) => {
+                entry.insert(Arc::new(RwLock::new(handle)));
+            }
+        }
+
+        // Only the handle which made it into the store is announced (without holding the list)
+        store::announce_inventory(uuid, &self.observers);
+
+        Ok(())
+    }
+
+    /// Removes an inventory from the store (after waiting for everyone using it)
+    pub async fn remove_inventory(&self, uuid: &Uuid) -> Option<InventoryHandle<'static>> {
+        let handle = self.map_mut().remove(uuid)?;
+
+        // Wait until nobody uses the inventory anymore
+        let guard = handle.write().await;
+        let removed = guard.clone();
+        drop(guard);
+
+        Some(removed)
+    }
+
+    /// Waits for read access to an inventory
+    pub async fn read(
+        &self,
+        uuid: &Uuid,
+    ) -> Option<OwnedRwLockReadGuard<InventoryHandle<'static>>> {
+        Some(self.handle(uuid)?.read_owned().await)
+    }
+
+    /// Waits for write access to an inventory
+    pub async fn write(
+        &self,
+        uuid: &Uuid,
+    ) -> Option<OwnedRwLockWriteGuard<InventoryHandle<'static>>> {
+        Some(self.handle(uuid)?.write_owned().await)
+    }
+
+    /// Reads from an inventory
+    pub async fn with_inventory<R>(
+        &self,
+        uuid: &Uuid,
+        read: impl FnOnce(&InventoryHandle<'static>) -> R,
+    ) -> Result<R> {
+        let handle = self
+            .read(uuid)
+            .await
+            .ok_or(anyhow!("Inventory not found"))?;
+
+        Ok(read(&handle))
+    }
+
+    /// Changes an inventory
+    pub async fn with_inventory_mut<R>(
+        &self,
+        uuid: &Uuid,
+        write: impl FnOnce(&mut InventoryHandle<'static>) -> Result<R>,
+    ) -> Result<R> {
+        let mut handle = self
+            .write(uuid)
+            .await
+            .ok_or(anyhow!("Inventory not found"))?;
+
+        write(&mut handle)
+    }
+
+    /// Appends the events which haven't been persisted yet to a storage backend (one inventory at a time)
+    ///
+    /// Only waiting for the inventories is async. Storage backends are synchronous, so the writing
+    /// blocks the executor thread while the inventory being written is locked. On a multi-threaded
+    /// runtime, call this within `tokio::task::block_in_place`.
+    pub async fn flush(&self, storage: &mut impl Storage) -> Result<()> {
+        for handle in self.handles() {
+            handle.write().await.flush(storage)?;
+        }
+
+        Ok(())
+    }
+
+    fn handle(&self, uuid: &Uuid) -> Option<SharedHandle> {
+        self.map().get(uuid).cloned()
+    }
+
+    /// The locks of all inventories (taken out of the list, so the list isn't locked while waiting for them)
+    fn handles(&self) -> Vec<SharedHandle> {
+        self.map().values().cloned().collect()
+    }
+
+    fn map(&self) -> std::sync::RwLockReadGuard<'_, BTreeMap<Uuid, SharedHandle>> {
+        self.inventories.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn map_mut(&self) -> std::sync::RwLockWriteGuard<'_, BTreeMap<Uuid, SharedHandle>> {
+        self.inventories.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// Subscriptions
+impl SharedStore {
+    /// Calls a function with every change of any inventory of the store
+    ///
+    /// The function must not subscribe to (or unsubscribe from) the store itself.
+    pub fn subscribe(&self, callback: impl FnMut(&Change) + Send + 'static) -> SubscriptionId {
+        self.observers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .subscribe(callback)
+    }
+
+    /// Sends every change of any inventory of the store into a channel
+    pub fn subscribe_channel(&self) -> (SubscriptionId, Receiver<Change>) {
+        self.observers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .subscribe_channel()
+    }
+
+    /// Ends a subscription to the store (returns if it existed)
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        self.observers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .unsubscribe(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{ChangeKind, Item};
+
+    /// Adds items to an inventory of a shared store
+    async fn add_items(store: &SharedStore, uuid: Uuid, count: usize) {
+        for i in 0..count {
+            store
+                .with_inventory_mut(&uuid, |handle| {
+                    let item =
+                        Item::new(&Arc::new((**handle).clone()), format!("Item {}", i), None);
+                    handle.create_item(item).map(|_| ())
+                })
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn changes_separate_inventories_concurrently() {
+        let store = Arc::new(SharedStore::new());
+        let (_, changes) = store.subscribe_channel();
+        let inventories: Vec<Uuid> = (0..8)
+            .map(|i| store.make_inventory(format!("Pantry {}", i), Uuid::new_v4()))
+            .collect();
+
+        let tasks: Vec<_> = inventories
+            .iter()
+            .map(|uuid| {
+                tokio::spawn({
+                    let store = Arc::clone(&store);
+                    let uuid = *uuid;
+                    async move { add_items(&store, uuid, 50).await }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        for uuid in &inventories {
+            let items = store
+                .with_inventory(uuid, |handle| {
+                    handle.verify_projection().unwrap();
+                    handle.items().len()
+                })
+                .await
+                .unwrap();
+            assert_eq!(items, 50);
+        }
+
+        let added = changes
+            .try_iter()
+            .filter(|c| matches!(c.kind, ChangeKind::ItemAdded { .. }))
+            .count();
+        assert_eq!(added, 8 * 50);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn announces_only_the_inventory_which_was_added() {
+        let store = Arc::new(SharedStore::new());
+        let (_, changes) = store.subscribe_channel();
+        let handle = InventoryHandle::new("Pantry".to_string(), Uuid::new_v4());
+
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let store = Arc::clone(&store);
+                let handle = handle.clone();
+                tokio::spawn(async move { store.add_inventory(handle).is_ok() })
+            })
+            .collect();
+
+        let mut added = 0;
+        for task in tasks {
+            if task.await.unwrap() {
+                added += 1;
+            }
+        }
+
+        assert_eq!(added, 1);
+        assert_eq!(changes.try_iter().count(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn snapshots_do_not_report_to_the_subscribers_of_the_store() {
+        let store = SharedStore::new();
+        let uuid = store.make_inventory("Pantry".to_string(), Uuid::new_v4());
+        let (_, changes) = store.subscribe_channel();
+
+        let mut snapshot = store.snapshot().await;
+        let handle = snapshot.inventory_mut(&uuid).unwrap();
+        let item = Item::new(&Arc::new((**handle).clone()), "Rice".to_string(), None);
+        handle.create_item(item).unwrap();
+
+        assert!(changes.try_iter().next().is_none());
+        assert!(store
+            .with_inventory(&uuid, |handle| handle.items().is_empty())
+            .await
+            .unwrap());
+    }
+}
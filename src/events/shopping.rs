@@ -0,0 +1,108 @@
+use crate::{
+    events::{Item, Quantity},
+    Timestamp,
+};
+use libocc::events::Utc;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ShoppingEntry {
+    /// The UUID of the shopping list entry
+    uuid: Uuid,
+
+    /// The inventory whose shopping list contains this entry
+    inventory_uuid: Uuid,
+
+    /// The item to buy (if it is known in the inventory)
+    item_uuid: Option<Uuid>,
+
+    /// What to buy
+    name: String,
+
+    /// How much to buy (if specified)
+    quantity: Option<Quantity>,
+
+    /// If the entry was added because the stock of its item fell below the minimum
+    automatic: bool,
+
+    /// The timestamp of the creation of the entry
+    created_on: Timestamp,
+}
+
+impl ShoppingEntry {
+    /// Generates a new (manual) shopping list entry
+    pub fn new(
+        inventory_uuid: Uuid,
+        item_uuid: Option<Uuid>,
+        name: String,
+        quantity: Option<Quantity>,
+    ) -> Self {
+        Self {
+            uuid: Uuid::new_v4(),
+            inventory_uuid,
+            item_uuid,
+            name,
+            quantity,
+            automatic: false,
+            created_on: Utc::now(),
+        }
+    }
+
+    /// Generates an entry for an item whose stock fell below its minimum
+    pub(super) fn automatic(item: &Item) -> Self {
+        Self {
+            uuid: Uuid::new_v4(),
+            inventory_uuid: *item.inventory_uuid(),
+            item_uuid: Some(*item.uuid()),
+            name: item.name().clone(),
+            quantity: None,
+            automatic: true,
+            created_on: Utc::now(),
+        }
+    }
+}
+
+impl PartialEq for ShoppingEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.uuid == other.uuid
+    }
+}
+
+// Getters
+impl ShoppingEntry {
+    /// The UUID of the shopping list entry
+    pub fn uuid(&self) -> &Uuid {
+        &self.uuid
+    }
+
+    /// The inventory whose shopping list contains this entry
+    pub fn inventory_uuid(&self) -> &Uuid {
+        &self.inventory_uuid
+    }
+
+    /// The item to buy (if it is known in the inventory)
+    pub fn item_uuid(&self) -> &Option<Uuid> {
+        &self.item_uuid
+    }
+
+    /// What to buy
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    /// How much to buy (if specified)
+    pub fn quantity(&self) -> &Option<Quantity> {
+        &self.quantity
+    }
+
+    /// If the entry was added because the stock of its item fell below the minimum
+    pub fn is_automatic(&self) -> bool {
+        self.automatic
+    }
+
+    /// The timestamp of the creation of the entry
+    pub fn created_on(&self) -> &Timestamp {
+        &self.created_on
+    }
+}
@@ -1,21 +1,36 @@
-use crate::events::{Inventory, Item, Unit};
+use crate::{
+    events::{
+        schema::{self, STORE_VERSION},
+        Change, ChangeKind, DeviceKey, Ean, EntryMap, Inventory, Item, Keyed, MinStock,
+        NotificationReceipt, Observers, Recipe, Rule, ShoppingEntry, SubscriptionId, Unit,
+    },
+    storage::Storage,
+};
 use anyhow::{anyhow, bail, Result};
-use libocc::events::{Event, Projector};
+use libocc::events::{Event, Projector, Utc, CRUD};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::{
     borrow::Cow,
-    collections::HashMap,
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
     convert::{TryFrom, TryInto},
     mem,
     ops::Deref,
+    sync::{mpsc::Receiver, Arc, Mutex},
 };
 use uuid::Uuid;
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+/// An event payload (serialized in a versioned envelope, see the `schema` module)
+#[derive(Clone, Debug)]
 pub enum ProjectionEntry {
     Inventory(Inventory),
     Item(Item),
     Unit(Unit),
+    ShoppingEntry(ShoppingEntry),
+    Recipe(Recipe),
+    Rule(Rule),
+    NotificationReceipt(NotificationReceipt),
 }
 
 impl PartialEq for ProjectionEntry {
@@ -25,24 +40,67 @@ impl PartialEq for ProjectionEntry {
             (ProjectionEntry::Inventory(s), ProjectionEntry::Inventory(o)) => s == o,
             (ProjectionEntry::Item(s), ProjectionEntry::Item(o)) => s == o,
             (ProjectionEntry::Unit(s), ProjectionEntry::Unit(o)) => s == o,
+            (ProjectionEntry::ShoppingEntry(s), ProjectionEntry::ShoppingEntry(o)) => s == o,
+            (ProjectionEntry::Recipe(s), ProjectionEntry::Recipe(o)) => s == o,
+            (ProjectionEntry::Rule(s), ProjectionEntry::Rule(o)) => s == o,
+            (ProjectionEntry::NotificationReceipt(s), ProjectionEntry::NotificationReceipt(o)) => {
+                s == o
+            }
 
-            // Non-matching variants can't be equal
-            (ProjectionEntry::Inventory(_), ProjectionEntry::Item(_)) => false,
-            (ProjectionEntry::Inventory(_), ProjectionEntry::Unit(_)) => false,
-            (ProjectionEntry::Item(_), ProjectionEntry::Inventory(_)) => false,
-            (ProjectionEntry::Item(_), ProjectionEntry::Unit(_)) => false,
-            (ProjectionEntry::Unit(_), ProjectionEntry::Inventory(_)) => false,
-            (ProjectionEntry::Unit(_), ProjectionEntry::Item(_)) => false,
+            // Non-matching variants can't be equal (every variant is listed, so none can be forgotten)
+            (ProjectionEntry::Inventory(_), _)
+            | (ProjectionEntry::Item(_), _)
+            | (ProjectionEntry::Unit(_), _)
+            | (ProjectionEntry::ShoppingEntry(_), _)
+            | (ProjectionEntry::Recipe(_), _)
+            | (ProjectionEntry::Rule(_), _)
+            | (ProjectionEntry::NotificationReceipt(_), _) => false,
         }
     }
 }
 
+/// The access level of a member of an inventory (besides its owner)
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum Role {
+    Admin,
+    Write,
+    Read,
+}
+
 /// The serialized version of Store
 #[serde(try_from = "StoreSer")]
 #[serde(into = "StoreSer")]
-#[derive(Deserialize, Serialize, Clone)]
+#[derive(Deserialize, Serialize)]
 pub struct Store<'a> {
     inventory_handles: Vec<InventoryHandle<'a>>,
+
+    /// The inventories which couldn't be loaded (kept, so they aren't lost when saving the store)
+    broken: Vec<BrokenInventory>,
+
+    /// The subscribers to the changes of every inventory (shared with the handles, which forward their changes)
+    observers: Arc<Mutex<Observers>>,
+}
+
+/// A serialized inventory which couldn't be loaded, kept as is so loading it can be retried later
+#[derive(Clone, Debug)]
+pub struct BrokenInventory {
+    /// The serialized projector of the inventory
+    raw: Value,
+
+    /// Why the inventory couldn't be loaded
+    error: String,
+}
+
+impl BrokenInventory {
+    /// The serialized projector of the inventory
+    pub fn raw(&self) -> &Value {
+        &self.raw
+    }
+
+    /// Why the inventory couldn't be loaded
+    pub fn error(&self) -> &str {
+        &self.error
+    }
 }
 
 impl<'a> Deref for Store<'a> {
@@ -53,10 +111,31 @@ impl<'a> Deref for Store<'a> {
     }
 }
 
+/// A copy of a store starts without subscribers (its inventories report to the copy only)
+impl<'a> Clone for Store<'a> {
+    fn clone(&self) -> Self {
+        let mut store = Self {
+            inventory_handles: vec![],
+            broken: self.broken.clone(),
+            observers: Arc::default(),
+        };
+
+        for handle in &self.inventory_handles {
+            let mut handle = handle.clone();
+            store.attach(&mut handle);
+            store.inventory_handles.push(handle);
+        }
+
+        store
+    }
+}
+
 impl<'a> Store<'a> {
     pub fn new() -> Self {
         Self {
             inventory_handles: vec![],
+            broken: vec![],
+            observers: Arc::default(),
         }
     }
 }
@@ -64,33 +143,302 @@ impl<'a> Store<'a> {
 impl<'a> Store<'a> {
     pub fn make_inventory(&mut self, name: String, owner: Uuid) -> Uuid {
         // Create the new inventory handle
-        let handle = InventoryHandle::new(name, owner);
+        let mut handle = InventoryHandle::new(name, owner);
 
         // Get the UUID of the new handle
         let uuid = handle.uuid().clone();
 
         // Store the new handle in the store
+        self.attach(&mut handle);
         self.inventory_handles.push(handle);
+        announce_inventory(uuid, &self.observers);
 
         // Return the UUID of the new inventory
         uuid
     }
+
+    /// Finds the handle of an inventory by its UUID
+    pub fn inventory(&self, uuid: &Uuid) -> Option<&InventoryHandle<'a>> {
+        self.inventory_handles.iter().find(|h| h.uuid() == uuid)
+    }
+
+    /// Finds the handle of an inventory by its UUID (mutable)
+    pub fn inventory_mut(&mut self, uuid: &Uuid) -> Option<&mut InventoryHandle<'a>> {
+        self.inventory_handles.iter_mut().find(|h| h.uuid() == uuid)
+    }
+
+    /// Adds the handle of an existing inventory (e.g. one restored from a backup)
+    pub fn add_inventory(&mut self, mut handle: InventoryHandle<'a>) -> Result<()> {
+        if self.inventory(handle.uuid()).is_some() {
+            bail!("The store already contains the inventory {}", handle.uuid());
+        }
+
+        let uuid = *handle.uuid();
+        self.attach(&mut handle);
+        self.inventory_handles.push(handle);
+        announce_inventory(uuid, &self.observers);
+
+        Ok(())
+    }
+
+    /// Makes a handle forward its changes to the subscribers of the store
+    fn attach(&self, handle: &mut InventoryHandle<'a>) {
+        forward_changes(handle, &self.observers);
+    }
+
+    /// Splits the store into its inventories, its broken inventories and its subscribers
+    pub(crate) fn into_parts(
+        self,
+    ) -> (
+        Vec<InventoryHandle<'a>>,
+        Vec<BrokenInventory>,
+        Arc<Mutex<Observers>>,
+    ) {
+        (self.inventory_handles, self.broken, self.observers)
+    }
+
+    /// Puts a store together from its parts (the handles must already forward their changes to the subscribers)
+    pub(crate) fn from_parts(
+        inventory_handles: Vec<InventoryHandle<'a>>,
+        broken: Vec<BrokenInventory>,
+        observers: Arc<Mutex<Observers>>,
+    ) -> Self {
+        Self {
+            inventory_handles,
+            broken,
+            observers,
+        }
+    }
+}
+
+/// Makes a handle forward its changes to the subscribers of a store
+pub(crate) fn forward_changes(handle: &mut InventoryHandle<'_>, observers: &Arc<Mutex<Observers>>) {
+    let forward_to = Arc::clone(observers);
+    handle.subscribe(move |change| {
+        forward_to
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .notify(std::slice::from_ref(change))
+    });
+}
+
+/// Tells the subscribers of a store about an inventory which was added to it
+pub(crate) fn announce_inventory(inventory_uuid: Uuid, observers: &Arc<Mutex<Observers>>) {
+    observers
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .notify(&[Change {
+            inventory_uuid,
+            kind: ChangeKind::InventoryAdded,
+        }]);
+}
+
+// Subscriptions
+impl<'a> Store<'a> {
+    /// Calls a function with every change of any inventory of the store
+    ///
+    /// The function must not subscribe to (or unsubscribe from) the store itself.
+    pub fn subscribe(&mut self, callback: impl FnMut(&Change) + Send + 'static) -> SubscriptionId {
+        self.observers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .subscribe(callback)
+    }
+
+    /// Calls a function which can't be sent between threads with every change of any inventory
+    #[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+    pub fn subscribe_local(&mut self, callback: impl FnMut(&Change) + 'static) -> SubscriptionId {
+        self.observers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .subscribe_local(callback)
+    }
+
+    /// Sends every change of any inventory of the store into a channel
+    pub fn subscribe_channel(&mut self) -> (SubscriptionId, Receiver<Change>) {
+        self.observers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .subscribe_channel()
+    }
+
+    /// Ends a subscription to the store (returns if it existed)
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        self.observers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .unsubscribe(id)
+    }
+}
+
+// Persistence
+impl<'a> Store<'a> {
+    /// Loads every inventory log from a storage backend
+    pub fn load(storage: &impl Storage) -> Result<Self> {
+        let mut store = Self::new();
+
+        for uuid in storage.inventories()? {
+            let mut projector = Projector::new();
+
+            for event in storage.load(&uuid)? {
+                projector.push(event)?;
+            }
+
+            store.add_inventory(projector.try_into()?)?;
+        }
+
+        Ok(store)
+    }
+
+    /// Loads a serialized store, setting the inventories which can't be loaded aside instead of failing
+    pub fn from_value_lenient(value: Value) -> Result<Self> {
+        let fields: RawFields = serde_json::from_value(schema::migrate_store(value)?)?;
+
+        let mut store = Self::new();
+        for raw in fields
+            .inventory_projectors
+            .into_iter()
+            .chain(fields.broken_projectors)
+        {
+            store.add_raw(raw);
+        }
+
+        Ok(store)
+    }
+
+    /// The inventories which couldn't be loaded
+    pub fn broken(&self) -> &[BrokenInventory] {
+        &self.broken
+    }
+
+    /// Tries to load the broken inventories again and returns how many of them could be loaded
+    pub fn retry_broken(&mut self) -> usize {
+        let before = self.broken.len();
+
+        for broken in mem::take(&mut self.broken) {
+            self.add_raw(broken.raw);
+        }
+
+        before - self.broken.len()
+    }
+
+    /// Gives up on a broken inventory (e.g. after getting it again from a peer) and returns it
+    pub fn remove_broken(&mut self, index: usize) -> Option<BrokenInventory> {
+        if index < self.broken.len() {
+            Some(self.broken.remove(index))
+        } else {
+            None
+        }
+    }
+
+    /// Loads a serialized projector, or sets it aside if that fails
+    fn add_raw(&mut self, raw: Value) {
+        let handle = serde_json::from_value::<Projector<'a, ProjectionEntry>>(raw.clone())
+            .map_err(anyhow::Error::from)
+            .and_then(InventoryHandle::try_from)
+            .and_then(|handle| self.add_inventory(handle));
+
+        if let Err(e) = handle {
+            self.set_aside(raw, e.to_string());
+        }
+    }
+
+    /// Keeps a serialized inventory which can't be loaded, so it isn't lost when saving the store
+    pub(super) fn set_aside(&mut self, raw: Value, error: String) {
+        self.broken.push(BrokenInventory { raw, error });
+    }
+
+    /// Appends the events which haven't been persisted yet to a storage backend
+    pub fn flush(&mut self, storage: &mut impl Storage) -> Result<()> {
+        for handle in &mut self.inventory_handles {
+            handle.flush(storage)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the complete log of every inventory to a storage backend which has none of them yet
+    ///
+    /// A store read from a file (or loaded from another backend) has nothing to flush, so this is how
+    /// it moves to a new backend. The broken inventories are not written.
+    pub fn save_all(&mut self, storage: &mut impl Storage) -> Result<()> {
+        let existing = storage.inventories()?;
+
+        if let Some(handle) = self
+            .inventory_handles
+            .iter()
+            .find(|h| existing.contains(h.uuid()))
+        {
+            bail!(
+                "The storage already has a log of the inventory {}",
+                handle.uuid()
+            );
+        }
+
+        for handle in &mut self.inventory_handles {
+            handle.save_all(storage)?;
+        }
+
+        Ok(())
+    }
 }
 
 /// The serialized version of Store
+#[serde(try_from = "Value")]
 #[derive(Deserialize, Serialize, Clone)]
 struct StoreSer<'a> {
+    /// The format version of the serialized store
+    version: u32,
+
     inventory_projectors: Vec<Projector<'a, ProjectionEntry>>,
+
+    /// The inventories which couldn't be loaded (as they were read)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    broken_projectors: Vec<Value>,
+}
+
+/// The fields of a serialized store, without reading the projectors
+#[derive(Deserialize)]
+struct RawFields {
+    inventory_projectors: Vec<Value>,
+
+    #[serde(default)]
+    broken_projectors: Vec<Value>,
+}
+
+impl<'a> TryFrom<Value> for StoreSer<'a> {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        #[derive(Deserialize)]
+        struct Fields<'f> {
+            inventory_projectors: Vec<Projector<'f, ProjectionEntry>>,
+
+            #[serde(default)]
+            broken_projectors: Vec<Value>,
+        }
+
+        // Upgrade stores written by earlier versions before reading them
+        let fields: Fields = serde_json::from_value(schema::migrate_store(value)?)?;
+
+        Ok(Self {
+            version: STORE_VERSION,
+            inventory_projectors: fields.inventory_projectors,
+            broken_projectors: fields.broken_projectors,
+        })
+    }
 }
 
 impl<'a> Into<StoreSer<'a>> for Store<'a> {
     fn into(self) -> StoreSer<'a> {
         StoreSer {
+            version: STORE_VERSION,
             inventory_projectors: self
                 .inventory_handles
                 .into_iter()
                 .map(|handle| handle.projector)
                 .collect(),
+            broken_projectors: self.broken.into_iter().map(|b| b.raw).collect(),
         }
     }
 }
@@ -99,13 +447,18 @@ impl<'a> TryFrom<StoreSer<'a>> for Store<'a> {
     type Error = anyhow::Error;
 
     fn try_from(ser: StoreSer<'a>) -> Result<Self, Self::Error> {
-        let mut inventory_handles = vec![];
+        let mut store = Self::new();
 
         for projector in ser.inventory_projectors {
-            inventory_handles.push(projector.try_into()?);
+            store.add_inventory(projector.try_into()?)?;
         }
 
-        Ok(Self { inventory_handles })
+        // Inventories which were broken before are retried (and set aside again if they still fail)
+        for raw in ser.broken_projectors {
+            store.add_raw(raw);
+        }
+
+        Ok(store)
     }
 }
 
@@ -113,6 +466,15 @@ impl<'a> TryFrom<StoreSer<'a>> for Store<'a> {
 pub struct InventoryHandle<'a> {
     projector: Projector<'a, ProjectionEntry>,
     inventory: Inventory,
+
+    /// Where to find the entries of the projection by their UUIDs
+    index: ProjectionIndex,
+
+    /// The subscribers to the changes of the inventory
+    observers: Observers,
+
+    /// The events which haven't been written to a storage backend yet
+    unsaved: Vec<ProjectionEvent<'a>>,
 }
 
 impl<'a> Deref for InventoryHandle<'a> {
@@ -130,6 +492,55 @@ impl<'a> InventoryHandle<'a> {
         &self.projector
     }
 
+    /// Builds a handle from the complete event log of an inventory which wasn't persisted yet
+    pub fn from_events(events: Vec<ProjectionEvent<'a>>) -> Result<Self> {
+        let mut projector = Projector::new();
+
+        for event in events.iter().cloned() {
+            projector.push(event)?;
+        }
+
+        let mut handle: Self = projector.try_into()?;
+        handle.unsaved = events;
+
+        Ok(handle)
+    }
+
+    /// Applies an event to the projection, pushes it onto the projector and keeps it for the next flush
+    fn push_event(&mut self, event: ProjectionEvent<'a>) -> Result<()> {
+        let changes = self.describe(&event);
+        self.apply(&event)?;
+
+        if let Err(e) = self.projector.push(event.clone()) {
+            // Undo the change to the projection
+            self.rebuild()?;
+            return Err(e);
+        }
+
+        self.unsaved.push(event);
+        self.observers.notify(&changes);
+
+        Ok(())
+    }
+
+    /// Appends the events which haven't been persisted yet to the log of this inventory
+    pub fn flush(&mut self, storage: &mut impl Storage) -> Result<()> {
+        if !self.unsaved.is_empty() {
+            storage.append(self.inventory.uuid(), &self.unsaved)?;
+            self.unsaved.clear();
+        }
+
+        Ok(())
+    }
+
+    /// Writes the complete log of this inventory (to a storage backend which has no log of it yet)
+    pub fn save_all(&mut self, storage: &mut impl Storage) -> Result<()> {
+        storage.append(self.inventory.uuid(), self.projector.get_events())?;
+        self.unsaved.clear();
+
+        Ok(())
+    }
+
     pub fn is_owned_by(&self, user_uuid: &Uuid) -> bool {
         self.inventory.owner() == user_uuid
     }
@@ -146,6 +557,59 @@ impl<'a> InventoryHandle<'a> {
         self.allow_write(user_uuid) || self.inventory.readables().iter().any(|w| w == user_uuid)
     }
 
+    /// The access level of a member (`None` for the owner and for users who aren't members)
+    pub fn role_of(&self, user_uuid: &Uuid) -> Option<Role> {
+        if self.inventory.admins().contains(user_uuid) {
+            Some(Role::Admin)
+        } else if self.inventory.writables().contains(user_uuid) {
+            Some(Role::Write)
+        } else if self.inventory.readables().contains(user_uuid) {
+            Some(Role::Read)
+        } else {
+            None
+        }
+    }
+
+    /// Gives a user access to this inventory, changes their access level, or removes them (with `None`)
+    pub fn set_role(&mut self, user_uuid: Uuid, role: Option<Role>) -> Result<()> {
+        if self.is_owned_by(&user_uuid) {
+            bail!("The access of the owner can't be changed");
+        }
+
+        let mut inventory = self.inventory.clone();
+        inventory.admins_mut().retain(|u| *u != user_uuid);
+        inventory.writables_mut().retain(|u| *u != user_uuid);
+        inventory.readables_mut().retain(|u| *u != user_uuid);
+
+        match role {
+            Some(Role::Admin) => inventory.admins_mut().push(user_uuid),
+            Some(Role::Write) => inventory.writables_mut().push(user_uuid),
+            Some(Role::Read) => inventory.readables_mut().push(user_uuid),
+            None => {}
+        }
+
+        self.update_inventory(inventory)
+    }
+
+    /// The UUIDs of everyone who can read this inventory (without duplicates)
+    pub fn members(&self) -> Vec<Uuid> {
+        let mut members = vec![*self.inventory.owner()];
+
+        for user_uuid in self
+            .inventory
+            .admins()
+            .iter()
+            .chain(self.inventory.writables())
+            .chain(self.inventory.readables())
+        {
+            if !members.contains(user_uuid) {
+                members.push(*user_uuid);
+            }
+        }
+
+        members
+    }
+
     // There is no create_inventory(), as every inventory has its own event log
     // Instead, `new` generates a new inventory:
     pub fn new(name: String, owner: Uuid) -> Self {
@@ -160,211 +624,1548 @@ impl<'a> InventoryHandle<'a> {
 
         // Push the event onto the projector
         // Unwraps safely, because the projector and its segment are created before the event is made
-        projector.push(creation_event).unwrap();
+        projector.push(creation_event.clone()).unwrap();
 
         Self {
             projector,
+            index: ProjectionIndex::build(&inventory),
+            observers: Observers::default(),
             inventory,
+            unsaved: vec![creation_event],
         }
     }
 
-    pub fn update_inventory(&mut self, mut inventory: Inventory) -> Result<()> {
-        // TODO check for update permissions
+    /// Merges an event received from a peer (without checking who made it) and updates the projection
+    pub fn merge(&mut self, event: ProjectionEvent<'a>) -> Result<()> {
+        let in_order = self
+            .projector
+            .get_events()
+            .last()
+            .map_or(true, |last| event.get_timestamp() >= last.get_timestamp());
+
+        if in_order {
+            return self.push_event(event);
+        }
 
-        // Preserve the items of the inventory
-        *inventory.items_mut() = mem::take(self.inventory.items_mut());
+        // An older event can change what the later ones did, so the projection is rebuilt
+        // (and the event is only accepted if the result is consistent)
+        let changes = self.describe(&event);
+        let mut projector = self.projector.clone();
+        projector.push(event.clone())?;
+        let inventory = project(&projector)?;
 
-        // Replace the target
-        self.inventory = inventory.clone();
+        self.projector = projector;
+        self.index = ProjectionIndex::build(&inventory);
+        self.inventory = inventory;
+        self.unsaved.push(event);
+        self.observers.notify(&changes);
 
-        // Make an event
-        self.projector
-            .push(Event::update(Cow::Owned(ProjectionEntry::Inventory(
-                inventory,
-            ))))
+        Ok(())
     }
 
-    pub fn delete_inventory(&mut self, inventory: Inventory) -> Result<()> {
-        // TODO check for update permissions
+    /// Checks if a user is allowed to make the change described by an event
+    ///
+    /// Without knowing the device key the event was signed with, a member can only change their own
+    /// device keys as long as they have none (see `check_signed_permission`).
+    pub fn check_permission(&self, user_uuid: &Uuid, event: &ProjectionEvent<'_>) -> Result<()> {
+        self.check_signed_permission(user_uuid, None, event)
+    }
+
+    /// Checks if a user is allowed to make the change described by an event, which was signed with a device key
+    pub fn check_signed_permission(
+        &self,
+        user_uuid: &Uuid,
+        signing_key: Option<&[u8; 32]>,
+        event: &ProjectionEvent<'_>,
+    ) -> Result<()> {
+        let allowed = match event.get_data().as_ref() {
+            ProjectionEntry::Inventory(inventory) => {
+                if inventory.uuid() != self.inventory.uuid() {
+                    bail!("The event belongs to another inventory");
+                }
+
+                // Only the owner may delete the inventory or hand it over to someone else
+                if matches!(event.get_operation(), CRUD::Delete) {
+                    self.is_owned_by(user_uuid)
+                } else if inventory.owner() != self.inventory.owner() {
+                    self.is_owned_by(user_uuid)
+                        && self.allow_device_key_changes(user_uuid, signing_key, inventory)
+                } else {
+                    self.allow_admin(user_uuid)
+                        && self.allow_admin_changes(user_uuid, inventory)
+                        && self.allow_device_key_changes(user_uuid, signing_key, inventory)
+                }
+            }
+            ProjectionEntry::Item(item) => {
+                if item.inventory_uuid() != self.inventory.uuid() {
+                    bail!("The event belongs to another inventory");
+                }
+
+                self.allow_write(user_uuid)
+            }
+            ProjectionEntry::Unit(unit) => {
+                // Units only know their item, which has to be part of this inventory
+                let item_known = self.inventory.item(unit.item_uuid()).is_some();
+                let unit_known = self.unit(unit.uuid()).is_some();
+
+                let belongs_here = match event.get_operation() {
+                    CRUD::Create => item_known,
+                    CRUD::Update => item_known && unit_known,
+                    CRUD::Delete => unit_known,
+                };
+
+                if !belongs_here {
+                    bail!("The event belongs to another inventory");
+                }
+
+                self.allow_write(user_uuid)
+            }
+            ProjectionEntry::ShoppingEntry(entry) => {
+                if entry.inventory_uuid() != self.inventory.uuid() {
+                    bail!("The event belongs to another inventory");
+                }
+
+                self.allow_write(user_uuid)
+            }
+            ProjectionEntry::Recipe(recipe) => {
+                if recipe.inventory_uuid() != self.inventory.uuid() {
+                    bail!("The event belongs to another inventory");
+                }
+
+                self.allow_write(user_uuid)
+            }
+            ProjectionEntry::Rule(rule) => {
+                if rule.inventory_uuid() != self.inventory.uuid() {
+                    bail!("The event belongs to another inventory");
+                }
+
+                // Every member may manage their own notifications (but not take over someone else's rule)
+                let stored = self.inventory.rules().get(rule.uuid());
+                let own = match (event.get_operation(), stored) {
+                    (CRUD::Create, _) => rule.user_uuid() == user_uuid,
+                    (CRUD::Update, Some(stored)) => {
+                        stored.user_uuid() == user_uuid && rule.user_uuid() == user_uuid
+                    }
+                    (CRUD::Delete, Some(stored)) => stored.user_uuid() == user_uuid,
+                    (CRUD::Update, None) | (CRUD::Delete, None) => false,
+                };
+
+                (own && self.allow_read(user_uuid)) || self.allow_admin(user_uuid)
+            }
+            ProjectionEntry::NotificationReceipt(receipt) => {
+                if receipt.inventory_uuid() != self.inventory.uuid() {
+                    bail!("The event belongs to another inventory");
+                }
+
+                self.allow_read(user_uuid)
+            }
+        };
+
+        if !allowed {
+            bail!("The user is not allowed to make this change");
+        }
+
+        Ok(())
+    }
+
+    /// Checks the changes to the admins made by an update of the inventory
+    ///
+    /// Only the owner may appoint or remove admins (admins may only step down themselves).
+    fn allow_admin_changes(&self, user_uuid: &Uuid, changed: &Inventory) -> bool {
+        let before = self.inventory.admins();
+        let after = changed.admins();
+
+        self.is_owned_by(user_uuid)
+            || (after.iter().all(|a| before.contains(a))
+                && before.iter().all(|a| after.contains(a) || a == user_uuid))
+    }
+
+    /// Checks the changes to the device keys made by an update of the inventory
+    ///
+    /// Only the owner may change the keys of someone else. Members may change their own keys, but
+    /// once they have some, only with an event signed by one of them. Otherwise, anyone allowed to
+    /// update the inventory could register a key in someone else's name and sign events as them.
+    fn allow_device_key_changes(
+        &self,
+        user_uuid: &Uuid,
+        signing_key: Option<&[u8; 32]>,
+        changed: &Inventory,
+    ) -> bool {
+        let before = self.inventory.device_keys();
+        let after = changed.device_keys();
+
+        let mut key_owners: Vec<&Uuid> = before
+            .iter()
+            .filter(|k| !after.contains(k))
+            .chain(after.iter().filter(|k| !before.contains(k)))
+            .map(DeviceKey::user_uuid)
+            .collect();
+        key_owners.sort();
+        key_owners.dedup();
+
+        key_owners.into_iter().all(|key_owner| {
+            if key_owner != user_uuid {
+                return self.is_owned_by(user_uuid);
+            }
+
+            let mut own_keys = before
+                .iter()
+                .filter(|k| k.user_uuid() == user_uuid)
+                .peekable();
+
+            own_keys.peek().is_none()
+                || signing_key.map_or(false, |signing_key| {
+                    own_keys.any(|k| k.public_key() == signing_key)
+                })
+        })
+    }
+
+    /// Registers the public key of a member's device
+    pub fn add_device_key(&mut self, device_key: DeviceKey) -> Result<()> {
+        let mut inventory = self.inventory.clone();
+        inventory.device_keys_mut().push(device_key);
+
+        self.update_inventory(inventory)
+    }
+
+    /// Removes the public key of a member's device
+    pub fn remove_device_key(&mut self, public_key: &[u8; 32]) -> Result<()> {
+        let mut inventory = self.inventory.clone();
+        inventory
+            .device_keys_mut()
+            .retain(|k| k.public_key() != public_key);
+
+        self.update_inventory(inventory)
+    }
 
-        // TODO Remove the index
-        // mem::take(&mut self.inventory);
+    pub fn update_inventory(&mut self, inventory: Inventory) -> Result<()> {
+        // TODO check for update permissions
 
         // Make an event
-        self.projector
-            .push(Event::delete(Cow::Owned(ProjectionEntry::Inventory(
-                inventory,
-            ))))
+        self.push_event(Event::update(Cow::Owned(ProjectionEntry::Inventory(
+            inventory,
+        ))))
     }
 
-    pub fn create_item(&mut self, item: Item) -> Result<()> {
+    pub fn delete_inventory(&mut self, inventory: Inventory) -> Result<()> {
+        // TODO check for update permissions
+
         // Make an event
-        self.projector
-            .push(Event::create(Cow::Owned(ProjectionEntry::Item(
-                item.clone(),
-            ))))?;
+        let event = Event::delete(Cow::Owned(ProjectionEntry::Inventory(inventory)));
 
-        self.inventory.items_mut().push(item);
+        // A deleted inventory can't be projected, so the handle keeps its last state
+        let changes = self.describe(&event);
+        self.projector.push(event.clone())?;
+        self.unsaved.push(event);
+        self.observers.notify(&changes);
 
         Ok(())
     }
 
-    pub fn update_item(&mut self, mut item: Item) -> Result<()> {
-        let target = self
-            .inventory
-            .items_mut()
-            .iter_mut()
-            .find(|i| i.uuid() == item.uuid())
-            .ok_or(anyhow!("Item not found"))?;
+    /// Creates an item and returns the UUIDs of the other items which already have its barcode
+    ///
+    /// The item is created anyway, the conflicts are only a warning (e.g. to show to the user).
+    pub fn create_item(&mut self, item: Item) -> Result<Vec<Uuid>> {
+        let conflicts = self
+            .ean_conflicts(&item)
+            .into_iter()
+            .map(|i| *i.uuid())
+            .collect();
+
+        // Make an event
+        self.push_event(Event::create(Cow::Owned(ProjectionEntry::Item(item))))?;
 
-        // Preserve the units of the item
-        *item.units_mut() = mem::take(target.units_mut());
+        Ok(conflicts)
+    }
 
-        // Replace the target
-        *target = item.clone();
+    pub fn update_item(&mut self, item: Item) -> Result<()> {
+        let item_uuid = *item.uuid();
 
         // Make an event
-        self.projector
-            .push(Event::update(Cow::Owned(ProjectionEntry::Item(item))))
+        self.push_event(Event::update(Cow::Owned(ProjectionEntry::Item(item))))?;
+
+        // The minimum stock might have changed
+        self.refresh_shopping_list(&item_uuid)
     }
 
     pub fn delete_item(&mut self, item: Item) -> Result<()> {
-        let index = self
+        let item_uuid = *item.uuid();
+        let units: Vec<Unit> = self
             .inventory
-            .items_mut()
-            .iter_mut()
-            .position(|i| i.uuid() == item.uuid())
-            .ok_or(anyhow!("Item not found"))?;
+            .item(item.uuid())
+            .ok_or(anyhow!("Item not found"))?
+            .units()
+            .iter()
+            .cloned()
+            .collect();
 
-        // Remove the index
-        self.inventory.items_mut().remove(index);
+        // The units of the item go with it
+        for unit in &units {
+            self.push_event(Event::delete(Cow::Owned(ProjectionEntry::Unit(
+                unit.clone(),
+            ))))?;
+        }
 
-        // Make an event
-        self.projector
-            .push(Event::delete(Cow::Owned(ProjectionEntry::Item(item))))
+        // Make an event (which records the units deleted with the item, see `reports`)
+        let mut item = item;
+        item.units_mut().clear();
+        for unit in units {
+            item.units_mut().insert(unit);
+        }
+        self.push_event(Event::delete(Cow::Owned(ProjectionEntry::Item(item))))?;
+
+        // Nothing has to be bought automatically for an item which is gone
+        self.refresh_shopping_list(&item_uuid)
     }
 
     pub fn create_unit(&mut self, unit: Unit) -> Result<()> {
-        // Get the units of the associated item
-        let units = self
-            .inventory
-            .items_mut()
-            .iter_mut()
-            .find(|i| i.uuid() == unit.item_uuid())
-            .ok_or(anyhow!("Item not found"))?
-            .units_mut();
-
-        // Add the unit
-        units.push(unit.clone());
+        let item_uuid = *unit.item_uuid();
 
         // Make an event
-        self.projector
-            .push(Event::create(Cow::Owned(ProjectionEntry::Unit(unit))))
+        self.push_event(Event::create(Cow::Owned(ProjectionEntry::Unit(unit))))?;
+
+        // The new unit might restore the stock of the item
+        self.refresh_shopping_list(&item_uuid)
     }
 
     pub fn update_unit(&mut self, unit: Unit) -> Result<()> {
-        // Get the units of the associated item
-        let units = self
-            .inventory
-            .items_mut()
-            .iter_mut()
-            .find(|i| i.uuid() == unit.item_uuid())
-            .ok_or(anyhow!("Item not found"))?
-            .units_mut();
+        let item_uuid = *unit.item_uuid();
 
-        // Find the unt to replace
-        let target = units
-            .iter_mut()
-            .find(|u| u.uuid() == unit.uuid())
-            .ok_or(anyhow!("Unit not found"))?;
+        // Make an event
+        self.push_event(Event::update(Cow::Owned(ProjectionEntry::Unit(unit))))?;
 
-        // Replace the target
-        *target = unit.clone();
+        // Put the item on the shopping list if it is running low
+        self.refresh_shopping_list(&item_uuid)
+    }
 
-        // Make an event
-        self.projector
-            .push(Event::update(Cow::Owned(ProjectionEntry::Unit(unit))))
+    /// Uses up some of a unit (in percent of the whole unit), opening it if it wasn't opened yet
+    pub fn consume_unit(&mut self, unit_uuid: &Uuid, percent: f64) -> Result<()> {
+        if percent.is_nan() || percent < 0.0 {
+            bail!("Can't consume a negative amount");
+        }
+
+        let mut unit = self
+            .unit(unit_uuid)
+            .ok_or(anyhow!("Unit not found"))?
+            .clone();
+
+        *unit.percent_left_mut() = (unit.percent_left() - percent).max(0.0);
+
+        if unit.opened_on().is_none() {
+            *unit.opened_on_mut() = Some(Utc::now());
+        }
+
+        self.update_unit(unit)
     }
 
-    pub fn delete_unit(&mut self, unit: Unit) -> Result<()> {
-        // Get the units of the associated item
-        let units = self
+    /// Replaces the tags of an item
+    pub fn set_tags(&mut self, item_uuid: &Uuid, tags: Vec<String>) -> Result<()> {
+        let mut item = self
             .inventory
-            .items_mut()
-            .iter_mut()
-            .find(|i| i.uuid() == unit.item_uuid())
+            .item(item_uuid)
             .ok_or(anyhow!("Item not found"))?
-            .units_mut();
+            .clone();
 
-        // Find the index of the unit to be deleted
-        let index = units
-            .iter()
-            .position(|u| u.uuid() == unit.uuid())
-            .ok_or(anyhow!("Unit not found"))?;
+        *item.tags_mut() = tags;
 
-        // Remove the index
-        units.remove(index);
+        self.update_item(item)
+    }
+
+    pub fn delete_unit(&mut self, unit: Unit) -> Result<()> {
+        let item_uuid = *unit.item_uuid();
 
         // Make an event
-        self.projector
-            .push(Event::delete(Cow::Owned(ProjectionEntry::Unit(unit))))
+        self.push_event(Event::delete(Cow::Owned(ProjectionEntry::Unit(unit))))?;
+
+        // Put the item on the shopping list if it is running low
+        self.refresh_shopping_list(&item_uuid)
     }
 }
 
-impl<'a> TryFrom<Projector<'a, ProjectionEntry>> for InventoryHandle<'a> {
-    type Error = anyhow::Error;
+// Lookups
+impl<'a> InventoryHandle<'a> {
+    /// Finds a unit of any item of the inventory by its UUID
+    pub fn unit(&self, uuid: &Uuid) -> Option<&Unit> {
+        self.item_of_unit(uuid)?.unit(uuid)
+    }
 
-    fn try_from(projector: Projector<'a, ProjectionEntry>) -> Result<Self, Self::Error> {
-        let mut inventory_option = None;
-        let mut items = HashMap::new();
-        let mut units = vec![];
-
-        // Split up the entries in the projection based on their types
-        for projected_thing in projector.get_projection().clone() {
-            match projected_thing.into_owned() {
-                ProjectionEntry::Inventory(inventory) => {
-                    if inventory_option.is_some() {
-                        bail!("Cannot have two inventories in one projector");
-                    } else {
-                        inventory_option = Some(inventory);
+    /// Finds the item which a unit belongs to
+    pub fn item_of_unit(&self, unit_uuid: &Uuid) -> Option<&Item> {
+        self.inventory.item(self.index.unit_items.get(unit_uuid)?)
+    }
+}
+
+// Barcodes
+impl<'a> InventoryHandle<'a> {
+    /// Finds an item by its barcode
+    pub fn item_by_ean(&self, ean: &Ean) -> Option<&Item> {
+        self.inventory
+            .items()
+            .iter()
+            .find(|i| i.ean().as_ref() == Some(ean))
+    }
+
+    /// Finds the other items which already have the barcode of an item (e.g. to warn before creating it)
+    pub fn ean_conflicts(&self, item: &Item) -> Vec<&Item> {
+        match item.ean() {
+            Some(ean) => self
+                .inventory
+                .items()
+                .iter()
+                .filter(|i| i.uuid() != item.uuid() && i.ean().as_ref() == Some(ean))
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    /// Finds the barcodes which are used by more than one item (together with the UUIDs of these items)
+    pub fn duplicate_eans(&self) -> Vec<(Ean, Vec<Uuid>)> {
+        let mut items_by_ean: BTreeMap<&Ean, Vec<Uuid>> = BTreeMap::new();
+
+        for item in self.inventory.items() {
+            if let Some(ean) = item.ean() {
+                items_by_ean.entry(ean).or_default().push(*item.uuid());
+            }
+        }
+
+        items_by_ean
+            .into_iter()
+            .filter(|(_, items)| items.len() > 1)
+            .map(|(ean, items)| (ean.clone(), items))
+            .collect()
+    }
+}
+
+// Shopping list
+impl<'a> InventoryHandle<'a> {
+    /// Sets the stock below which an item is put on the shopping list
+    pub fn set_min_stock(&mut self, item_uuid: &Uuid, min_stock: Option<MinStock>) -> Result<()> {
+        let mut item = self
+            .inventory
+            .item(item_uuid)
+            .ok_or(anyhow!("Item not found"))?
+            .clone();
+
+        *item.min_stock_mut() = min_stock;
+
+        self.update_item(item)
+    }
+
+    pub fn create_shopping_entry(&mut self, entry: ShoppingEntry) -> Result<()> {
+        if entry.inventory_uuid() != self.inventory.uuid() {
+            bail!("The shopping list entry is not part of the inventory");
+        }
+
+        // Make an event
+        self.push_event(Event::create(Cow::Owned(ProjectionEntry::ShoppingEntry(
+            entry,
+        ))))
+    }
+
+    pub fn delete_shopping_entry(&mut self, entry: ShoppingEntry) -> Result<()> {
+        // Make an event
+        self.push_event(Event::delete(Cow::Owned(ProjectionEntry::ShoppingEntry(
+            entry,
+        ))))
+    }
+
+    /// Checks off an entry of the shopping list and adds the bought units to its item
+    pub fn check_off_shopping_entry(&mut self, entry_uuid: &Uuid, units: Vec<Unit>) -> Result<()> {
+        let entry = self
+            .inventory
+            .shopping_list()
+            .get(entry_uuid)
+            .ok_or(anyhow!("Shopping list entry not found"))?
+            .clone();
+
+        // Only units of the entry's item can be bought with it
+        if units
+            .iter()
+            .any(|u| Some(*u.item_uuid()) != *entry.item_uuid())
+        {
+            bail!("The units don't belong to the item of the shopping list entry");
+        }
+
+        self.delete_shopping_entry(entry)?;
+
+        for unit in units {
+            self.create_unit(unit)?;
+        }
+
+        Ok(())
+    }
+
+    /// Keeps the automatic entries of the shopping list in line with the stock of an item
+    ///
+    /// An item whose stock fell below its minimum is put on the list (unless it is listed already),
+    /// and its automatic entries are taken off again once the stock is restored (or the item is gone).
+    fn refresh_shopping_list(&mut self, item_uuid: &Uuid) -> Result<()> {
+        let is_listed = self
+            .inventory
+            .shopping_list()
+            .iter()
+            .any(|e| e.item_uuid() == &Some(*item_uuid));
+
+        match self.inventory.item(item_uuid) {
+            Some(item) if item.is_below_min_stock() => {
+                if !is_listed {
+                    let entry = ShoppingEntry::automatic(item);
+                    self.create_shopping_entry(entry)?;
+                }
+            }
+            _ => {
+                // Taking an entry off lists the next one of the item (see `listing_order`)
+                while let Some(entry) = self
+                    .inventory
+                    .shopping_list()
+                    .iter()
+                    .find(|e| e.is_automatic() && e.item_uuid() == &Some(*item_uuid))
+                    .cloned()
+                {
+                    self.delete_shopping_entry(entry)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Recipes
+impl<'a> InventoryHandle<'a> {
+    pub fn create_recipe(&mut self, recipe: Recipe) -> Result<()> {
+        if recipe.inventory_uuid() != self.inventory.uuid() {
+            bail!("The recipe is not part of the inventory");
+        }
+
+        // Make an event
+        self.push_event(Event::create(Cow::Owned(ProjectionEntry::Recipe(recipe))))
+    }
+
+    pub fn update_recipe(&mut self, recipe: Recipe) -> Result<()> {
+        // Make an event
+        self.push_event(Event::update(Cow::Owned(ProjectionEntry::Recipe(recipe))))
+    }
+
+    pub fn delete_recipe(&mut self, recipe: Recipe) -> Result<()> {
+        // Make an event
+        self.push_event(Event::delete(Cow::Owned(ProjectionEntry::Recipe(recipe))))
+    }
+}
+
+// Notification rules
+impl<'a> InventoryHandle<'a> {
+    pub fn create_rule(&mut self, rule: Rule) -> Result<()> {
+        if rule.inventory_uuid() != self.inventory.uuid() {
+            bail!("The rule is not part of the inventory");
+        }
+
+        // Make an event
+        self.push_event(Event::create(Cow::Owned(ProjectionEntry::Rule(rule))))
+    }
+
+    pub fn update_rule(&mut self, rule: Rule) -> Result<()> {
+        // Make an event
+        self.push_event(Event::update(Cow::Owned(ProjectionEntry::Rule(rule))))
+    }
+
+    pub fn delete_rule(&mut self, rule: Rule) -> Result<()> {
+        // Make an event
+        self.push_event(Event::delete(Cow::Owned(ProjectionEntry::Rule(rule))))
+    }
+
+    pub fn create_notification_receipt(&mut self, receipt: NotificationReceipt) -> Result<()> {
+        if receipt.inventory_uuid() != self.inventory.uuid() {
+            bail!("The notification receipt is not part of the inventory");
+        }
+
+        // Make an event
+        self.push_event(Event::create(Cow::Owned(
+            ProjectionEntry::NotificationReceipt(receipt),
+        )))
+    }
+
+    pub fn delete_notification_receipt(&mut self, receipt: NotificationReceipt) -> Result<()> {
+        // Make an event
+        self.push_event(Event::delete(Cow::Owned(
+            ProjectionEntry::NotificationReceipt(receipt),
+        )))
+    }
+}
+
+// Subscriptions
+impl<'a> InventoryHandle<'a> {
+    /// Calls a function with every change of the inventory
+    pub fn subscribe(&mut self, callback: impl FnMut(&Change) + Send + 'static) -> SubscriptionId {
+        self.observers.subscribe(callback)
+    }
+
+    /// Calls a function which can't be sent between threads with every change of the inventory
+    #[cfg(all(target_arch = "wasm32", not(target_feature = "atomics")))]
+    pub fn subscribe_local(&mut self, callback: impl FnMut(&Change) + 'static) -> SubscriptionId {
+        self.observers.subscribe_local(callback)
+    }
+
+    /// Sends every change of the inventory into a channel
+    pub fn subscribe_channel(&mut self) -> (SubscriptionId, Receiver<Change>) {
+        self.observers.subscribe_channel()
+    }
+
+    /// Takes over the state of a copy of this handle which was changed on its own
+    ///
+    /// The copy has no subscribers, so the changes made to it are only reported now (to the
+    /// subscribers of this handle).
+    pub(crate) fn commit(&mut self, copy: InventoryHandle<'a>, changes: &[Change]) {
+        let observers = std::mem::take(&mut self.observers);
+
+        *self = InventoryHandle { observers, ..copy };
+        self.observers.notify(changes);
+    }
+
+    /// Ends a subscription (returns if it existed)
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        self.observers.unsubscribe(id)
+    }
+
+    /// Describes what an event is going to change (before it is applied)
+    fn describe(&self, event: &ProjectionEvent<'_>) -> Vec<Change> {
+        if self.observers.is_empty() {
+            return vec![];
+        }
+
+        let kinds = match (event.get_operation(), event.get_data().as_ref()) {
+            (CRUD::Create, ProjectionEntry::Inventory(inventory))
+            | (CRUD::Update, ProjectionEntry::Inventory(inventory)) => {
+                let before = roles(&self.inventory);
+                let after = roles(inventory);
+                let mut kinds = vec![ChangeKind::InventoryUpdated];
+
+                for (user_uuid, role) in &after {
+                    match before.get(user_uuid) {
+                        None => kinds.push(ChangeKind::MemberAdded {
+                            user_uuid: *user_uuid,
+                            role: *role,
+                        }),
+                        Some(previous) if previous != role => {
+                            kinds.push(ChangeKind::MemberRoleChanged {
+                                user_uuid: *user_uuid,
+                                role: *role,
+                            })
+                        }
+                        Some(_) => {}
                     }
                 }
-                ProjectionEntry::Item(item) => {
-                    if items.insert(item.uuid().clone(), item).is_some() {
-                        bail!("Cannot have two items with the same uuid");
+
+                for user_uuid in before.keys().filter(|u| !after.contains_key(u)) {
+                    kinds.push(ChangeKind::MemberRemoved {
+                        user_uuid: *user_uuid,
+                    });
+                }
+
+                kinds
+            }
+            (CRUD::Delete, ProjectionEntry::Inventory(_)) => vec![ChangeKind::InventoryDeleted],
+            (CRUD::Create, ProjectionEntry::Item(item)) => vec![ChangeKind::ItemAdded {
+                item_uuid: *item.uuid(),
+            }],
+            (CRUD::Update, ProjectionEntry::Item(item)) => vec![ChangeKind::ItemUpdated {
+                item_uuid: *item.uuid(),
+            }],
+            (CRUD::Delete, ProjectionEntry::Item(item)) => vec![ChangeKind::ItemRemoved {
+                item_uuid: *item.uuid(),
+            }],
+            (CRUD::Create, ProjectionEntry::Unit(unit)) => vec![ChangeKind::UnitAdded {
+                item_uuid: *unit.item_uuid(),
+                unit_uuid: *unit.uuid(),
+            }],
+            (CRUD::Update, ProjectionEntry::Unit(unit)) => {
+                let consumed = self.unit(unit.uuid()).map_or(0.0, |previous| {
+                    previous.percent_left() - unit.percent_left()
+                });
+
+                if consumed > 0.0 {
+                    vec![ChangeKind::UnitConsumed {
+                        item_uuid: *unit.item_uuid(),
+                        unit_uuid: *unit.uuid(),
+                        percent: consumed,
+                    }]
+                } else {
+                    vec![ChangeKind::UnitUpdated {
+                        item_uuid: *unit.item_uuid(),
+                        unit_uuid: *unit.uuid(),
+                    }]
+                }
+            }
+            (CRUD::Delete, ProjectionEntry::Unit(unit)) => vec![ChangeKind::UnitRemoved {
+                item_uuid: *unit.item_uuid(),
+                unit_uuid: *unit.uuid(),
+            }],
+            (CRUD::Create, ProjectionEntry::ShoppingEntry(entry)) => {
+                vec![ChangeKind::ShoppingEntryAdded {
+                    entry_uuid: *entry.uuid(),
+                }]
+            }
+            (CRUD::Update, ProjectionEntry::ShoppingEntry(entry)) => {
+                vec![ChangeKind::ShoppingEntryUpdated {
+                    entry_uuid: *entry.uuid(),
+                }]
+            }
+            (CRUD::Delete, ProjectionEntry::ShoppingEntry(entry)) => {
+                vec![ChangeKind::ShoppingEntryRemoved {
+                    entry_uuid: *entry.uuid(),
+                }]
+            }
+            (CRUD::Create, ProjectionEntry::Recipe(recipe)) => vec![ChangeKind::RecipeAdded {
+                recipe_uuid: *recipe.uuid(),
+            }],
+            (CRUD::Update, ProjectionEntry::Recipe(recipe)) => vec![ChangeKind::RecipeUpdated {
+                recipe_uuid: *recipe.uuid(),
+            }],
+            (CRUD::Delete, ProjectionEntry::Recipe(recipe)) => vec![ChangeKind::RecipeRemoved {
+                recipe_uuid: *recipe.uuid(),
+            }],
+            (CRUD::Create, ProjectionEntry::Rule(rule)) => vec![ChangeKind::RuleAdded {
+                rule_uuid: *rule.uuid(),
+            }],
+            (CRUD::Update, ProjectionEntry::Rule(rule)) => vec![ChangeKind::RuleUpdated {
+                rule_uuid: *rule.uuid(),
+            }],
+            (CRUD::Delete, ProjectionEntry::Rule(rule)) => vec![ChangeKind::RuleRemoved {
+                rule_uuid: *rule.uuid(),
+            }],
+            (CRUD::Create, ProjectionEntry::NotificationReceipt(receipt)) => {
+                vec![ChangeKind::NotificationSent {
+                    receipt_uuid: *receipt.uuid(),
+                }]
+            }
+            // Receipts are only ever created
+            (_, ProjectionEntry::NotificationReceipt(_)) => vec![],
+        };
+
+        kinds
+            .into_iter()
+            .map(|kind| Change {
+                inventory_uuid: *self.inventory.uuid(),
+                kind,
+            })
+            .collect()
+    }
+}
+
+/// The access levels of the members of an inventory (besides its owner)
+fn roles(inventory: &Inventory) -> BTreeMap<Uuid, Role> {
+    let mut roles = BTreeMap::new();
+
+    // The highest access level counts (like in `role_of`)
+    for (users, role) in &[
+        (inventory.readables(), Role::Read),
+        (inventory.writables(), Role::Write),
+        (inventory.admins(), Role::Admin),
+    ] {
+        for user_uuid in users.iter() {
+            roles.insert(*user_uuid, *role);
+        }
+    }
+
+    roles
+}
+
+// Projection
+impl<'a> InventoryHandle<'a> {
+    /// Applies an event to the projection, finding its target through the indexes
+    ///
+    /// Local changes and events merged from peers both go through here. The event is checked before
+    /// anything is changed, so a rejected event leaves the projection as it was.
+    fn apply(&mut self, event: &ProjectionEvent<'_>) -> Result<()> {
+        let Self {
+            projector,
+            inventory,
+            index,
+            ..
+        } = self;
+        let inventory_uuid = *inventory.uuid();
+
+        match (event.get_operation(), event.get_data().as_ref()) {
+            (CRUD::Create, ProjectionEntry::Inventory(changed))
+            | (CRUD::Update, ProjectionEntry::Inventory(changed)) => {
+                if changed.uuid() != &inventory_uuid {
+                    bail!("Cannot have two inventories in one projector");
+                }
+
+                // Preserve the collections of the inventory (they are projected on their own)
+                let mut changed = changed.clone();
+                *changed.items_mut() = mem::take(inventory.items_mut());
+                *changed.shopping_list_mut() = mem::take(inventory.shopping_list_mut());
+                *changed.recipes_mut() = mem::take(inventory.recipes_mut());
+                *changed.rules_mut() = mem::take(inventory.rules_mut());
+                *changed.notification_receipts_mut() =
+                    mem::take(inventory.notification_receipts_mut());
+
+                *inventory = changed;
+            }
+            (CRUD::Delete, ProjectionEntry::Inventory(_)) => {
+                bail!("A deleted inventory can't be projected")
+            }
+            (CRUD::Create, ProjectionEntry::Item(item)) => {
+                check_inventory(&inventory_uuid, item)?;
+
+                if inventory.items().contains(item.uuid()) {
+                    bail!("Cannot have two items with the same uuid");
+                }
+
+                // The units are projected on their own
+                let mut item = item.clone();
+                item.units_mut().clear();
+
+                inventory.items_mut().insert(item);
+            }
+            (CRUD::Update, ProjectionEntry::Item(item)) => {
+                check_inventory(&inventory_uuid, item)?;
+                let target = inventory
+                    .items_mut()
+                    .get_mut(item.uuid())
+                    .ok_or(anyhow!("Item not found"))?;
+
+                // Preserve the units of the item
+                let mut item = item.clone();
+                *item.units_mut() = mem::take(target.units_mut());
+
+                *target = item;
+            }
+            (CRUD::Delete, ProjectionEntry::Item(item)) => {
+                let target = inventory
+                    .item(item.uuid())
+                    .ok_or(anyhow!("Item not found"))?;
+
+                if !target.units().is_empty() {
+                    bail!("The item still has units");
+                }
+
+                inventory.items_mut().remove(item.uuid());
+            }
+            (CRUD::Create, ProjectionEntry::Unit(unit)) => {
+                if index.unit_items.contains_key(unit.uuid()) {
+                    bail!("Cannot have two units with the same uuid");
+                }
+
+                inventory
+                    .items_mut()
+                    .get_mut(unit.item_uuid())
+                    .ok_or(anyhow!("No such item"))?
+                    .units_mut()
+                    .insert(unit.clone());
+                index.unit_items.insert(*unit.uuid(), *unit.item_uuid());
+            }
+            (CRUD::Update, ProjectionEntry::Unit(unit)) => {
+                let previous_item_uuid = *index
+                    .unit_items
+                    .get(unit.uuid())
+                    .ok_or(anyhow!("Unit not found"))?;
+
+                if !inventory.items().contains(unit.item_uuid()) {
+                    bail!("No such item");
+                }
+
+                // The unit might have moved to another item
+                if let Some(previous_item) = inventory.items_mut().get_mut(&previous_item_uuid) {
+                    previous_item.units_mut().remove(unit.uuid());
+                }
+
+                inventory
+                    .items_mut()
+                    .get_mut(unit.item_uuid())
+                    .ok_or(anyhow!("No such item"))?
+                    .units_mut()
+                    .insert(unit.clone());
+                index.unit_items.insert(*unit.uuid(), *unit.item_uuid());
+            }
+            (CRUD::Delete, ProjectionEntry::Unit(unit)) => {
+                let item_uuid = index
+                    .unit_items
+                    .remove(unit.uuid())
+                    .ok_or(anyhow!("Unit not found"))?;
+
+                if let Some(item) = inventory.items_mut().get_mut(&item_uuid) {
+                    item.units_mut().remove(unit.uuid());
+                }
+            }
+            (CRUD::Create, ProjectionEntry::ShoppingEntry(entry)) => {
+                check_inventory(&inventory_uuid, entry)?;
+
+                // Only the first automatic entry of an item is listed (see `listing_order`)
+                let listed = inventory
+                    .shopping_list()
+                    .iter()
+                    .find(|e| compete(e, entry))
+                    .cloned();
+                match listed {
+                    Some(listed) if listing_order(&listed, entry) == Ordering::Less => {
+                        if projected_entries(projector)
+                            .into_iter()
+                            .any(|e| e.uuid() == entry.uuid())
+                        {
+                            bail!("Cannot have two entries with the uuid {}", entry.uuid());
+                        }
+                    }
+                    Some(listed) => {
+                        insert_new(inventory.shopping_list_mut(), entry.clone())?;
+                        inventory.shopping_list_mut().remove(listed.uuid());
                     }
+                    None => insert_new(inventory.shopping_list_mut(), entry.clone())?,
+                }
+            }
+            (CRUD::Update, ProjectionEntry::ShoppingEntry(entry)) => {
+                check_inventory(&inventory_uuid, entry)?;
+
+                // Entries which aren't listed are only kept by the projector
+                if !is_unlisted(projector, inventory.shopping_list(), entry.uuid()) {
+                    replace_existing(inventory.shopping_list_mut(), entry.clone())?;
                 }
-                ProjectionEntry::Unit(unit) => {
-                    units.push(unit);
+            }
+            (CRUD::Delete, ProjectionEntry::ShoppingEntry(entry)) => {
+                if !is_unlisted(projector, inventory.shopping_list(), entry.uuid()) {
+                    let removed = remove_existing(inventory.shopping_list_mut(), entry.uuid())?;
+
+                    // The next automatic entry of the item (if any) takes its place
+                    let next = projected_entries(projector)
+                        .into_iter()
+                        .filter(|e| compete(e, &removed))
+                        .min_by(|a, b| listing_order(a, b))
+                        .cloned();
+                    if let Some(next) = next {
+                        inventory.shopping_list_mut().insert(next);
+                    }
                 }
             }
+            (CRUD::Create, ProjectionEntry::Recipe(recipe)) => {
+                check_inventory(&inventory_uuid, recipe)?;
+                insert_new(inventory.recipes_mut(), recipe.clone())?;
+            }
+            (CRUD::Update, ProjectionEntry::Recipe(recipe)) => {
+                check_inventory(&inventory_uuid, recipe)?;
+                replace_existing(inventory.recipes_mut(), recipe.clone())?;
+            }
+            (CRUD::Delete, ProjectionEntry::Recipe(recipe)) => {
+                remove_existing(inventory.recipes_mut(), recipe.uuid())?;
+            }
+            (CRUD::Create, ProjectionEntry::Rule(rule)) => {
+                check_inventory(&inventory_uuid, rule)?;
+                insert_new(inventory.rules_mut(), rule.clone())?;
+            }
+            (CRUD::Update, ProjectionEntry::Rule(rule)) => {
+                check_inventory(&inventory_uuid, rule)?;
+                replace_existing(inventory.rules_mut(), rule.clone())?;
+            }
+            (CRUD::Delete, ProjectionEntry::Rule(rule)) => {
+                remove_existing(inventory.rules_mut(), rule.uuid())?;
+            }
+            (CRUD::Create, ProjectionEntry::NotificationReceipt(receipt)) => {
+                check_inventory(&inventory_uuid, receipt)?;
+                insert_new(inventory.notification_receipts_mut(), receipt.clone())?;
+            }
+            (CRUD::Update, ProjectionEntry::NotificationReceipt(receipt)) => {
+                check_inventory(&inventory_uuid, receipt)?;
+                replace_existing(inventory.notification_receipts_mut(), receipt.clone())?;
+            }
+            (CRUD::Delete, ProjectionEntry::NotificationReceipt(receipt)) => {
+                remove_existing(inventory.notification_receipts_mut(), receipt.uuid())?;
+            }
         }
 
-        // Build the inventory to return
-        let mut inventory = inventory_option.ok_or(anyhow!("No such inventory"))?;
+        Ok(())
+    }
 
-        // Push the units into their respective items
-        for unit in units {
-            items
-                .get_mut(unit.uuid())
-                .ok_or(anyhow!("No such item"))?
-                .units_mut()
-                .push(unit)
+    /// Rebuilds the projection (and its indexes) from the projector
+    fn rebuild(&mut self) -> Result<()> {
+        self.inventory = project(&self.projector)?;
+        self.index = ProjectionIndex::build(&self.inventory);
+
+        Ok(())
+    }
+
+    /// Checks that the projection equals a rebuild from the projector, and that its indexes are up to date
+    ///
+    /// This rebuilds the whole inventory, so it is meant for tests and diagnostics.
+    pub fn verify_projection(&self) -> Result<()> {
+        let rebuilt = project(&self.projector)?;
+
+        if serde_json::to_value(&self.inventory)? != serde_json::to_value(&rebuilt)? {
+            bail!("The projection differs from a rebuild of the projector");
         }
 
-        // Push the items into the inventory (checked)
-        for (_, item) in items {
-            if item.inventory_uuid() != inventory.uuid() {
-                bail!("The item is not part of the inventory");
-            }
+        if self.index != ProjectionIndex::build(&self.inventory) {
+            bail!("The indexes of the projection are out of date");
+        }
+
+        Ok(())
+    }
+}
+
+/// An entry which is part of exactly one inventory
+trait InventoryPart: Keyed {
+    fn owner_uuid(&self) -> &Uuid;
+}
+
+impl InventoryPart for Item {
+    fn owner_uuid(&self) -> &Uuid {
+        self.inventory_uuid()
+    }
+}
+
+impl Keyed for ShoppingEntry {
+    const NAME: &'static str = "shopping list entry";
+
+    fn key(&self) -> &Uuid {
+        self.uuid()
+    }
+}
+
+impl InventoryPart for ShoppingEntry {
+    fn owner_uuid(&self) -> &Uuid {
+        self.inventory_uuid()
+    }
+}
 
-            inventory.items_mut().push(item);
+impl Keyed for Recipe {
+    const NAME: &'static str = "recipe";
+
+    fn key(&self) -> &Uuid {
+        self.uuid()
+    }
+}
+
+impl InventoryPart for Recipe {
+    fn owner_uuid(&self) -> &Uuid {
+        self.inventory_uuid()
+    }
+}
+
+impl Keyed for Rule {
+    const NAME: &'static str = "rule";
+
+    fn key(&self) -> &Uuid {
+        self.uuid()
+    }
+}
+
+impl InventoryPart for Rule {
+    fn owner_uuid(&self) -> &Uuid {
+        self.inventory_uuid()
+    }
+}
+
+impl Keyed for NotificationReceipt {
+    const NAME: &'static str = "notification receipt";
+
+    fn key(&self) -> &Uuid {
+        self.uuid()
+    }
+}
+
+impl InventoryPart for NotificationReceipt {
+    fn owner_uuid(&self) -> &Uuid {
+        self.inventory_uuid()
+    }
+}
+
+/// Checks that an entry is part of the inventory with the given UUID
+fn check_inventory<T: InventoryPart>(inventory_uuid: &Uuid, entry: &T) -> Result<()> {
+    if entry.owner_uuid() != inventory_uuid {
+        bail!("The {} is not part of the inventory", T::NAME);
+    }
+
+    Ok(())
+}
+
+/// Adds an entry to a collection of the projection (which mustn't have it yet)
+fn insert_new<T: Keyed>(entries: &mut EntryMap<T>, entry: T) -> Result<()> {
+    if entries.contains(entry.key()) {
+        bail!("Cannot have two entries with the uuid {}", entry.key());
+    }
+
+    entries.insert(entry);
+
+    Ok(())
+}
+
+/// Replaces an entry of a collection of the projection
+fn replace_existing<T: Keyed>(entries: &mut EntryMap<T>, entry: T) -> Result<()> {
+    let target = entries
+        .get_mut(entry.key())
+        .ok_or_else(|| anyhow!("The {} {} doesn't exist", T::NAME, entry.key()))?;
+    *target = entry;
+
+    Ok(())
+}
+
+/// The order in which the automatic entries of an item are listed: the oldest one first (and the
+/// one with the lower UUID, if they are equally old)
+///
+/// Devices which sync can each add an automatic entry for the same item. Only the first one is
+/// listed, so every device lists the same entry, no matter in which order the entries arrive.
+fn listing_order(a: &ShoppingEntry, b: &ShoppingEntry) -> Ordering {
+    (a.created_on(), a.uuid()).cmp(&(b.created_on(), b.uuid()))
+}
+
+/// Checks if two shopping list entries are different automatic entries for the same item
+fn compete(a: &ShoppingEntry, b: &ShoppingEntry) -> bool {
+    a.is_automatic()
+        && b.is_automatic()
+        && a.item_uuid().is_some()
+        && a.item_uuid() == b.item_uuid()
+        && a.uuid() != b.uuid()
+}
+
+/// The shopping list entries of a projector (including the automatic ones which aren't listed)
+fn projected_entries<'p>(projector: &'p Projector<'_, ProjectionEntry>) -> Vec<&'p ShoppingEntry> {
+    projector
+        .get_projection()
+        .iter()
+        .filter_map(|entry| match entry.as_ref() {
+            ProjectionEntry::ShoppingEntry(entry) => Some(entry),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Checks if a shopping list entry is an automatic entry which is left out of the list
+fn is_unlisted(
+    projector: &Projector<'_, ProjectionEntry>,
+    listed: &EntryMap<ShoppingEntry>,
+    uuid: &Uuid,
+) -> bool {
+    !listed.contains(uuid)
+        && projected_entries(projector)
+            .into_iter()
+            .any(|e| e.uuid() == uuid)
+}
+
+/// Removes an entry from a collection of the projection
+fn remove_existing<T: Keyed>(entries: &mut EntryMap<T>, uuid: &Uuid) -> Result<T> {
+    entries
+        .remove(uuid)
+        .ok_or_else(|| anyhow!("The {} {} doesn't exist", T::NAME, uuid))
+}
+
+/// Where to find the entries of the projection of an inventory by their UUIDs
+///
+/// The other collections are keyed by UUID themselves.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct ProjectionIndex {
+    /// The item of every unit
+    unit_items: HashMap<Uuid, Uuid>,
+}
+
+impl ProjectionIndex {
+    fn build(inventory: &Inventory) -> Self {
+        let mut index = Self::default();
+
+        for item in inventory.items() {
+            for unit in item.units() {
+                index.unit_items.insert(*unit.uuid(), *item.uuid());
+            }
         }
 
+        index
+    }
+}
+
+impl<'a> TryFrom<Projector<'a, ProjectionEntry>> for InventoryHandle<'a> {
+    type Error = anyhow::Error;
+
+    fn try_from(projector: Projector<'a, ProjectionEntry>) -> Result<Self, Self::Error> {
+        let inventory = project(&projector)?;
+
         // Return the projected inventory
         Ok(Self {
             projector,
+            index: ProjectionIndex::build(&inventory),
+            observers: Observers::default(),
             inventory,
+            unsaved: vec![],
         })
     }
 }
+
+/// Builds the inventory (with its items and units) from the projection of a projector
+pub(super) fn project(projector: &Projector<'_, ProjectionEntry>) -> Result<Inventory> {
+    let mut inventory_option = None;
+    let mut items = HashMap::new();
+    let mut units = vec![];
+    let mut shopping_list = vec![];
+    let mut recipes = vec![];
+    let mut rules = vec![];
+    let mut notification_receipts = vec![];
+
+    // Split up the entries in the projection based on their types
+    for projected_thing in projector.get_projection().clone() {
+        match projected_thing.into_owned() {
+            ProjectionEntry::Inventory(inventory) => {
+                if inventory_option.is_some() {
+                    bail!("Cannot have two inventories in one projector");
+                } else {
+                    inventory_option = Some(inventory);
+                }
+            }
+            ProjectionEntry::Item(mut item) => {
+                // The units are projected on their own
+                item.units_mut().clear();
+
+                if items.insert(item.uuid().clone(), item).is_some() {
+                    bail!("Cannot have two items with the same uuid");
+                }
+            }
+            ProjectionEntry::Unit(unit) => {
+                units.push(unit);
+            }
+            ProjectionEntry::ShoppingEntry(entry) => {
+                shopping_list.push(entry);
+            }
+            ProjectionEntry::Recipe(recipe) => {
+                recipes.push(recipe);
+            }
+            ProjectionEntry::Rule(rule) => {
+                rules.push(rule);
+            }
+            ProjectionEntry::NotificationReceipt(receipt) => {
+                notification_receipts.push(receipt);
+            }
+        }
+    }
+
+    // Build the inventory to return (its collections are projected on their own)
+    let mut inventory = inventory_option.ok_or(anyhow!("No such inventory"))?;
+    inventory.items_mut().clear();
+    inventory.shopping_list_mut().clear();
+    inventory.recipes_mut().clear();
+    inventory.rules_mut().clear();
+    inventory.notification_receipts_mut().clear();
+
+    // Push the units into their respective items
+    for unit in units {
+        items
+            .get_mut(unit.item_uuid())
+            .ok_or(anyhow!("No such item"))?
+            .units_mut()
+            .insert(unit);
+    }
+
+    // Push the items into the inventory (checked)
+    for (_, item) in items {
+        if item.inventory_uuid() != inventory.uuid() {
+            bail!("The item is not part of the inventory");
+        }
+
+        inventory.items_mut().insert(item);
+    }
+
+    // Push the entries into the shopping list (checked), only the first automatic entry per item
+    let mut automatic: HashMap<Uuid, ShoppingEntry> = HashMap::new();
+    for entry in shopping_list {
+        if entry.inventory_uuid() != inventory.uuid() {
+            bail!("The shopping list entry is not part of the inventory");
+        }
+
+        if let (true, Some(item_uuid)) = (entry.is_automatic(), *entry.item_uuid()) {
+            let listed = automatic.entry(item_uuid).or_insert_with(|| entry.clone());
+            if listing_order(&entry, listed) == Ordering::Less {
+                *listed = entry;
+            }
+
+            continue;
+        }
+
+        if inventory.shopping_list_mut().insert(entry).is_some() {
+            bail!("Cannot have two shopping list entries with the same uuid");
+        }
+    }
+
+    for (_, entry) in automatic {
+        if inventory.shopping_list_mut().insert(entry).is_some() {
+            bail!("Cannot have two shopping list entries with the same uuid");
+        }
+    }
+
+    // Push the recipes into the inventory (checked)
+    for recipe in recipes {
+        if recipe.inventory_uuid() != inventory.uuid() {
+            bail!("The recipe is not part of the inventory");
+        }
+
+        if inventory.recipes_mut().insert(recipe).is_some() {
+            bail!("Cannot have two recipes with the same uuid");
+        }
+    }
+
+    // Push the rules and the receipts into the inventory (checked)
+    for rule in rules {
+        if rule.inventory_uuid() != inventory.uuid() {
+            bail!("The rule is not part of the inventory");
+        }
+
+        if inventory.rules_mut().insert(rule).is_some() {
+            bail!("Cannot have two rules with the same uuid");
+        }
+    }
+
+    for receipt in notification_receipts {
+        if receipt.inventory_uuid() != inventory.uuid() {
+            bail!("The notification receipt is not part of the inventory");
+        }
+
+        if inventory
+            .notification_receipts_mut()
+            .insert(receipt)
+            .is_some()
+        {
+            bail!("Cannot have two notification receipts with the same uuid");
+        }
+    }
+
+    Ok(inventory)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::RuleKind;
+    use rand::{rngs::StdRng, seq::IteratorRandom, Rng, SeedableRng};
+
+    /// Makes an inventory with an item and a unit of it
+    fn pantry() -> (InventoryHandle<'static>, Arc<Item>, Uuid) {
+        let mut handle = InventoryHandle::new("Pantry".to_string(), Uuid::new_v4());
+        let item = Item::new(
+            &Arc::new(handle.inventory.clone()),
+            "Rice".to_string(),
+            None,
+        );
+        handle.create_item(item.clone()).unwrap();
+
+        let item = Arc::new(item);
+        let unit = Unit::new(&item, None, "1 kg bag".to_string(), 100.0);
+        let unit_uuid = *unit.uuid();
+        handle.create_unit(unit).unwrap();
+
+        (handle, item, unit_uuid)
+    }
+
+    #[test]
+    fn copies_only_report_their_changes_once_committed() {
+        let (mut handle, item, _) = pantry();
+        let (_, changes) = handle.subscribe_channel();
+
+        let mut copy = handle.clone();
+        let (_, copied) = copy.subscribe_channel();
+        copy.delete_item((*item).clone()).unwrap();
+        assert!(changes.try_iter().next().is_none());
+
+        handle.commit(copy, &copied.try_iter().collect::<Vec<_>>());
+        assert!(handle.item(item.uuid()).is_none());
+        assert!(changes.try_iter().any(|c| c.kind
+            == ChangeKind::ItemRemoved {
+                item_uuid: *item.uuid()
+            }));
+    }
+
+    #[test]
+    fn lists_items_only_while_they_run_low() {
+        let (mut handle, item, unit_uuid) = pantry();
+        handle
+            .set_min_stock(item.uuid(), Some(MinStock::Units(1)))
+            .unwrap();
+        assert!(handle.shopping_list().is_empty());
+
+        handle.consume_unit(&unit_uuid, 100.0).unwrap();
+        assert_eq!(handle.shopping_list().len(), 1);
+        assert!(handle
+            .shopping_list()
+            .iter()
+            .all(ShoppingEntry::is_automatic));
+
+        let manual =
+            ShoppingEntry::new(*handle.uuid(), Some(*item.uuid()), "Rice".to_string(), None);
+        handle.create_shopping_entry(manual).unwrap();
+
+        // Buying a unit takes the automatic entry off the list again (but not the manual one)
+        let unit = Unit::new(&item, None, "1 kg bag".to_string(), 100.0);
+        handle.create_unit(unit).unwrap();
+        assert_eq!(handle.shopping_list().len(), 1);
+        assert!(!handle
+            .shopping_list()
+            .iter()
+            .any(ShoppingEntry::is_automatic));
+        handle.verify_projection().unwrap();
+    }
+
+    #[test]
+    fn lists_one_automatic_entry_per_item_after_syncing() {
+        let (handle, item, _) = pantry();
+        let (mut phone, mut laptop) = (handle.clone(), handle);
+        phone.unsaved.clear();
+        laptop.unsaved.clear();
+
+        // Both devices notice the item running low before they sync
+        let first = ShoppingEntry::automatic(&item);
+        phone.create_shopping_entry(first.clone()).unwrap();
+        let second = ShoppingEntry::automatic(&item);
+        laptop.create_shopping_entry(second.clone()).unwrap();
+
+        let from_phone = phone.unsaved.clone();
+        let from_laptop = laptop.unsaved.clone();
+        from_laptop
+            .into_iter()
+            .for_each(|e| phone.merge(e).unwrap());
+        from_phone
+            .into_iter()
+            .for_each(|e| laptop.merge(e).unwrap());
+
+        let listed = if listing_order(&first, &second) == Ordering::Less {
+            first
+        } else {
+            second
+        };
+        for handle in &[&phone, &laptop] {
+            assert_eq!(handle.shopping_list().len(), 1);
+            assert!(handle.shopping_list().get(listed.uuid()).is_some());
+            handle.verify_projection().unwrap();
+        }
+
+        // Restocking takes both entries off the list
+        let unit = Unit::new(&item, None, "1 kg bag".to_string(), 100.0);
+        phone.create_unit(unit).unwrap();
+        assert!(phone.shopping_list().is_empty());
+        phone.verify_projection().unwrap();
+    }
+
+    #[test]
+    fn warns_about_barcodes_of_other_items() {
+        let (mut handle, _, _) = pantry();
+        let inventory = Arc::new(handle.inventory.clone());
+        let ean = Ean::parse("4006381333931").unwrap();
+
+        let pen = Item::new(&inventory, "Pen".to_string(), Some(ean.clone()));
+        let pen_uuid = *pen.uuid();
+        assert!(handle.create_item(pen).unwrap().is_empty());
+
+        let copy = Item::new(&inventory, "Pen (copy)".to_string(), Some(ean));
+        assert_eq!(handle.create_item(copy).unwrap(), vec![pen_uuid]);
+        assert_eq!(handle.duplicate_eans().len(), 1);
+    }
+
+    #[test]
+    fn members_only_change_their_own_rules() {
+        let (mut handle, _, _) = pantry();
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        handle.set_role(alice, Some(Role::Read)).unwrap();
+        handle.set_role(bob, Some(Role::Read)).unwrap();
+
+        let rule = Rule::new(*handle.uuid(), alice, RuleKind::BeforeExpiry { days: 2 });
+        handle.create_rule(rule.clone()).unwrap();
+
+        // Bob claims Alice's rule by putting himself into the payload
+        let mut claimed = serde_json::to_value(&rule).unwrap();
+        claimed["user_uuid"] = serde_json::json!(bob);
+        let claimed: Rule = serde_json::from_value(claimed).unwrap();
+
+        let update = Event::update(Cow::Owned(ProjectionEntry::Rule(claimed.clone())));
+        assert!(handle.check_permission(&bob, &update).is_err());
+        let delete = Event::delete(Cow::Owned(ProjectionEntry::Rule(claimed)));
+        assert!(handle.check_permission(&bob, &delete).is_err());
+
+        let delete = Event::delete(Cow::Owned(ProjectionEntry::Rule(rule)));
+        handle.check_permission(&alice, &delete).unwrap();
+    }
+
+    #[test]
+    fn prunes_receipts_which_cannot_matter_anymore() {
+        let (mut handle, item, _) = pantry();
+        let owner = *handle.owner();
+        let rule = Rule::new(
+            *handle.uuid(),
+            owner,
+            RuleKind::LowStock {
+                item_uuid: *item.uuid(),
+                min_stock: MinStock::Units(2),
+            },
+        );
+        handle.create_rule(rule.clone()).unwrap();
+
+        let notification = handle.due_notifications(Utc::now()).remove(0);
+        handle.mark_sent(&notification, Utc::now()).unwrap();
+        handle.mark_sent(&notification, Utc::now()).unwrap();
+        assert_eq!(handle.notification_receipts().len(), 1);
+
+        handle.delete_rule(rule).unwrap();
+        handle.prune_notification_receipts().unwrap();
+        assert!(handle.notification_receipts().is_empty());
+        handle.verify_projection().unwrap();
+    }
+
+    #[test]
+    fn incremental_projection_matches_rebuild() {
+        for seed in 0..32 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let owner = Uuid::new_v4();
+            let mut handle = InventoryHandle::new("Pantry".to_string(), owner);
+
+            for step in 0..200 {
+                let inventory = Arc::new(handle.inventory.clone());
+                let item = handle.items().iter().cloned().choose(&mut rng);
+                let unit = handle
+                    .items()
+                    .iter()
+                    .flat_map(|i| i.units())
+                    .cloned()
+                    .choose(&mut rng);
+                let entry = handle.shopping_list().iter().cloned().choose(&mut rng);
+                let rule = handle.rules().iter().cloned().choose(&mut rng);
+                let delete: bool = rng.gen();
+
+                // Some of the changes are invalid, these have to fail without touching the projection
+                let _ = match rng.gen_range(0, 10) {
+                    0 | 1 => handle
+                        .create_item(Item::new(&inventory, format!("Item {}", step), None))
+                        .map(|_| ()),
+                    2 => item.map_or(Ok(()), |item| handle.delete_item(item)),
+                    3 | 4 => item.map_or(Ok(()), |item| {
+                        let unit = Unit::new(&Arc::new(item), None, "Unit".to_string(), 100.0);
+                        handle.create_unit(unit)
+                    }),
+                    5 => unit.map_or(Ok(()), |unit| {
+                        handle.consume_unit(unit.uuid(), rng.gen_range(0.0, 150.0))
+                    }),
+                    6 => unit.map_or(Ok(()), |unit| handle.delete_unit(unit)),
+                    7 => item.map_or(Ok(()), |item| {
+                        let min_stock = MinStock::Units(rng.gen_range(0, 3));
+                        handle.set_min_stock(item.uuid(), Some(min_stock))
+                    }),
+                    8 => match entry {
+                        // The second delete of an entry is invalid
+                        Some(entry) if delete => handle
+                            .delete_shopping_entry(entry.clone())
+                            .and_then(|_| handle.delete_shopping_entry(entry)),
+                        _ => handle.create_shopping_entry(ShoppingEntry::new(
+                            *handle.uuid(),
+                            None,
+                            format!("Entry {}", step),
+                            None,
+                        )),
+                    },
+                    _ => match rule {
+                        Some(rule) if delete => handle.delete_rule(rule),
+                        _ => {
+                            let kind = RuleKind::BeforeExpiry {
+                                days: rng.gen_range(0, 5),
+                            };
+                            handle.create_rule(Rule::new(*handle.uuid(), owner, kind))
+                        }
+                    },
+                };
+
+                handle
+                    .verify_projection()
+                    .unwrap_or_else(|e| panic!("Seed {}, step {}: {}", seed, step, e));
+            }
+        }
+    }
+}
@@ -70,8 +70,8 @@ impl Unit {
         &self.uuid
     }
 
-    /// The UUID of the unit
-    pub(super) fn item_uuid(&self) -> &Uuid {
+    /// The UUID of the item which this unit belongs to
+    pub fn item_uuid(&self) -> &Uuid {
         &self.item_uuid
     }
 
@@ -95,6 +95,11 @@ impl Unit {
         &self.percent_left
     }
 
+    /// The percentage of how much of the unit is left
+    pub(super) fn percent_left_mut(&mut self) -> &mut f64 {
+        &mut self.percent_left
+    }
+
     /// The timestamp of the creation of the unit
     pub fn created_on(&self) -> &Timestamp {
         &self.created_on
@@ -104,6 +109,18 @@ impl Unit {
     pub fn opened_on(&self) -> &Option<Timestamp> {
         &self.opened_on
     }
+
+    /// The timestamp this unit was opened for the first time (if ever)
+    pub(crate) fn opened_on_mut(&mut self) -> &mut Option<Timestamp> {
+        &mut self.opened_on
+    }
+
+    /// The timestamp after which the unit should be used up (if it was opened and has a time limit)
+    pub fn expires_on(&self) -> Option<Timestamp> {
+        let use_up_after = chrono::Duration::from_std(self.use_up_after?).ok()?;
+
+        self.opened_on?.checked_add_signed(use_up_after)
+    }
 }
 
 // #[derive(Deserialize, Serialize, Clone)]
@@ -0,0 +1,344 @@
+use crate::{
+    events::{by_expiry, store::InventoryHandle, Item, MinStock},
+    reports::Period,
+    Timestamp,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// How far back the history is used to estimate consumption rates (in days)
+const HISTORY_DAYS: i64 = 90;
+
+/// The predicted future of the stock of one item
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ItemForecast {
+    /// The item the forecast is about
+    pub item_uuid: Uuid,
+
+    /// The current stock (in units, so half of a unit is 0.5)
+    pub stock: f64,
+
+    /// The average consumption in units per day (if anything was consumed recently)
+    pub units_per_day: Option<f64>,
+
+    /// When the stock will be used up at the current rate (if it is consumed at all, and not too slowly to tell)
+    pub runs_out_on: Option<Timestamp>,
+
+    /// When the item should be bought again, which is when the stock falls below
+    /// its minimum (or runs out, if the item has no minimum stock), none if that is too far away to tell
+    pub reorder_on: Option<Timestamp>,
+
+    /// The units which will expire before they can be used up at the current rate
+    pub expiring_unused: Vec<ExpiringUnused>,
+}
+
+impl ItemForecast {
+    /// If there is more stock than can be used within its shelf life
+    pub fn is_overstocked(&self) -> bool {
+        !self.expiring_unused.is_empty()
+    }
+}
+
+/// A unit which is predicted to expire before it is used up
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ExpiringUnused {
+    /// The unit which will expire
+    pub unit_uuid: Uuid,
+
+    /// When the unit will expire (unopened units are expected to be opened once the ones before them are used up)
+    pub expires_on: Timestamp,
+
+    /// How much of the unit will be left when it expires (in percent)
+    pub percent_wasted: f64,
+}
+
+impl<'a> InventoryHandle<'a> {
+    /// Predicts the stock of every item, based on how fast it was consumed recently
+    pub fn forecast(&self, now: Timestamp) -> Vec<ItemForecast> {
+        let rates = self.consumption_rates(now);
+
+        self.items()
+            .iter()
+            .map(|item| forecast_item(item, rates.get(item.uuid()).copied(), now))
+            .collect()
+    }
+
+    /// The average consumption per item in units per day (over the recent history)
+    fn consumption_rates(&self, now: Timestamp) -> HashMap<Uuid, f64> {
+        let history_start = (now - chrono::Duration::days(HISTORY_DAYS))
+            .naive_utc()
+            .date();
+
+        let mut consumed = HashMap::new();
+        let mut first_seen = HashMap::new();
+
+        for row in self.consumption_report(Period::Day).rows {
+            if row.period_start < history_start {
+                continue;
+            }
+
+            *consumed.entry(row.item_uuid).or_insert(0.0) += row.consumed;
+            first_seen.entry(row.item_uuid).or_insert(row.period_start);
+        }
+
+        consumed
+            .into_iter()
+            .filter(|(_, consumed)| *consumed > 0.0)
+            .map(|(item_uuid, consumed)| {
+                // Every day since the item was first seen counts (including the ones without consumption)
+                let days = (now.naive_utc().date() - first_seen[&item_uuid]).num_days() + 1;
+
+                (item_uuid, consumed / days.max(1) as f64)
+            })
+            .collect()
+    }
+}
+
+/// Simulates using up the units of an item (closest to expiry first) at a constant rate
+fn forecast_item(item: &Item, units_per_day: Option<f64>, now: Timestamp) -> ItemForecast {
+    let mut units: Vec<_> = item
+        .units()
+        .iter()
+        .filter(|u| *u.percent_left() > 0.0)
+        .collect();
+    units.sort_by(|a, b| by_expiry(a, b));
+
+    let stock: f64 = units.iter().map(|u| u.percent_left() / 100.0).sum();
+
+    let mut forecast = ItemForecast {
+        item_uuid: *item.uuid(),
+        stock,
+        units_per_day,
+        runs_out_on: None,
+        reorder_on: None,
+        expiring_unused: vec![],
+    };
+
+    let rate = match units_per_day {
+        Some(rate) => rate,
+        None => return forecast,
+    };
+    // The time in some days from now (none if it is too far away to be represented, e.g. for a tiny rate)
+    let at = |days: f64| {
+        let seconds = days * 24.0 * 60.0 * 60.0;
+
+        // A `chrono::Duration` counts milliseconds in an `i64`
+        if seconds.is_nan() || seconds.abs() >= (i64::MAX / 1000) as f64 {
+            return None;
+        }
+
+        now.checked_add_signed(chrono::Duration::seconds(seconds as i64))
+    };
+
+    // The time (in days from now) at which the current unit is started and the remaining stock
+    let mut time = 0.0;
+    let mut remaining = stock;
+
+    // When each unit is used up (or thrown away)
+    let mut finished = vec![];
+
+    for unit in units {
+        let left = unit.percent_left() / 100.0;
+
+        // Unopened units get opened once the units before them are used up
+        let expires_on = unit.expires_on().or_else(|| {
+            let use_up_after = chrono::Duration::from_std((*unit.use_up_after())?).ok()?;
+            at(time)?.checked_add_signed(use_up_after)
+        });
+        let used_up = time + left / rate;
+
+        match expires_on {
+            Some(expires_on) if at(used_up).map_or(true, |used_up| expires_on < used_up) => {
+                let expiry = (expires_on - now).num_seconds() as f64 / (24.0 * 60.0 * 60.0);
+                let used = ((expiry - time) * rate).max(0.0).min(left);
+
+                forecast.expiring_unused.push(ExpiringUnused {
+                    unit_uuid: *unit.uuid(),
+                    expires_on,
+                    percent_wasted: (left - used) * 100.0,
+                });
+
+                time += used / rate;
+            }
+            _ => time = used_up,
+        }
+
+        remaining -= left;
+        finished.push((time, remaining));
+    }
+
+    forecast.runs_out_on = at(time);
+
+    // Buy again once the minimum isn't satisfied anymore
+    let reorder_after = match item.min_stock() {
+        Some(MinStock::Units(min)) => {
+            let min = *min as usize;
+
+            if finished.len() < min {
+                Some(0.0)
+            } else {
+                finished.get(finished.len() - min).map(|(time, _)| *time)
+            }
+        }
+        Some(MinStock::PercentLeft(min)) => {
+            if stock * 100.0 < *min {
+                Some(0.0)
+            } else {
+                finished
+                    .iter()
+                    .find(|(_, remaining)| remaining * 100.0 < *min)
+                    .map(|(time, _)| *time)
+            }
+        }
+        None => Some(time),
+    };
+
+    forecast.reorder_on = reorder_after.and_then(at);
+
+    forecast
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Unit;
+    use chrono::{Duration, Utc};
+    use std::sync::Arc;
+
+    /// Makes an inventory with an item of sealed units (which last some days once opened)
+    fn shelf(units: usize, use_up_after_days: Option<u64>) -> (InventoryHandle<'static>, Uuid) {
+        let mut handle = InventoryHandle::new("Pantry".to_string(), Uuid::new_v4());
+        let item = Item::new(&Arc::new((*handle).clone()), "Milk".to_string(), None);
+        let item_uuid = *item.uuid();
+        handle.create_item(item.clone()).unwrap();
+
+        let item = Arc::new(item);
+        let use_up_after =
+            use_up_after_days.map(|days| std::time::Duration::from_secs(days * 24 * 60 * 60));
+        for _ in 0..units {
+            let unit = Unit::new(&item, use_up_after, "1 l".to_string(), 100.0);
+            handle.create_unit(unit).unwrap();
+        }
+
+        (handle, item_uuid)
+    }
+
+    #[test]
+    fn runs_out_at_the_rate_of_consumption() {
+        let (mut handle, item_uuid) = shelf(4, None);
+
+        // Two units are used up today
+        let units: Vec<Uuid> = handle
+            .item(&item_uuid)
+            .unwrap()
+            .units()
+            .iter()
+            .map(|u| *u.uuid())
+            .collect();
+        for unit_uuid in &units[..2] {
+            handle.consume_unit(unit_uuid, 100.0).unwrap();
+        }
+
+        let now = Utc::now();
+        let forecast = handle.forecast(now);
+        assert_eq!(forecast.len(), 1);
+        assert_eq!(forecast[0].item_uuid, item_uuid);
+        assert_eq!(forecast[0].stock, 2.0);
+        assert_eq!(forecast[0].units_per_day, Some(2.0));
+        assert_eq!(forecast[0].runs_out_on, Some(now + Duration::days(1)));
+        assert_eq!(forecast[0].reorder_on, forecast[0].runs_out_on);
+        assert!(!forecast[0].is_overstocked());
+    }
+
+    #[test]
+    fn predicts_nothing_without_consumption() {
+        let (handle, _) = shelf(2, Some(3));
+        let forecast = handle.forecast(Utc::now());
+
+        assert_eq!(forecast[0].stock, 2.0);
+        assert_eq!(forecast[0].units_per_day, None);
+        assert_eq!(forecast[0].runs_out_on, None);
+        assert_eq!(forecast[0].reorder_on, None);
+        assert!(!forecast[0].is_overstocked());
+    }
+
+    #[test]
+    fn reorders_when_the_minimum_stock_is_reached() {
+        let (mut handle, item_uuid) = shelf(4, None);
+        let now = Utc::now();
+        let reorder_on = |handle: &InventoryHandle<'_>| {
+            forecast_item(handle.item(&item_uuid).unwrap(), Some(1.0), now).reorder_on
+        };
+
+        // One unit is used up per day, so fewer than two are left after three days
+        handle
+            .set_min_stock(&item_uuid, Some(MinStock::Units(2)))
+            .unwrap();
+        assert_eq!(reorder_on(&handle), Some(now + Duration::days(3)));
+
+        handle
+            .set_min_stock(&item_uuid, Some(MinStock::Units(5)))
+            .unwrap();
+        assert_eq!(reorder_on(&handle), Some(now));
+
+        handle
+            .set_min_stock(&item_uuid, Some(MinStock::PercentLeft(150.0)))
+            .unwrap();
+        assert_eq!(reorder_on(&handle), Some(now + Duration::days(3)));
+
+        handle
+            .set_min_stock(&item_uuid, Some(MinStock::PercentLeft(500.0)))
+            .unwrap();
+        assert_eq!(reorder_on(&handle), Some(now));
+
+        // Without a minimum, the item is bought again once it runs out
+        handle.set_min_stock(&item_uuid, None).unwrap();
+        assert_eq!(reorder_on(&handle), Some(now + Duration::days(4)));
+    }
+
+    #[test]
+    fn finds_units_which_expire_unused() {
+        let (handle, item_uuid) = shelf(3, Some(2));
+        let item = handle.item(&item_uuid).unwrap();
+        let now = Utc::now();
+
+        // A unit a day is used up long before it expires
+        let forecast = forecast_item(item, Some(1.0), now);
+        assert!(!forecast.is_overstocked());
+        assert_eq!(forecast.runs_out_on, Some(now + Duration::days(3)));
+
+        // A quarter of a unit a day only uses up half of each unit within two days
+        let forecast = forecast_item(item, Some(0.25), now);
+        assert!(forecast.is_overstocked());
+        assert_eq!(forecast.expiring_unused.len(), 3);
+        assert_eq!(
+            forecast.expiring_unused[0].expires_on,
+            now + Duration::days(2)
+        );
+        assert!(forecast
+            .expiring_unused
+            .iter()
+            .all(|e| (e.percent_wasted - 50.0).abs() < 1e-9));
+        assert_eq!(forecast.runs_out_on, Some(now + Duration::days(6)));
+    }
+
+    #[test]
+    fn leaves_out_dates_beyond_the_range_of_timestamps() {
+        let mut handle = InventoryHandle::new("Pantry".to_string(), Uuid::new_v4());
+        let item = Item::new(&Arc::new((*handle).clone()), "Salt".to_string(), None);
+        let item_uuid = *item.uuid();
+        handle.create_item(item.clone()).unwrap();
+
+        let unit = Unit::new(&Arc::new(item), None, "1 kg bag".to_string(), 100.0);
+        handle.create_unit(unit).unwrap();
+
+        let item = handle.item(&item_uuid).unwrap();
+        let forecast = forecast_item(item, Some(1e-30), Utc::now());
+        assert_eq!(forecast.runs_out_on, None);
+        assert_eq!(forecast.reorder_on, None);
+
+        let forecast = forecast_item(item, Some(1.0), Utc::now());
+        assert!(forecast.runs_out_on.is_some());
+    }
+}
@@ -8,8 +8,23 @@
 
 mod tree;
 
+pub mod calendar;
 pub mod core;
+#[cfg(feature = "crypto")]
+pub mod crypto;
 pub mod events;
+pub mod forecast;
+#[cfg(feature = "products")]
+pub mod products;
+pub mod query;
+pub mod reports;
+pub mod search;
+#[cfg(feature = "signatures")]
+pub mod signatures;
+pub mod storage;
+pub mod transfer;
+#[cfg(feature = "wire")]
+pub mod wire;
 
 pub use libocc::events::{Timestamp, Utc};
 
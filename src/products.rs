@@ -0,0 +1,435 @@
+use crate::events::{store::ProjectionEvent, Ean, Inventory, Item};
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{
+    collections::BTreeMap,
+    convert::{TryFrom, TryInto},
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+/// The magic bytes at the start of an index file
+const MAGIC: &[u8; 8] = b"SFIPROD1";
+
+/// The length of an entry of the lookup table (the canonical EAN and the offset of the record)
+const ENTRY_LEN: usize = 14 + 8;
+
+/// The typical shelf life after opening (in days) for some Open Food Facts category tags
+///
+/// Products are tagged with their parent categories too, so the more specific categories come
+/// first: the first entry matching any of the tags of a product wins.
+const SHELF_LIVES: &[(&str, u64)] = &[
+    ("en:fishes", 2),
+    ("en:meats", 3),
+    ("en:poultries", 3),
+    ("en:breads", 4),
+    ("en:yogurts", 5),
+    ("en:milks", 5),
+    ("en:creams", 5),
+    ("en:dairies", 7),
+    ("en:fruit-juices", 7),
+    ("en:juices", 7),
+    ("en:fruits", 7),
+    ("en:vegetables", 7),
+    ("en:cheeses", 21),
+    ("en:sauces", 30),
+    ("en:jams", 30),
+    ("en:spreads", 30),
+    ("en:condiments", 60),
+    ("en:coffees", 90),
+    ("en:breakfast-cereals", 90),
+    ("en:pastas", 180),
+    ("en:rices", 180),
+];
+
+/// The supported formats of Open Food Facts dumps
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DumpFormat {
+    /// The tab separated CSV export
+    Csv,
+
+    /// The JSONL export (one product per line)
+    Jsonl,
+}
+
+/// A suggestion for a new item, based on a product found in the database
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ItemTemplate {
+    /// The name of the product
+    pub name: String,
+
+    /// The barcode of the product
+    pub ean: Ean,
+
+    /// The most specific category of the product (if known)
+    pub category: Option<String>,
+
+    /// The typical duration after opening after which units of the product expire (if known)
+    pub use_up_after: Option<Duration>,
+}
+
+impl ItemTemplate {
+    /// Makes the event creating an item from this template
+    pub fn create_item<'a>(&self, inventory: &Arc<Inventory>) -> ProjectionEvent<'a> {
+        Item::create(inventory, self.name.clone(), Some(self.ean.clone()))
+    }
+}
+
+/// The record of a product as stored in the index
+#[derive(Deserialize, Serialize, Clone, Debug)]
+struct ProductRecord {
+    name: String,
+    category: Option<String>,
+    use_up_after_days: Option<u64>,
+}
+
+/// The result of an import
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImportSummary {
+    /// The number of products written to the index
+    pub imported: usize,
+
+    /// The number of products skipped (because of an invalid barcode, a missing name or a duplicate)
+    pub skipped: usize,
+
+    /// The number of lines (or rows) of the dump which couldn't be read and were skipped
+    pub malformed: usize,
+}
+
+/// Builds an index file from a downloaded Open Food Facts dump
+///
+/// Lines which can't be read are skipped and counted, so a few broken products don't spoil the import.
+pub fn import_dump(
+    dump: impl Read,
+    format: DumpFormat,
+    index_path: impl AsRef<Path>,
+) -> Result<ImportSummary> {
+    let mut products = BTreeMap::new();
+    let mut summary = ImportSummary::default();
+
+    // Returns if the product was added
+    let mut add = |code: &str, name: &str, tags: &[&str]| {
+        let ean = match Ean::parse(code) {
+            Ok(ean) if !name.trim().is_empty() && !products.contains_key(&ean) => ean,
+            _ => return false,
+        };
+
+        products.insert(
+            ean,
+            ProductRecord {
+                name: name.trim().to_string(),
+                category: tags.last().map(|t| t.trim_start_matches("en:").to_string()),
+                use_up_after_days: shelf_life(tags),
+            },
+        );
+
+        true
+    };
+
+    match format {
+        DumpFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new()
+                .delimiter(b'\t')
+                .quoting(false)
+                .flexible(true)
+                .from_reader(dump);
+
+            let headers = reader.headers()?.clone();
+            let column = |name: &str| {
+                headers
+                    .iter()
+                    .position(|h| h == name)
+                    .ok_or_else(|| anyhow!("The dump has no column {}", name))
+            };
+            let (code, name, tags) = (
+                column("code")?,
+                column("product_name")?,
+                column("categories_tags")?,
+            );
+
+            for record in reader.records() {
+                let record = match record {
+                    Ok(record) => record,
+                    Err(e) if e.is_io_error() => return Err(e.into()),
+                    Err(_) => {
+                        summary.malformed += 1;
+                        continue;
+                    }
+                };
+                let tags: Vec<_> = record
+                    .get(tags)
+                    .unwrap_or("")
+                    .split(',')
+                    .filter(|t| !t.is_empty())
+                    .collect();
+
+                if !add(
+                    record.get(code).unwrap_or(""),
+                    record.get(name).unwrap_or(""),
+                    &tags,
+                ) {
+                    summary.skipped += 1;
+                }
+            }
+        }
+        DumpFormat::Jsonl => {
+            #[derive(Deserialize)]
+            struct Product {
+                #[serde(default, deserialize_with = "null_as_default")]
+                code: String,
+                #[serde(default, deserialize_with = "null_as_default")]
+                product_name: String,
+                #[serde(default, deserialize_with = "null_as_default")]
+                categories_tags: Vec<String>,
+            }
+
+            for line in BufReader::new(dump).lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                let product: Product = match serde_json::from_str(&line) {
+                    Ok(product) => product,
+                    Err(_) => {
+                        summary.malformed += 1;
+                        continue;
+                    }
+                };
+                let tags: Vec<_> = product.categories_tags.iter().map(String::as_str).collect();
+
+                if !add(&product.code, &product.product_name, &tags) {
+                    summary.skipped += 1;
+                }
+            }
+        }
+    }
+
+    summary.imported = products.len();
+    write_index(&products, index_path)?;
+
+    Ok(summary)
+}
+
+/// Reads a missing value (`null` in the dump) as the default value
+fn null_as_default<'de, D, T>(deserializer: D) -> std::result::Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// Finds the typical shelf life for the category tags of a product
+fn shelf_life(tags: &[&str]) -> Option<u64> {
+    SHELF_LIVES
+        .iter()
+        .find(|(category, _)| tags.iter().any(|t| t.trim() == *category))
+        .map(|(_, days)| *days)
+}
+
+/// Writes the index file: the magic bytes, the number of products, the sorted lookup table and the records
+fn write_index(products: &BTreeMap<Ean, ProductRecord>, path: impl AsRef<Path>) -> Result<()> {
+    let mut records = vec![];
+    let mut table = Vec::with_capacity(products.len() * ENTRY_LEN);
+
+    for (ean, record) in products {
+        let payload = serde_json::to_vec(record)?;
+
+        table.extend_from_slice(ean.as_str().as_bytes());
+        table.extend_from_slice(&(records.len() as u64).to_le_bytes());
+
+        records.extend_from_slice(&u32::try_from(payload.len())?.to_le_bytes());
+        records.extend_from_slice(&payload);
+    }
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(MAGIC)?;
+    file.write_all(&u32::try_from(products.len())?.to_le_bytes())?;
+    file.write_all(&table)?;
+    file.write_all(&records)?;
+    file.flush()?;
+
+    Ok(())
+}
+
+/// A product database on disk, built by `import_dump`
+#[derive(Debug)]
+pub struct ProductIndex {
+    file: File,
+
+    /// The number of products in the index
+    len: u64,
+}
+
+impl ProductIndex {
+    /// Opens an index file
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let mut file = File::open(path)?;
+
+        let mut header = [0; 12];
+        file.read_exact(&mut header)?;
+
+        if &header[..8] != MAGIC {
+            bail!("The file is not a product index");
+        }
+
+        let len = u64::from(u32::from_le_bytes(header[8..].try_into()?));
+
+        Ok(Self { file, len })
+    }
+
+    /// The number of products in the index
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// If there are no products in the index
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Looks up a product by its barcode and suggests an item for it
+    pub fn lookup(&mut self, ean: &Ean) -> Result<Option<ItemTemplate>> {
+        // Binary search in the sorted lookup table
+        let (mut low, mut high) = (0, self.len);
+
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let (code, offset) = self.entry(middle)?;
+
+            match code.as_str().cmp(ean.as_str()) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => {
+                    let record = self.record(offset)?;
+
+                    return Ok(Some(ItemTemplate {
+                        name: record.name,
+                        ean: ean.clone(),
+                        category: record.category,
+                        use_up_after: record
+                            .use_up_after_days
+                            .map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+                    }));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Reads an entry of the lookup table
+    fn entry(&mut self, index: u64) -> Result<(String, u64)> {
+        let mut entry = [0; ENTRY_LEN];
+        self.file
+            .seek(SeekFrom::Start(12 + index * ENTRY_LEN as u64))?;
+        self.file.read_exact(&mut entry)?;
+
+        Ok((
+            String::from_utf8(entry[..14].to_vec())?,
+            u64::from_le_bytes(entry[14..].try_into()?),
+        ))
+    }
+
+    /// Reads a record from the data section
+    fn record(&mut self, offset: u64) -> Result<ProductRecord> {
+        let data_start = 12 + self.len * ENTRY_LEN as u64;
+        self.file.seek(SeekFrom::Start(data_start + offset))?;
+
+        let mut len = [0; 4];
+        self.file.read_exact(&mut len)?;
+
+        let mut payload = vec![0; u32::from_le_bytes(len) as usize];
+        self.file.read_exact(&mut payload)?;
+
+        Ok(serde_json::from_slice(&payload)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    /// Imports a fixture dump into a temporary index and opens it
+    fn import(dump: &str, format: DumpFormat) -> (ImportSummary, ProductIndex) {
+        let path = std::env::temp_dir().join(format!("sfi-products-{}.idx", Uuid::new_v4()));
+        let summary = import_dump(dump.as_bytes(), format, &path).unwrap();
+        let index = ProductIndex::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        (summary, index)
+    }
+
+    #[test]
+    fn imports_jsonl_dumps() {
+        let (summary, mut index) = import(
+            include_str!("../tests/fixtures/products.jsonl"),
+            DumpFormat::Jsonl,
+        );
+
+        assert_eq!(
+            summary,
+            ImportSummary {
+                imported: 2,
+                skipped: 2,
+                malformed: 1,
+            }
+        );
+
+        let milk = index
+            .lookup(&Ean::parse("4006381333931").unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(milk.name, "Whole milk");
+        assert_eq!(milk.category, Some("milks".to_string()));
+        assert_eq!(
+            milk.use_up_after,
+            Some(Duration::from_secs(5 * 24 * 60 * 60))
+        );
+
+        // A product without categories (`null` in the dump)
+        let rice = index
+            .lookup(&Ean::parse("5901234123457").unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(rice.category, None);
+        assert_eq!(rice.use_up_after, None);
+
+        assert!(index
+            .lookup(&Ean::parse("96385074").unwrap())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn matches_whole_category_tags() {
+        // The more specific category wins, no matter the order of the tags
+        assert_eq!(shelf_life(&["en:yogurts", "en:dairies"]), Some(5));
+        assert_eq!(shelf_life(&["en:dairies", "en:fermented-foods"]), Some(7));
+
+        // Only whole tags count
+        assert_eq!(shelf_life(&["en:plant-based-milks"]), None);
+        assert_eq!(shelf_life(&[]), None);
+    }
+
+    #[test]
+    fn imports_csv_dumps() {
+        let dump = "code\tproduct_name\tcategories_tags\n\
+                    4006381333931\tWhole milk\ten:dairies,en:milks\n\
+                    12345\tBroken barcode\t\n";
+        let (summary, mut index) = import(dump, DumpFormat::Csv);
+
+        assert_eq!(summary.imported, 1);
+        assert_eq!(summary.skipped, 1);
+        assert_eq!(index.len(), 1);
+        assert!(index
+            .lookup(&Ean::parse("4006381333931").unwrap())
+            .unwrap()
+            .is_some());
+    }
+}
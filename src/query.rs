@@ -0,0 +1,558 @@
+use crate::{
+    events::{
+        store::{InventoryHandle, Store},
+        Ean, Item, Unit,
+    },
+    Timestamp,
+};
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, time::Duration};
+use uuid::Uuid;
+
+/// A condition on a unit (and the item it belongs to)
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum Filter {
+    /// Every one of the filters matches (an empty list matches everything)
+    And(Vec<Filter>),
+
+    /// Any of the filters matches (an empty list matches nothing)
+    Or(Vec<Filter>),
+
+    /// The filter doesn't match
+    Not(Box<Filter>),
+
+    /// The name of the item or of the unit contains a text (ignoring case)
+    NameContains(String),
+
+    /// The item has a barcode
+    Ean(Ean),
+
+    /// The unit was opened (`true`) or not (`false`)
+    Opened(bool),
+
+    /// The percentage left of the unit is in a range (including both ends)
+    PercentLeft { min: f64, max: f64 },
+
+    /// The unit expires (or has expired) before a duration from now is over
+    ExpiresWithin(Duration),
+
+    /// The unit was created in a time span (including `from`, excluding `until`)
+    CreatedBetween { from: Timestamp, until: Timestamp },
+}
+
+impl Filter {
+    /// Checks if a unit of an item matches the filter
+    pub fn matches(&self, item: &Item, unit: &Unit, now: Timestamp) -> bool {
+        match self {
+            Filter::And(filters) => filters.iter().all(|f| f.matches(item, unit, now)),
+            Filter::Or(filters) => filters.iter().any(|f| f.matches(item, unit, now)),
+            Filter::Not(filter) => !filter.matches(item, unit, now),
+            Filter::NameContains(text) => {
+                let text = text.to_lowercase();
+
+                item.name().to_lowercase().contains(&text)
+                    || unit.name().to_lowercase().contains(&text)
+            }
+            Filter::Ean(ean) => item.ean().as_ref() == Some(ean),
+            Filter::Opened(opened) => unit.opened_on().is_some() == *opened,
+            Filter::PercentLeft { min, max } => (*min..=*max).contains(unit.percent_left()),
+            Filter::ExpiresWithin(duration) => match unit.expires_on() {
+                // A duration beyond the range of timestamps covers every unit which expires at all
+                Some(expires_on) => chrono::Duration::from_std(*duration)
+                    .ok()
+                    .and_then(|duration| now.checked_add_signed(duration))
+                    .map_or(true, |until| expires_on <= until),
+                None => false,
+            },
+            Filter::CreatedBetween { from, until } => {
+                from <= unit.created_on() && unit.created_on() < until
+            }
+        }
+    }
+}
+
+/// What to order the results of a query by
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum SortKey {
+    /// The name of the item, then the name of the unit
+    Name,
+
+    /// The expiry of the unit (units which don't expire always come last)
+    ExpiresOn,
+
+    /// The percentage left of the unit
+    PercentLeft,
+
+    /// The creation of the unit
+    CreatedOn,
+}
+
+/// One ordering of the results of a query
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct Sort {
+    pub key: SortKey,
+
+    /// Order from the largest to the smallest value
+    #[serde(default)]
+    pub descending: bool,
+}
+
+/// A search for units, with sorting and pagination
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Query {
+    /// The condition the units must match (all units match without one)
+    pub filter: Option<Filter>,
+
+    /// The orderings of the results (the later ones break ties of the earlier ones)
+    pub sort: Vec<Sort>,
+
+    /// The number of results to skip
+    pub offset: usize,
+
+    /// The maximum number of results to return (all of them without one)
+    pub limit: Option<usize>,
+}
+
+/// A unit which matched a query
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct QueryHit {
+    /// The inventory of the unit
+    pub inventory_uuid: Uuid,
+
+    /// The item of the unit
+    pub item_uuid: Uuid,
+
+    /// The name of the item
+    pub item_name: String,
+
+    /// The barcode of the item (if it has one)
+    pub ean: Option<Ean>,
+
+    /// The matching unit
+    pub unit: Unit,
+}
+
+/// One page of the results of a query
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct QueryPage {
+    /// The number of matching units (on all pages)
+    pub total: usize,
+
+    /// The matching units on this page
+    pub hits: Vec<QueryHit>,
+}
+
+/// A matching unit, before the page is cut out
+struct Candidate<'h> {
+    inventory_uuid: &'h Uuid,
+    item: &'h Item,
+    unit: &'h Unit,
+}
+
+impl Query {
+    /// Evaluates the query on the units of some inventories
+    fn evaluate<'h, 'a: 'h>(
+        &self,
+        handles: impl Iterator<Item = &'h InventoryHandle<'a>>,
+        now: Timestamp,
+    ) -> QueryPage {
+        let mut candidates = vec![];
+
+        for handle in handles {
+            for item in handle.items() {
+                for unit in item.units() {
+                    if self
+                        .filter
+                        .as_ref()
+                        .map_or(true, |f| f.matches(item, unit, now))
+                    {
+                        candidates.push(Candidate {
+                            inventory_uuid: handle.uuid(),
+                            item,
+                            unit,
+                        });
+                    }
+                }
+            }
+        }
+
+        candidates.sort_by(|a, b| self.compare(a, b));
+
+        QueryPage {
+            total: candidates.len(),
+            hits: candidates
+                .into_iter()
+                .skip(self.offset)
+                .take(self.limit.unwrap_or(usize::MAX))
+                .map(|c| QueryHit {
+                    inventory_uuid: *c.inventory_uuid,
+                    item_uuid: *c.item.uuid(),
+                    item_name: c.item.name().clone(),
+                    ean: c.item.ean().clone(),
+                    unit: c.unit.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Orders two matching units by the sort keys (and by their UUIDs, so pages are stable)
+    fn compare(&self, a: &Candidate<'_>, b: &Candidate<'_>) -> Ordering {
+        self.sort
+            .iter()
+            .map(|sort| {
+                let ordering = match sort.key {
+                    SortKey::Name => a
+                        .item
+                        .name()
+                        .to_lowercase()
+                        .cmp(&b.item.name().to_lowercase())
+                        .then_with(|| a.unit.name().cmp(b.unit.name())),
+                    SortKey::ExpiresOn => match (a.unit.expires_on(), b.unit.expires_on()) {
+                        (Some(a), Some(b)) => a.cmp(&b),
+                        // Units which don't expire come last in both directions
+                        (Some(_), None) => return Ordering::Less,
+                        (None, Some(_)) => return Ordering::Greater,
+                        (None, None) => Ordering::Equal,
+                    },
+                    SortKey::PercentLeft => a
+                        .unit
+                        .percent_left()
+                        .partial_cmp(b.unit.percent_left())
+                        .unwrap_or(Ordering::Equal),
+                    SortKey::CreatedOn => a.unit.created_on().cmp(b.unit.created_on()),
+                };
+
+                if sort.descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            })
+            .find(|ordering| *ordering != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| a.inventory_uuid.cmp(b.inventory_uuid))
+            .then_with(|| a.unit.uuid().cmp(b.unit.uuid()))
+    }
+}
+
+impl<'a> InventoryHandle<'a> {
+    /// Finds the units of the inventory which match a query
+    pub fn query(&self, query: &Query, now: Timestamp) -> QueryPage {
+        query.evaluate(std::iter::once(self), now)
+    }
+}
+
+impl<'a> Store<'a> {
+    /// Finds the units of all inventories which match a query
+    pub fn query(&self, query: &Query, now: Timestamp) -> QueryPage {
+        query.evaluate(self.iter(), now)
+    }
+
+    /// Finds the units which match a query in the inventories a user is allowed to read
+    pub fn query_as(&self, user_uuid: &Uuid, query: &Query, now: Timestamp) -> QueryPage {
+        query.evaluate(self.iter().filter(|h| h.allow_read(user_uuid)), now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::store::Role;
+    use chrono::Utc;
+    use std::sync::Arc;
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    /// Adds an item with units (given as name, percentage left, use-up duration in days and the
+    /// days since opening) to an inventory
+    fn add_item(
+        handle: &mut InventoryHandle<'_>,
+        name: &str,
+        ean: Option<&str>,
+        units: &[(&str, f64, Option<u64>, Option<i64>)],
+    ) {
+        let ean = ean.map(|ean| Ean::parse(ean).unwrap());
+        let item = Item::new(&Arc::new((**handle).clone()), name.to_string(), ean);
+        handle.create_item(item.clone()).unwrap();
+        let item = Arc::new(item);
+
+        for (unit_name, percent_left, use_up_after, opened_days_ago) in units {
+            let use_up_after = use_up_after.map(|days| Duration::from_secs(days * DAY));
+            let mut unit = Unit::new(&item, use_up_after, unit_name.to_string(), *percent_left);
+            *unit.opened_on_mut() =
+                opened_days_ago.map(|days| Utc::now() - chrono::Duration::days(days));
+
+            handle.create_unit(unit).unwrap();
+        }
+    }
+
+    /// Makes an inventory with four units
+    fn pantry(owner: Uuid) -> InventoryHandle<'static> {
+        let mut handle = InventoryHandle::new("Pantry".to_string(), owner);
+        add_item(
+            &mut handle,
+            "Oat milk",
+            Some("4006381333931"),
+            &[
+                ("1 l carton", 60.0, Some(5), Some(2)),
+                ("1 l carton", 100.0, Some(5), None),
+            ],
+        );
+        add_item(
+            &mut handle,
+            "Rice",
+            None,
+            &[("1 kg bag", 30.0, Some(365), Some(10))],
+        );
+        add_item(&mut handle, "salt", None, &[("500 g", 100.0, None, None)]);
+
+        handle
+    }
+
+    fn query(filter: Filter) -> Query {
+        Query {
+            filter: Some(filter),
+            ..Query::default()
+        }
+    }
+
+    /// The item names and percentages left of the hits of a query
+    fn hits(handle: &InventoryHandle<'_>, query: &Query) -> Vec<(String, f64)> {
+        handle
+            .query(query, Utc::now())
+            .hits
+            .into_iter()
+            .map(|hit| (hit.item_name, *hit.unit.percent_left()))
+            .collect()
+    }
+
+    fn count(handle: &InventoryHandle<'_>, filter: Filter) -> usize {
+        handle.query(&query(filter), Utc::now()).total
+    }
+
+    #[test]
+    fn filters_units() {
+        let start = Utc::now();
+        let handle = pantry(Uuid::new_v4());
+        let end = Utc::now() + chrono::Duration::milliseconds(1);
+
+        assert_eq!(count(&handle, Filter::NameContains("MILK".to_string())), 2);
+        assert_eq!(count(&handle, Filter::NameContains("bag".to_string())), 1);
+        assert_eq!(count(&handle, Filter::NameContains("flour".to_string())), 0);
+
+        let ean = Ean::parse("4006381333931").unwrap();
+        assert_eq!(count(&handle, Filter::Ean(ean)), 2);
+        assert_eq!(
+            count(&handle, Filter::Ean(Ean::parse("96385074").unwrap())),
+            0
+        );
+
+        assert_eq!(count(&handle, Filter::Opened(true)), 2);
+        assert_eq!(count(&handle, Filter::Opened(false)), 2);
+
+        let percent_left = Filter::PercentLeft {
+            min: 30.0,
+            max: 60.0,
+        };
+        assert_eq!(count(&handle, percent_left), 2);
+
+        // The opened milk expires in three days, the rice in almost a year
+        let within = |days| Filter::ExpiresWithin(Duration::from_secs(days * DAY));
+        assert_eq!(count(&handle, within(1)), 0);
+        assert_eq!(count(&handle, within(4)), 1);
+        assert_eq!(count(&handle, within(400)), 2);
+
+        let created = |from, until| Filter::CreatedBetween { from, until };
+        assert_eq!(count(&handle, created(start, end)), 4);
+        assert_eq!(count(&handle, created(start, start)), 0);
+        assert_eq!(
+            count(&handle, created(end, end + chrono::Duration::days(1))),
+            0
+        );
+
+        let combined = Filter::And(vec![
+            Filter::Opened(false),
+            Filter::Not(Box::new(Filter::NameContains("salt".to_string()))),
+        ]);
+        assert_eq!(count(&handle, combined), 1);
+        assert_eq!(count(&handle, Filter::And(vec![])), 4);
+        assert_eq!(count(&handle, Filter::Or(vec![])), 0);
+    }
+
+    #[test]
+    fn sorts_and_pages_results() {
+        let handle = pantry(Uuid::new_v4());
+        let sort = |key, descending| Sort { key, descending };
+
+        let by_name = Query {
+            sort: vec![
+                sort(SortKey::Name, false),
+                sort(SortKey::PercentLeft, false),
+            ],
+            ..Query::default()
+        };
+        assert_eq!(
+            hits(&handle, &by_name),
+            vec![
+                ("Oat milk".to_string(), 60.0),
+                ("Oat milk".to_string(), 100.0),
+                ("Rice".to_string(), 30.0),
+                ("salt".to_string(), 100.0),
+            ]
+        );
+
+        // Units which don't expire come last, no matter the direction
+        for descending in &[false, true] {
+            let by_expiry = Query {
+                sort: vec![
+                    sort(SortKey::ExpiresOn, *descending),
+                    sort(SortKey::Name, false),
+                ],
+                ..Query::default()
+            };
+            let names: Vec<_> = hits(&handle, &by_expiry).into_iter().map(|h| h.0).collect();
+            let expiring = if *descending {
+                ["Rice", "Oat milk"]
+            } else {
+                ["Oat milk", "Rice"]
+            };
+            assert_eq!(names[..2], expiring);
+            assert_eq!(names[2..], ["Oat milk", "salt"]);
+        }
+
+        let page = Query {
+            sort: vec![sort(SortKey::PercentLeft, true), sort(SortKey::Name, false)],
+            offset: 1,
+            limit: Some(2),
+            ..Query::default()
+        };
+        let result = handle.query(&page, Utc::now());
+        assert_eq!(result.total, 4);
+        assert_eq!(
+            hits(&handle, &page),
+            vec![("salt".to_string(), 100.0), ("Oat milk".to_string(), 60.0)]
+        );
+
+        let past_the_end = Query {
+            offset: 10,
+            ..page.clone()
+        };
+        let result = handle.query(&past_the_end, Utc::now());
+        assert_eq!(result.total, 4);
+        assert!(result.hits.is_empty());
+
+        // Pages don't change between queries
+        let unsorted = Query {
+            limit: Some(2),
+            ..Query::default()
+        };
+        let first = handle.query(&unsorted, Utc::now());
+        let second = handle.query(&unsorted, Utc::now());
+        assert_eq!(
+            first
+                .hits
+                .iter()
+                .map(|h| *h.unit.uuid())
+                .collect::<Vec<_>>(),
+            second
+                .hits
+                .iter()
+                .map(|h| *h.unit.uuid())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn only_queries_readable_inventories() {
+        let (owner, reader, stranger) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let mut store = Store::new();
+
+        let mut shared = pantry(owner);
+        shared.set_role(reader, Some(Role::Read)).unwrap();
+        let shared_uuid = *shared.uuid();
+        store.add_inventory(shared).unwrap();
+        store.add_inventory(pantry(owner)).unwrap();
+
+        let all = Query::default();
+        assert_eq!(store.query(&all, Utc::now()).total, 8);
+        assert_eq!(store.query_as(&owner, &all, Utc::now()).total, 8);
+        assert_eq!(store.query_as(&stranger, &all, Utc::now()).total, 0);
+
+        let page = store.query_as(&reader, &all, Utc::now());
+        assert_eq!(page.total, 4);
+        assert!(page.hits.iter().all(|h| h.inventory_uuid == shared_uuid));
+    }
+
+    #[test]
+    fn round_trips_queries() {
+        let query = Query {
+            filter: Some(Filter::Or(vec![
+                Filter::And(vec![
+                    Filter::NameContains("milk".to_string()),
+                    Filter::Ean(Ean::parse("4006381333931").unwrap()),
+                ]),
+                Filter::Not(Box::new(Filter::Opened(true))),
+                Filter::PercentLeft {
+                    min: 10.0,
+                    max: 50.5,
+                },
+                Filter::ExpiresWithin(Duration::from_secs(3 * DAY)),
+                Filter::CreatedBetween {
+                    from: Utc::now() - chrono::Duration::days(7),
+                    until: Utc::now(),
+                },
+            ])),
+            sort: vec![Sort {
+                key: SortKey::ExpiresOn,
+                descending: true,
+            }],
+            offset: 20,
+            limit: Some(10),
+        };
+
+        let json = serde_json::to_string(&query).unwrap();
+        assert_eq!(serde_json::from_str::<Query>(&json).unwrap(), query);
+
+        // Everything can be left out
+        assert_eq!(
+            serde_json::from_str::<Query>("{}").unwrap(),
+            Query::default()
+        );
+        let sorted: Query = serde_json::from_str(r#"{ "sort": [{ "key": "Name" }] }"#).unwrap();
+        assert!(!sorted.sort[0].descending);
+    }
+
+    #[test]
+    fn huge_durations_match_every_expiring_unit() {
+        let handle = InventoryHandle::new("Pantry".to_string(), Uuid::new_v4());
+        let item = Arc::new(Item::new(
+            &Arc::new((*handle).clone()),
+            "Milk".to_string(),
+            None,
+        ));
+
+        let mut opened = Unit::new(
+            &item,
+            Some(Duration::from_secs(60)),
+            "1 l".to_string(),
+            100.0,
+        );
+        *opened.opened_on_mut() = Some(Utc::now());
+        let unopened = Unit::new(
+            &item,
+            Some(Duration::from_secs(60)),
+            "1 l".to_string(),
+            100.0,
+        );
+
+        for duration in &[
+            Duration::from_secs(u64::MAX),
+            Duration::from_secs(1_000_000_000_000_000),
+        ] {
+            let filter = Filter::ExpiresWithin(*duration);
+            assert!(filter.matches(&item, &opened, Utc::now()));
+            assert!(!filter.matches(&item, &unopened, Utc::now()));
+        }
+    }
+}
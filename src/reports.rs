@@ -0,0 +1,305 @@
+use crate::{
+    events::{
+        store::{InventoryHandle, ProjectionEntry, ProjectionEvent},
+        Unit,
+    },
+    Timestamp,
+};
+use chrono::{Datelike, NaiveDate};
+use libocc::events::{Projector, CRUD};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::Duration,
+};
+use uuid::Uuid;
+
+/// The length of the periods a report is split into
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum Period {
+    Day,
+
+    /// A week starting on Monday
+    Week,
+
+    Month,
+}
+
+impl Period {
+    /// The first day of the period containing a timestamp (in UTC)
+    pub fn start_of(self, timestamp: &Timestamp) -> NaiveDate {
+        let date = timestamp.naive_utc().date();
+
+        match self {
+            Period::Day => date,
+            Period::Week => {
+                date - chrono::Duration::days(i64::from(date.weekday().num_days_from_monday()))
+            }
+            Period::Month => NaiveDate::from_ymd(date.year(), date.month(), 1),
+        }
+    }
+}
+
+/// The figures of one item in one period
+///
+/// Amounts are counted in units, so half of a unit is 0.5.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ReportRow {
+    /// The item the figures are about
+    pub item_uuid: Uuid,
+
+    /// The name of the item (as of the end of the event log)
+    pub item_name: String,
+
+    /// The first day of the period
+    pub period_start: NaiveDate,
+
+    /// How many units were added
+    pub bought: f64,
+
+    /// How much was used
+    pub consumed: f64,
+
+    /// How much was thrown away after it expired
+    pub wasted: f64,
+
+    /// The average time from adding a unit to using it up (of the units used up in this period)
+    pub average_time_to_use: Option<Duration>,
+}
+
+impl ReportRow {
+    /// The share of what was gone in this period which was thrown away (if anything was gone)
+    pub fn waste_ratio(&self) -> Option<f64> {
+        let gone = self.consumed + self.wasted;
+
+        if gone > 0.0 {
+            Some(self.wasted / gone)
+        } else {
+            None
+        }
+    }
+}
+
+/// Consumption and waste statistics of an inventory, worked out from its event log
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ConsumptionReport {
+    /// The length of the periods of the report
+    pub period: Period,
+
+    /// The figures per period and item (sorted by period)
+    pub rows: Vec<ReportRow>,
+}
+
+/// The running totals of one item in one period
+#[derive(Default)]
+struct Totals {
+    bought: f64,
+    consumed: f64,
+    wasted: f64,
+    time_to_use: Duration,
+    used_up: u32,
+}
+
+impl ConsumptionReport {
+    /// Works out the report by replaying the event log of an inventory
+    ///
+    /// Lowering the `percent_left` of a unit counts as consumption. Deleting a unit which is
+    /// not used up counts as waste if the unit had expired by then, and as consumption otherwise.
+    /// The units deleted together with their item count as neither.
+    pub fn from_projector(projector: &Projector<'_, ProjectionEntry>, period: Period) -> Self {
+        let mut names = HashMap::new();
+        let mut units: HashMap<Uuid, Unit> = HashMap::new();
+        let mut totals: BTreeMap<(NaiveDate, Uuid), Totals> = BTreeMap::new();
+
+        let events = projector.get_events();
+        let cascaded = cascaded_units(events);
+
+        for event in events.iter() {
+            let timestamp = event.get_timestamp();
+            let start = period.start_of(timestamp);
+
+            match (event.get_operation(), event.get_data().as_ref()) {
+                (CRUD::Create, ProjectionEntry::Item(item))
+                | (CRUD::Update, ProjectionEntry::Item(item)) => {
+                    names.insert(*item.uuid(), item.name().clone());
+                }
+                (CRUD::Create, ProjectionEntry::Unit(unit)) => {
+                    names
+                        .entry(*unit.item_uuid())
+                        .or_insert_with(|| unit.name().clone());
+
+                    totals.entry((start, *unit.item_uuid())).or_default().bought += 1.0;
+
+                    units.insert(*unit.uuid(), unit.clone());
+                }
+                (CRUD::Update, ProjectionEntry::Unit(unit)) => {
+                    let previous = match units.insert(*unit.uuid(), unit.clone()) {
+                        Some(previous) => previous,
+                        None => continue,
+                    };
+
+                    let row = totals.entry((start, *unit.item_uuid())).or_default();
+                    let used = previous.percent_left() - unit.percent_left();
+
+                    if used > 0.0 {
+                        row.consumed += used / 100.0;
+                    }
+
+                    if *previous.percent_left() > 0.0 && *unit.percent_left() <= 0.0 {
+                        row.record_use(unit, timestamp);
+                    }
+                }
+                (CRUD::Delete, ProjectionEntry::Unit(unit)) => {
+                    // The deleted unit as it was last known
+                    let unit = units.remove(unit.uuid()).unwrap_or_else(|| unit.clone());
+                    let left = unit.percent_left() / 100.0;
+
+                    if left <= 0.0 || cascaded.contains(unit.uuid()) {
+                        continue;
+                    }
+
+                    let row = totals.entry((start, *unit.item_uuid())).or_default();
+
+                    if unit
+                        .expires_on()
+                        .map_or(false, |expiry| expiry < *timestamp)
+                    {
+                        row.wasted += left;
+                    } else {
+                        row.consumed += left;
+                        row.record_use(&unit, timestamp);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let rows = totals
+            .into_iter()
+            .map(|((period_start, item_uuid), totals)| ReportRow {
+                item_uuid,
+                item_name: names.get(&item_uuid).cloned().unwrap_or_default(),
+                period_start,
+                bought: totals.bought,
+                consumed: totals.consumed,
+                wasted: totals.wasted,
+                average_time_to_use: if totals.used_up > 0 {
+                    Some(totals.time_to_use / totals.used_up)
+                } else {
+                    None
+                },
+            })
+            .collect();
+
+        Self { period, rows }
+    }
+
+    /// Writes the report as CSV (with a header line, the average time to use is given in days)
+    #[cfg(feature = "csv")]
+    pub fn write_csv(&self, writer: impl std::io::Write) -> anyhow::Result<()> {
+        let mut writer = ::csv::Writer::from_writer(writer);
+        writer.write_record(&[
+            "item_uuid",
+            "item_name",
+            "period_start",
+            "bought",
+            "consumed",
+            "wasted",
+            "average_days_to_use",
+        ])?;
+
+        for row in &self.rows {
+            writer.write_record(&[
+                row.item_uuid.to_string(),
+                row.item_name.clone(),
+                row.period_start.to_string(),
+                format!("{:.2}", row.bought),
+                format!("{:.2}", row.consumed),
+                format!("{:.2}", row.wasted),
+                row.average_time_to_use
+                    .map(|d| format!("{:.2}", d.as_secs_f64() / (24.0 * 60.0 * 60.0)))
+                    .unwrap_or_default(),
+            ])?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+impl Totals {
+    /// Counts a unit which was used up at the given time
+    fn record_use(&mut self, unit: &Unit, used_up_on: &Timestamp) {
+        if let Ok(time_to_use) = used_up_on
+            .signed_duration_since(*unit.created_on())
+            .to_std()
+        {
+            self.time_to_use += time_to_use;
+            self.used_up += 1;
+        }
+    }
+}
+
+/// Finds the units which were only deleted because their item was deleted
+///
+/// `InventoryHandle::delete_item` deletes the units of an item right before the item itself, and
+/// records them in the delete of the item.
+fn cascaded_units(events: &[ProjectionEvent<'_>]) -> HashSet<Uuid> {
+    events
+        .iter()
+        .filter_map(
+            |event| match (event.get_operation(), event.get_data().as_ref()) {
+                (CRUD::Delete, ProjectionEntry::Item(item)) => Some(item.units()),
+                _ => None,
+            },
+        )
+        .flatten()
+        .map(|unit| *unit.uuid())
+        .collect()
+}
+
+impl<'a> InventoryHandle<'a> {
+    /// Works out the consumption and waste statistics of this inventory
+    pub fn consumption_report(&self, period: Period) -> ConsumptionReport {
+        ConsumptionReport::from_projector(self.get_projector(), period)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Item;
+    use std::sync::Arc;
+
+    #[test]
+    fn ignores_units_deleted_with_their_item() {
+        let mut handle = InventoryHandle::new("Pantry".to_string(), Uuid::new_v4());
+        let item = Item::new(&Arc::new((*handle).clone()), "Rice".to_string(), None);
+        handle.create_item(item.clone()).unwrap();
+
+        let item = Arc::new(item);
+        for _ in 0..2 {
+            let unit = Unit::new(&item, None, "1 kg bag".to_string(), 100.0);
+            handle.create_unit(unit).unwrap();
+        }
+
+        // One unit is used up by hand, the other one goes with the item
+        let unit = handle
+            .item(item.uuid())
+            .unwrap()
+            .units()
+            .iter()
+            .next()
+            .unwrap()
+            .clone();
+        handle.delete_unit(unit).unwrap();
+        handle.delete_item((*item).clone()).unwrap();
+
+        let report = handle.consumption_report(Period::Month);
+        assert_eq!(report.rows.len(), 1);
+        assert_eq!(report.rows[0].bought, 2.0);
+        assert_eq!(report.rows[0].consumed, 1.0);
+        assert_eq!(report.rows[0].wasted, 0.0);
+    }
+}
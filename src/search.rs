@@ -0,0 +1,633 @@
+use crate::events::{
+    store::{InventoryHandle, Store},
+    Change, ChangeKind, Item, SubscriptionId,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    sync::mpsc::Receiver,
+};
+use uuid::Uuid;
+
+/// How much less a match on a tag counts than a match on the name
+const TAG_WEIGHT: f64 = 0.8;
+
+/// The lowest trigram similarity which still counts as a (weak) match
+const MIN_SIMILARITY: f64 = 0.3;
+
+/// An item which matched a search
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct SearchHit {
+    /// The inventory of the item
+    pub inventory_uuid: Uuid,
+
+    /// The matching item
+    pub item_uuid: Uuid,
+
+    /// The name of the item
+    pub name: String,
+
+    /// How well the item matches (between 0 and 1, higher is better)
+    pub score: f64,
+}
+
+/// The searchable parts of one item
+#[derive(Clone, Debug)]
+struct Document {
+    inventory_uuid: Uuid,
+    name: String,
+
+    /// The folded words of the name
+    name_terms: Vec<String>,
+
+    /// The folded words of the tags
+    tag_terms: Vec<String>,
+
+    /// The barcode in its full and short form
+    eans: Vec<String>,
+}
+
+impl Document {
+    fn new(inventory_uuid: Uuid, item: &Item) -> Self {
+        Self {
+            inventory_uuid,
+            name: item.name().clone(),
+            name_terms: terms(item.name()),
+            tag_terms: item.tags().iter().flat_map(|t| terms(t)).collect(),
+            eans: item
+                .ean()
+                .iter()
+                .flat_map(|ean| vec![ean.as_str().to_string(), ean.short_form().to_string()])
+                .collect(),
+        }
+    }
+
+    fn all_terms(&self) -> impl Iterator<Item = &String> {
+        self.name_terms.iter().chain(&self.tag_terms)
+    }
+}
+
+/// A typo-tolerant full-text index over the names, tags and barcodes of the items of many inventories
+///
+/// Names and tags are compared without case and accents, and a word may be misspelled (or only be
+/// the beginning of a word). An index which follows a store is kept up to date by applying the
+/// changes of the store, instead of building it again.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    /// The indexed items
+    documents: HashMap<Uuid, Document>,
+
+    /// The items having a word with a trigram
+    trigrams: HashMap<String, HashSet<Uuid>>,
+
+    /// The items having a barcode
+    eans: HashMap<String, HashSet<Uuid>>,
+
+    /// The subscription to the changes of the followed store (if any)
+    changes: Option<(SubscriptionId, Receiver<Change>)>,
+}
+
+impl SearchIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds the index over every inventory of a store
+    pub fn build(store: &Store<'_>) -> Self {
+        let mut index = Self::new();
+
+        for handle in store.iter() {
+            index.add_inventory(handle);
+        }
+
+        index
+    }
+
+    /// Builds the index over every inventory of a store and subscribes it to the changes of the store
+    ///
+    /// The changes are picked up by `catch_up`, which has to be called before searching.
+    pub fn follow(store: &mut Store<'_>) -> Self {
+        let changes = store.subscribe_channel();
+        let mut index = Self::build(store);
+        index.changes = Some(changes);
+
+        index
+    }
+
+    /// Applies the changes which the followed store made since the last call
+    pub fn catch_up(&mut self, store: &Store<'_>) {
+        let changes: Vec<Change> = match &self.changes {
+            Some((_, receiver)) => receiver.try_iter().collect(),
+            None => return,
+        };
+
+        for change in &changes {
+            self.apply(store, change);
+        }
+    }
+
+    /// Stops following a store (returns the subscription, so it can be ended)
+    pub fn unfollow(&mut self) -> Option<SubscriptionId> {
+        self.changes.take().map(|(id, _)| id)
+    }
+
+    /// Adds (or refreshes) the items of an inventory
+    pub fn add_inventory(&mut self, handle: &InventoryHandle<'_>) {
+        for item in handle.items() {
+            self.insert(*handle.uuid(), item);
+        }
+    }
+
+    /// Removes the items of an inventory
+    pub fn remove_inventory(&mut self, inventory_uuid: &Uuid) {
+        let items: Vec<Uuid> = self
+            .documents
+            .iter()
+            .filter(|(_, d)| d.inventory_uuid == *inventory_uuid)
+            .map(|(uuid, _)| *uuid)
+            .collect();
+
+        for item_uuid in items {
+            self.remove(&item_uuid);
+        }
+    }
+
+    /// Updates the index with a change of a store
+    ///
+    /// The changed item is read from the projection of its inventory, not from the event, so merging
+    /// an older event can't bring back an outdated version of the item.
+    pub fn apply(&mut self, store: &Store<'_>, change: &Change) {
+        let handle = store.inventory(&change.inventory_uuid);
+
+        match &change.kind {
+            ChangeKind::InventoryAdded => {
+                if let Some(handle) = handle {
+                    self.add_inventory(handle);
+                }
+            }
+            ChangeKind::InventoryDeleted => self.remove_inventory(&change.inventory_uuid),
+            ChangeKind::ItemAdded { item_uuid }
+            | ChangeKind::ItemUpdated { item_uuid }
+            | ChangeKind::ItemRemoved { item_uuid } => {
+                match handle.and_then(|h| h.item(item_uuid)) {
+                    Some(item) => self.insert(change.inventory_uuid, item),
+                    None => self.remove(item_uuid),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Searches the items of every indexed inventory, returning the best matches first
+    pub fn search(&self, text: &str, limit: usize) -> Vec<SearchHit> {
+        self.search_in(text, limit, |_| true)
+    }
+
+    /// Searches the items of the inventories which a user is allowed to read
+    pub fn search_as(
+        &self,
+        store: &Store<'_>,
+        user_uuid: &Uuid,
+        text: &str,
+        limit: usize,
+    ) -> Vec<SearchHit> {
+        self.search_in(text, limit, |inventory_uuid| {
+            store
+                .inventory(inventory_uuid)
+                .map_or(false, |h| h.allow_read(user_uuid))
+        })
+    }
+
+    fn search_in(
+        &self,
+        text: &str,
+        limit: usize,
+        readable: impl Fn(&Uuid) -> bool,
+    ) -> Vec<SearchHit> {
+        let query = terms(text);
+
+        if query.is_empty() {
+            return vec![];
+        }
+
+        // Barcodes only match as a whole
+        let digits: String = text.chars().filter(char::is_ascii_digit).collect();
+        let by_ean = self.eans.get(&digits);
+
+        // Only the items sharing a trigram with the query can match
+        let mut candidates: HashSet<&Uuid> = query
+            .iter()
+            .flat_map(|term| trigrams(term))
+            .filter_map(|trigram| self.trigrams.get(&trigram))
+            .flatten()
+            .collect();
+        candidates.extend(by_ean.into_iter().flatten());
+
+        let mut hits: Vec<SearchHit> = candidates
+            .into_iter()
+            .filter_map(|uuid| Some((uuid, self.documents.get(uuid)?)))
+            .filter(|(_, document)| readable(&document.inventory_uuid))
+            .filter_map(|(uuid, document)| {
+                let score = if by_ean.map_or(false, |items| items.contains(uuid)) {
+                    1.0
+                } else {
+                    score(&query, document)?
+                };
+
+                Some(SearchHit {
+                    inventory_uuid: document.inventory_uuid,
+                    item_uuid: *uuid,
+                    name: document.name.clone(),
+                    score,
+                })
+            })
+            .collect();
+
+        hits.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a.name.cmp(&b.name))
+                .then_with(|| a.item_uuid.cmp(&b.item_uuid))
+        });
+        hits.truncate(limit);
+
+        hits
+    }
+
+    fn insert(&mut self, inventory_uuid: Uuid, item: &Item) {
+        // Forget the old version of the item first
+        self.remove(item.uuid());
+
+        let document = Document::new(inventory_uuid, item);
+
+        for term in document.all_terms() {
+            for trigram in trigrams(term) {
+                self.trigrams
+                    .entry(trigram)
+                    .or_default()
+                    .insert(*item.uuid());
+            }
+        }
+
+        for ean in &document.eans {
+            self.eans
+                .entry(ean.clone())
+                .or_default()
+                .insert(*item.uuid());
+        }
+
+        self.documents.insert(*item.uuid(), document);
+    }
+
+    fn remove(&mut self, item_uuid: &Uuid) {
+        let document = match self.documents.remove(item_uuid) {
+            Some(document) => document,
+            None => return,
+        };
+
+        for term in document.all_terms() {
+            for trigram in trigrams(term) {
+                if let Some(items) = self.trigrams.get_mut(&trigram) {
+                    items.remove(item_uuid);
+
+                    if items.is_empty() {
+                        self.trigrams.remove(&trigram);
+                    }
+                }
+            }
+        }
+
+        for ean in &document.eans {
+            if let Some(items) = self.eans.get_mut(ean) {
+                items.remove(item_uuid);
+
+                if items.is_empty() {
+                    self.eans.remove(ean);
+                }
+            }
+        }
+    }
+}
+
+/// How well an item matches the words of a query (`None` if any of the words doesn't match)
+///
+/// The score is the average of how well each word of the query matches its best word of the item.
+fn score(query: &[String], document: &Document) -> Option<f64> {
+    let mut total = 0.0;
+
+    for word in query {
+        let on_name = document
+            .name_terms
+            .iter()
+            .map(|term| similarity(word, term));
+        let on_tags = document
+            .tag_terms
+            .iter()
+            .map(|term| similarity(word, term) * TAG_WEIGHT);
+
+        let best = on_name.chain(on_tags).fold(0.0, f64::max);
+
+        if best <= 0.0 {
+            return None;
+        }
+
+        total += best;
+    }
+
+    Some(total / query.len() as f64)
+}
+
+/// How well a word of a query matches a word of an item (0 if it doesn't match at all)
+fn similarity(word: &str, term: &str) -> f64 {
+    if word == term {
+        return 1.0;
+    }
+
+    if term.starts_with(word) {
+        return 0.9;
+    }
+
+    let word_chars: Vec<char> = word.chars().collect();
+    let term_chars: Vec<char> = term.chars().collect();
+    let allowed = allowed_typos(word_chars.len());
+
+    if allowed > 0 {
+        // A misspelled word
+        let distance = edit_distance(&word_chars, &term_chars);
+        if distance <= allowed {
+            return 0.8 - 0.1 * distance as f64;
+        }
+
+        // The misspelled beginning of a word
+        if term_chars.len() > word_chars.len() {
+            let distance = edit_distance(&word_chars, &term_chars[..word_chars.len()]);
+            if distance <= allowed {
+                return 0.7 - 0.1 * distance as f64;
+            }
+        }
+    }
+
+    let word_trigrams = trigrams(word);
+    let term_trigrams = trigrams(term);
+    let shared = word_trigrams.intersection(&term_trigrams).count() as f64;
+    let jaccard = shared / word_trigrams.union(&term_trigrams).count() as f64;
+
+    if jaccard >= MIN_SIMILARITY {
+        0.5 * jaccard
+    } else {
+        0.0
+    }
+}
+
+/// The number of typos tolerated in a word of a query (short words must be spelled right)
+fn allowed_typos(length: usize) -> usize {
+    match length {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+/// The Damerau-Levenshtein distance of two words (in its restricted form, counting swapped neighbours as one edit)
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+
+            rows[i][j] = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                rows[i][j] = rows[i][j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    rows[a.len()][b.len()]
+}
+
+/// The trigrams of a word (padded, so its beginning and end count too)
+fn trigrams(term: &str) -> HashSet<String> {
+    let chars: Vec<char> = format!(" {} ", term).chars().collect();
+
+    chars.windows(3).map(|w| w.iter().collect()).collect()
+}
+
+/// Splits a text into words without case and accents
+fn terms(text: &str) -> Vec<String> {
+    fold(text).split_whitespace().map(str::to_string).collect()
+}
+
+/// Lowercases a text and replaces accented letters by their plain forms (and punctuation by spaces)
+fn fold(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+
+    for c in text.chars().flat_map(char::to_lowercase) {
+        match c {
+            'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => folded.push('a'),
+            'æ' => folded.push_str("ae"),
+            'ç' | 'ć' | 'č' => folded.push('c'),
+            'ď' | 'đ' => folded.push('d'),
+            'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ė' | 'ę' | 'ě' => folded.push('e'),
+            'ğ' => folded.push('g'),
+            'ì' | 'í' | 'î' | 'ï' | 'ī' | 'į' | 'ı' => folded.push('i'),
+            'ł' | 'ľ' | 'ĺ' => folded.push('l'),
+            'ñ' | 'ń' | 'ň' => folded.push('n'),
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ő' => folded.push('o'),
+            'œ' => folded.push_str("oe"),
+            'ř' | 'ŕ' => folded.push('r'),
+            'ś' | 'š' | 'ş' => folded.push('s'),
+            'ß' => folded.push_str("ss"),
+            'ť' | 'ţ' => folded.push('t'),
+            'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' | 'ű' | 'ų' => folded.push('u'),
+            'ý' | 'ÿ' => folded.push('y'),
+            'ź' | 'ż' | 'ž' => folded.push('z'),
+            c if c.is_alphanumeric() => folded.push(c),
+            _ => folded.push(' '),
+        }
+    }
+
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{store::Role, Ean};
+    use std::{sync::Arc, thread, time::Duration};
+
+    /// Adds an item with tags (and a barcode) to an inventory
+    fn add_item(handle: &mut InventoryHandle<'_>, name: &str, tags: &[&str], ean: Option<&str>) {
+        let ean = ean.map(|ean| Ean::parse(ean).unwrap());
+        let item = Item::new(&Arc::new((**handle).clone()), name.to_string(), ean);
+        let item_uuid = *item.uuid();
+        handle.create_item(item).unwrap();
+
+        let tags = tags.iter().map(|t| t.to_string()).collect();
+        handle.set_tags(&item_uuid, tags).unwrap();
+    }
+
+    /// Makes a store with an inventory of the given items (with their tags)
+    fn store_with(items: &[(&str, &[&str])]) -> Store<'static> {
+        let mut store = Store::new();
+        let inventory_uuid = store.make_inventory("Fridge".to_string(), Uuid::new_v4());
+        let handle = store.inventory_mut(&inventory_uuid).unwrap();
+
+        for (name, tags) in items {
+            add_item(handle, name, tags, None);
+        }
+
+        store
+    }
+
+    fn names(hits: &[SearchHit]) -> Vec<&str> {
+        hits.iter().map(|hit| hit.name.as_str()).collect()
+    }
+
+    #[test]
+    fn finds_misspelled_words() {
+        let store = store_with(&[("Joghurt natur", &[]), ("Rice", &[]), ("Jam", &[])]);
+        let index = SearchIndex::build(&store);
+
+        assert_eq!(names(&index.search("jogurt", 10)), vec!["Joghurt natur"]);
+        assert_eq!(
+            names(&index.search("Natur Jogurt", 10)),
+            vec!["Joghurt natur"]
+        );
+        assert_eq!(names(&index.search("jog", 10)), vec!["Joghurt natur"]);
+
+        // Short words must be spelled right, and every word has to match
+        assert!(index.search("jem", 10).is_empty());
+        assert!(index.search("jogurt rice", 10).is_empty());
+        assert!(index.search("", 10).is_empty());
+    }
+
+    #[test]
+    fn folds_case_and_accents() {
+        assert_eq!(fold("Crème Brûlée"), "creme brulee");
+        assert_eq!(fold("Straße, Äpfel!"), "strasse  apfel ");
+        assert_eq!(fold("ŁÓDŹ Œuvre"), "lodz oeuvre");
+        assert_eq!(terms("Straße, Äpfel!"), vec!["strasse", "apfel"]);
+
+        let store = store_with(&[("Crème fraîche", &[]), ("Äpfel", &[])]);
+        let index = SearchIndex::build(&store);
+        assert_eq!(names(&index.search("CREME", 10)), vec!["Crème fraîche"]);
+        assert_eq!(names(&index.search("apfel", 10)), vec!["Äpfel"]);
+    }
+
+    #[test]
+    fn matches_tags_and_barcodes() {
+        let mut store = store_with(&[("Rice", &[])]);
+        let inventory_uuid = *store[0].uuid();
+        add_item(
+            store.inventory_mut(&inventory_uuid).unwrap(),
+            "Oat drink",
+            &["Milk alternative"],
+            Some("4006381333931"),
+        );
+        let index = SearchIndex::build(&store);
+
+        let hits = index.search("milk", 10);
+        assert_eq!(names(&hits), vec!["Oat drink"]);
+        assert!((hits[0].score - TAG_WEIGHT).abs() < 1e-9);
+
+        for code in &["4006381333931", "04006381333931", "400-6381333931"] {
+            let hits = index.search(code, 10);
+            assert_eq!(names(&hits), vec!["Oat drink"]);
+            assert!((hits[0].score - 1.0).abs() < 1e-9);
+        }
+
+        // Barcodes only match as a whole
+        assert!(index.search("400638133", 10).is_empty());
+        assert!(index.search("4006381333932", 10).is_empty());
+    }
+
+    #[test]
+    fn ranks_the_best_matches_first() {
+        let store = store_with(&[
+            ("Oat drink", &["milk"]),
+            ("Milky bar", &[]),
+            ("Milk chocolate", &[]),
+            ("Milk", &[]),
+            ("Milc", &[]),
+            ("Bread", &[]),
+        ]);
+        let index = SearchIndex::build(&store);
+
+        let hits = index.search("milk", 10);
+        assert_eq!(
+            names(&hits),
+            vec!["Milk", "Milk chocolate", "Milky bar", "Oat drink", "Milc"]
+        );
+        assert!(hits.windows(2).all(|w| w[0].score >= w[1].score));
+
+        assert_eq!(
+            names(&index.search("milk", 2)),
+            vec!["Milk", "Milk chocolate"]
+        );
+    }
+
+    #[test]
+    fn hides_inventories_which_cannot_be_read() {
+        let (owner, reader) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut store = Store::new();
+
+        for name in &["Shared", "Private"] {
+            let inventory_uuid = store.make_inventory(name.to_string(), owner);
+            let handle = store.inventory_mut(&inventory_uuid).unwrap();
+            add_item(handle, &format!("{} rice", name), &[], None);
+
+            if *name == "Shared" {
+                handle.set_role(reader, Some(Role::Read)).unwrap();
+            }
+        }
+
+        let index = SearchIndex::build(&store);
+
+        assert_eq!(index.search("rice", 10).len(), 2);
+        assert_eq!(index.search_as(&store, &owner, "rice", 10).len(), 2);
+        assert_eq!(
+            names(&index.search_as(&store, &reader, "rice", 10)),
+            vec!["Shared rice"]
+        );
+        assert!(index
+            .search_as(&store, &Uuid::new_v4(), "rice", 10)
+            .is_empty());
+    }
+
+    #[test]
+    fn keeps_the_newest_name_when_an_older_update_is_merged() {
+        let mut store = Store::new();
+        let inventory_uuid = store.make_inventory("Pantry".to_string(), Uuid::new_v4());
+        let inventory = Arc::new((**store.inventory(&inventory_uuid).unwrap()).clone());
+        let item = Item::new(&inventory, "Rice".to_string(), None);
+
+        let mut index = SearchIndex::follow(&mut store);
+        let handle = store.inventory_mut(&inventory_uuid).unwrap();
+        handle.create_item(item.clone()).unwrap();
+
+        // The older rename arrives after the newer one
+        let older = item.clone().update_name("Brown rice".to_string());
+        thread::sleep(Duration::from_millis(5));
+        handle
+            .merge(item.update_name("Basmati rice".to_string()))
+            .unwrap();
+        handle.merge(older).unwrap();
+
+        index.catch_up(&store);
+        assert_eq!(index.search("basmati", 10).len(), 1);
+        assert!(index.search("brown", 10).is_empty());
+    }
+}
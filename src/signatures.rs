@@ -0,0 +1,207 @@
+use crate::events::store::{InventoryHandle, ProjectionEvent};
+use anyhow::{anyhow, bail, Result};
+use ed25519_dalek::{PublicKey, Signature, Signer, Verifier};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use uuid::Uuid;
+
+pub use ed25519_dalek::Keypair;
+
+/// The context string mixed into every signed message
+const SIGNING_CONTEXT: &[u8] = b"sfi event signature v1";
+
+/// An event signed with the key of its author's device
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SignedEvent {
+    /// The UUID of the inventory the event belongs to
+    inventory_uuid: Uuid,
+
+    /// The UUID of the member who made the event
+    author_uuid: Uuid,
+
+    /// The public key of the device which signed the event
+    public_key: [u8; 32],
+
+    /// The serialized event (signed as is, so re-serializing can't break the signature)
+    payload: Vec<u8>,
+
+    signature: Vec<u8>,
+}
+
+impl SignedEvent {
+    /// Signs an event of an inventory with the key of the author's device
+    pub fn sign(
+        inventory_uuid: Uuid,
+        event: &ProjectionEvent<'_>,
+        author_uuid: Uuid,
+        keypair: &Keypair,
+    ) -> Result<Self> {
+        let payload = serde_json::to_vec(event)?;
+        let signature = keypair.sign(&message(&inventory_uuid, &author_uuid, &payload));
+
+        Ok(Self {
+            inventory_uuid,
+            author_uuid,
+            public_key: keypair.public.to_bytes(),
+            payload,
+            signature: signature.to_bytes().to_vec(),
+        })
+    }
+
+    /// The UUID of the inventory the event belongs to
+    pub fn inventory_uuid(&self) -> &Uuid {
+        &self.inventory_uuid
+    }
+
+    /// The UUID of the member who made the event
+    pub fn author_uuid(&self) -> &Uuid {
+        &self.author_uuid
+    }
+
+    /// The public key of the device which signed the event
+    pub fn public_key(&self) -> &[u8; 32] {
+        &self.public_key
+    }
+
+    /// Checks the signature and returns the signed event
+    ///
+    /// This only proves that the event was signed with the contained key,
+    /// use `InventoryHandle::merge_signed` to also check who the key belongs to.
+    pub fn verify<'a>(&self) -> Result<ProjectionEvent<'a>> {
+        let public_key =
+            PublicKey::from_bytes(&self.public_key).map_err(|_| anyhow!("Invalid public key"))?;
+        let signature = Signature::try_from(self.signature.as_slice())
+            .map_err(|_| anyhow!("Invalid signature"))?;
+
+        public_key
+            .verify(
+                &message(&self.inventory_uuid, &self.author_uuid, &self.payload),
+                &signature,
+            )
+            .map_err(|_| anyhow!("The signature of the event is not valid"))?;
+
+        Ok(serde_json::from_slice(&self.payload)?)
+    }
+}
+
+/// The message which actually gets signed (binding the event to its inventory and author)
+fn message(inventory_uuid: &Uuid, author_uuid: &Uuid, payload: &[u8]) -> Vec<u8> {
+    let mut message = SIGNING_CONTEXT.to_vec();
+    message.extend_from_slice(inventory_uuid.as_bytes());
+    message.extend_from_slice(author_uuid.as_bytes());
+    message.extend_from_slice(payload);
+    message
+}
+
+impl<'a> InventoryHandle<'a> {
+    /// Merges a signed event received from a peer
+    ///
+    /// The event is only accepted if its signature is valid, the signing key is registered
+    /// for its author in this inventory, and the author is allowed to make the change.
+    pub fn merge_signed(&mut self, signed: &SignedEvent) -> Result<()> {
+        if signed.inventory_uuid != *self.uuid() {
+            bail!("The event belongs to another inventory");
+        }
+
+        if !self
+            .device_keys()
+            .iter()
+            .any(|k| k.user_uuid() == &signed.author_uuid && k.public_key() == &signed.public_key)
+        {
+            bail!("The signing key is not registered for the author of the event");
+        }
+
+        let event = signed.verify()?;
+        self.check_signed_permission(&signed.author_uuid, Some(&signed.public_key), &event)?;
+
+        self.merge(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{store::Role, DeviceKey};
+    use rand::rngs::OsRng;
+
+    /// Makes an inventory with an admin, where both the owner and the admin have a device key
+    fn inventory() -> (InventoryHandle<'static>, Keypair, Uuid, Keypair) {
+        let owner = Uuid::new_v4();
+        let admin = Uuid::new_v4();
+        let owner_keys = Keypair::generate(&mut OsRng);
+        let admin_keys = Keypair::generate(&mut OsRng);
+
+        let mut handle = InventoryHandle::new("Pantry".to_string(), owner);
+        handle.set_role(admin, Some(Role::Admin)).unwrap();
+        for (user_uuid, keypair) in &[(owner, &owner_keys), (admin, &admin_keys)] {
+            handle
+                .add_device_key(DeviceKey::new(*user_uuid, keypair.public.to_bytes()))
+                .unwrap();
+        }
+
+        (handle, owner_keys, admin, admin_keys)
+    }
+
+    /// Signs an update of the device keys of an inventory
+    fn with_key(
+        handle: &InventoryHandle<'_>,
+        key: DeviceKey,
+        author_uuid: Uuid,
+        keypair: &Keypair,
+    ) -> SignedEvent {
+        let mut keys = handle.device_keys().clone();
+        keys.push(key);
+        let event = (**handle).clone().update_device_keys(keys);
+
+        SignedEvent::sign(*handle.uuid(), &event, author_uuid, keypair).unwrap()
+    }
+
+    #[test]
+    fn admins_cannot_register_keys_for_the_owner() {
+        let (mut handle, _, admin, admin_keys) = inventory();
+        let owner = *handle.owner();
+
+        let key = DeviceKey::new(owner, admin_keys.public.to_bytes());
+        let signed = with_key(&handle, key, admin, &admin_keys);
+
+        assert!(handle.merge_signed(&signed).is_err());
+        assert!(!handle
+            .device_keys()
+            .iter()
+            .any(|k| k.user_uuid() == &owner && k.public_key() == &admin_keys.public.to_bytes()));
+    }
+
+    #[test]
+    fn members_change_their_keys_with_a_registered_key() {
+        let (mut handle, _, admin, admin_keys) = inventory();
+        let new_keys = Keypair::generate(&mut OsRng);
+
+        // Unsigned, the admin's keys could have been changed by anyone
+        let key = DeviceKey::new(admin, new_keys.public.to_bytes());
+        let signed = with_key(&handle, key, admin, &admin_keys);
+        assert!(handle
+            .check_permission(&admin, &signed.verify().unwrap())
+            .is_err());
+
+        handle.merge_signed(&signed).unwrap();
+        assert_eq!(handle.device_keys().len(), 3);
+    }
+
+    #[test]
+    fn only_the_owner_appoints_admins() {
+        let (mut handle, owner_keys, admin, admin_keys) = inventory();
+        let owner = *handle.owner();
+        let member = Uuid::new_v4();
+
+        let mut admins = handle.admins().clone();
+        admins.push(member);
+        let event = (*handle).clone().update_admins(admins);
+
+        let signed = SignedEvent::sign(*handle.uuid(), &event, admin, &admin_keys).unwrap();
+        assert!(handle.merge_signed(&signed).is_err());
+
+        let signed = SignedEvent::sign(*handle.uuid(), &event, owner, &owner_keys).unwrap();
+        handle.merge_signed(&signed).unwrap();
+        assert!(handle.allow_admin(&member));
+    }
+}
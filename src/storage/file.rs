@@ -0,0 +1,277 @@
+use super::{decode_event, encode_event, Storage};
+use crate::events::store::ProjectionEvent;
+use anyhow::{anyhow, bail, Result};
+use std::{
+    collections::HashMap,
+    convert::{TryFrom, TryInto},
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+use uuid::Uuid;
+
+/// The length of a record header (the payload length and the CRC32 of the payload, little endian)
+const HEADER_LEN: usize = 8;
+
+/// A storage which keeps one append-only log file per inventory in a directory
+#[derive(Clone, Debug)]
+pub struct FileStorage {
+    /// The directory containing the log files
+    directory: PathBuf,
+
+    /// The length of the valid part of each log file which has been checked already
+    valid_lengths: HashMap<Uuid, u64>,
+}
+
+impl FileStorage {
+    /// Opens a storage directory (creating it if needed)
+    pub fn open(directory: impl Into<PathBuf>) -> Result<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+
+        Ok(Self {
+            directory,
+            valid_lengths: HashMap::new(),
+        })
+    }
+
+    /// The path of the log file of an inventory
+    fn log_path(&self, inventory_uuid: &Uuid) -> PathBuf {
+        self.directory.join(format!("{}.log", inventory_uuid))
+    }
+
+    /// Reads the raw log file of an inventory (a missing file is an empty log)
+    fn read_log(&self, inventory_uuid: &Uuid) -> Result<Vec<u8>> {
+        match fs::read(self.log_path(inventory_uuid)) {
+            Ok(log) => Ok(log),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Storage for FileStorage {
+    fn inventories(&self) -> Result<Vec<Uuid>> {
+        let mut inventories = vec![];
+
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+
+            if path.extension().map_or(false, |e| e == "log") {
+                if let Some(Ok(uuid)) = path
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .map(Uuid::parse_str)
+                {
+                    inventories.push(uuid);
+                }
+            }
+        }
+
+        // The directory lists its files in no particular order
+        inventories.sort();
+
+        Ok(inventories)
+    }
+
+    fn load<'a>(&self, inventory_uuid: &Uuid) -> Result<Vec<ProjectionEvent<'a>>> {
+        let log = self.read_log(inventory_uuid)?;
+        let (records, _) = parse_records(&log)?;
+
+        records.into_iter().map(decode_event).collect()
+    }
+
+    fn append(&mut self, inventory_uuid: &Uuid, events: &[ProjectionEvent<'_>]) -> Result<()> {
+        // Encode all records first, so they can be written at once
+        let mut buffer = vec![];
+        for event in events {
+            let payload = encode_event(event)?;
+            let len =
+                u32::try_from(payload.len()).map_err(|_| anyhow!("The event is too large"))?;
+
+            buffer.extend_from_slice(&len.to_le_bytes());
+            buffer.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+            buffer.extend_from_slice(&payload);
+        }
+
+        // Find out where the valid part of the log ends (only once per log)
+        let valid_len = match self.valid_lengths.get(inventory_uuid) {
+            Some(len) => *len,
+            None => parse_records(&self.read_log(inventory_uuid)?)?.1 as u64,
+        };
+
+        let path = self.log_path(inventory_uuid);
+        let created = !path.exists();
+
+        let mut file = OpenOptions::new().create(true).write(true).open(&path)?;
+
+        // Cut off a truncated last record (if any) and append the new records
+        file.set_len(valid_len)?;
+        file.seek(SeekFrom::End(0))?;
+        file.write_all(&buffer)?;
+        file.sync_data()?;
+
+        // A new log file only survives a crash once its directory entry is on disk as well
+        if created {
+            sync_directory(&self.directory)?;
+        }
+
+        self.valid_lengths
+            .insert(*inventory_uuid, valid_len + buffer.len() as u64);
+
+        Ok(())
+    }
+}
+
+/// Flushes the entries of a directory to disk
+#[cfg(unix)]
+fn sync_directory(directory: &Path) -> Result<()> {
+    fs::File::open(directory)?.sync_all()?;
+
+    Ok(())
+}
+
+/// Directories can't be opened (or synced) as files on other platforms
+#[cfg(not(unix))]
+fn sync_directory(_directory: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Splits a log into its record payloads and returns them together with the length of its valid part
+///
+/// A truncated or damaged last record (as left behind by a crash while appending) is ignored,
+/// damage anywhere else is an error.
+fn parse_records(log: &[u8]) -> Result<(Vec<&[u8]>, usize)> {
+    let mut records = vec![];
+    let mut offset = 0;
+
+    while log.len() - offset >= HEADER_LEN {
+        let rest = &log[offset..];
+        let len = u32::from_le_bytes(rest[0..4].try_into()?) as usize;
+        let crc = u32::from_le_bytes(rest[4..8].try_into()?);
+
+        // The last record was not written completely
+        if rest.len() - HEADER_LEN < len {
+            break;
+        }
+
+        let end = HEADER_LEN + len;
+        let payload = &rest[HEADER_LEN..end];
+
+        if crc32fast::hash(payload) != crc {
+            if end == rest.len() {
+                // The last record was not written completely
+                break;
+            }
+
+            bail!("The record at offset {} of the log is damaged", offset);
+        }
+
+        records.push(payload);
+        offset += end;
+    }
+
+    Ok((records, offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{store::InventoryHandle, Item};
+    use std::sync::Arc;
+
+    /// Makes an empty storage directory, together with the log of an inventory with a few items
+    fn setup() -> (PathBuf, Uuid, Vec<ProjectionEvent<'static>>) {
+        let directory = std::env::temp_dir().join(format!("sfi-storage-{}", Uuid::new_v4()));
+
+        let mut handle = InventoryHandle::new("Pantry".to_string(), Uuid::new_v4());
+        for name in &["Rice", "Pasta", "Flour"] {
+            let item = Item::new(&Arc::new((*handle).clone()), name.to_string(), None);
+            handle.create_item(item).unwrap();
+        }
+
+        let events = handle.get_projector().get_events().to_vec();
+
+        (directory, *handle.uuid(), events)
+    }
+
+    #[test]
+    fn recovers_from_a_truncated_last_record() {
+        let (directory, uuid, events) = setup();
+        let (last, written) = events.split_last().unwrap();
+
+        let mut storage = FileStorage::open(&directory).unwrap();
+        storage.append(&uuid, written).unwrap();
+        let valid_len = fs::metadata(storage.log_path(&uuid)).unwrap().len();
+        storage.append(&uuid, std::slice::from_ref(last)).unwrap();
+
+        // A crash while appending leaves half a record behind
+        let path = storage.log_path(&uuid);
+        let full_len = fs::metadata(&path).unwrap().len();
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(full_len - 3).unwrap();
+        drop(file);
+
+        let mut storage = FileStorage::open(&directory).unwrap();
+        assert_eq!(storage.load(&uuid).unwrap().len(), written.len());
+
+        // Appending again replaces the broken record
+        storage.append(&uuid, std::slice::from_ref(last)).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), full_len);
+
+        let storage = FileStorage::open(&directory).unwrap();
+        let loaded = storage.load(&uuid).unwrap();
+        assert_eq!(loaded.len(), events.len());
+        assert_eq!(
+            serde_json::to_value(&loaded).unwrap(),
+            serde_json::to_value(&events).unwrap()
+        );
+        assert_eq!(
+            InventoryHandle::from_events(loaded).unwrap().items().len(),
+            3
+        );
+
+        // Even a record whose header is cut off is dropped
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(valid_len + 3).unwrap();
+        drop(file);
+        let storage = FileStorage::open(&directory).unwrap();
+        assert_eq!(storage.load(&uuid).unwrap().len(), written.len());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn detects_damaged_records_in_the_middle() {
+        let (directory, uuid, events) = setup();
+
+        let mut storage = FileStorage::open(&directory).unwrap();
+        storage.append(&uuid, &events).unwrap();
+
+        // Flip a bit in the payload of the first record
+        let path = storage.log_path(&uuid);
+        let mut log = fs::read(&path).unwrap();
+        log[HEADER_LEN + 1] ^= 1;
+        fs::write(&path, &log).unwrap();
+
+        let mut storage = FileStorage::open(&directory).unwrap();
+        assert!(storage.load(&uuid).is_err());
+        assert!(storage.append(&uuid, &events).is_err());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn lists_the_inventories_with_a_log() {
+        let (directory, uuid, events) = setup();
+
+        let mut storage = FileStorage::open(&directory).unwrap();
+        assert!(storage.inventories().unwrap().is_empty());
+
+        storage.append(&uuid, &events).unwrap();
+        fs::write(directory.join("notes.txt"), "not a log").unwrap();
+        assert_eq!(storage.inventories().unwrap(), vec![uuid]);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
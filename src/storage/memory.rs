@@ -0,0 +1,51 @@
+use super::{decode_event, encode_event, Storage};
+use crate::events::store::ProjectionEvent;
+use anyhow::Result;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// A storage which keeps the event logs in memory (useful for tests)
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStorage {
+    /// The encoded records of each inventory's log
+    logs: HashMap<Uuid, Vec<Vec<u8>>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn inventories(&self) -> Result<Vec<Uuid>> {
+        let mut inventories: Vec<Uuid> = self.logs.keys().cloned().collect();
+        inventories.sort();
+
+        Ok(inventories)
+    }
+
+    fn load<'a>(&self, inventory_uuid: &Uuid) -> Result<Vec<ProjectionEvent<'a>>> {
+        self.logs
+            .get(inventory_uuid)
+            .map_or(&[][..], Vec::as_slice)
+            .iter()
+            .map(|record| decode_event(record))
+            .collect()
+    }
+
+    fn append(&mut self, inventory_uuid: &Uuid, events: &[ProjectionEvent<'_>]) -> Result<()> {
+        // Encode all events first, so a failing event doesn't leave a partial append behind
+        let records = events
+            .iter()
+            .map(encode_event)
+            .collect::<Result<Vec<_>>>()?;
+
+        self.logs
+            .entry(*inventory_uuid)
+            .or_default()
+            .extend(records);
+
+        Ok(())
+    }
+}
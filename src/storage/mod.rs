@@ -0,0 +1,65 @@
+mod file;
+mod memory;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+pub use file::*;
+pub use memory::*;
+#[cfg(feature = "sqlite")]
+pub use sqlite::*;
+
+use crate::events::store::ProjectionEvent;
+use anyhow::Result;
+use uuid::Uuid;
+
+/// An append-only storage for the event logs of inventories (one log per inventory)
+pub trait Storage {
+    /// The UUIDs of all inventories which have a log in this storage (ordered by UUID)
+    fn inventories(&self) -> Result<Vec<Uuid>>;
+
+    /// Reads the whole event log of an inventory
+    fn load<'a>(&self, inventory_uuid: &Uuid) -> Result<Vec<ProjectionEvent<'a>>>;
+
+    /// Appends events to the log of an inventory (creating the log if there is none yet)
+    fn append(&mut self, inventory_uuid: &Uuid, events: &[ProjectionEvent<'_>]) -> Result<()>;
+}
+
+/// Encodes an event into a record payload
+fn encode_event(event: &ProjectionEvent<'_>) -> Result<Vec<u8>> {
+    Ok(serde_json::to_vec(event)?)
+}
+
+/// Decodes an event from a record payload
+fn decode_event<'a>(payload: &[u8]) -> Result<ProjectionEvent<'a>> {
+    Ok(serde_json::from_slice(payload)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::store::Store;
+
+    #[test]
+    fn moves_a_deserialized_store_to_a_new_backend() {
+        let mut store = Store::new();
+        store.make_inventory("Pantry".to_string(), Uuid::new_v4());
+        store.make_inventory("Freezer".to_string(), Uuid::new_v4());
+
+        let mut store: Store<'static> =
+            serde_json::from_value(serde_json::to_value(&store).unwrap()).unwrap();
+        let mut storage = MemoryStorage::new();
+
+        // Nothing was changed since the store was read
+        store.flush(&mut storage).unwrap();
+        assert!(storage.inventories().unwrap().is_empty());
+
+        store.save_all(&mut storage).unwrap();
+        let mut expected: Vec<Uuid> = store.iter().map(|h| *h.uuid()).collect();
+        expected.sort();
+        assert_eq!(storage.inventories().unwrap(), expected);
+        assert_eq!(Store::load(&storage).unwrap().len(), 2);
+
+        // The logs would be duplicated
+        assert!(store.save_all(&mut storage).is_err());
+    }
+}
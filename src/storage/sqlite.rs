@@ -0,0 +1,534 @@
+use super::{decode_event, encode_event, Storage};
+use crate::{
+    events::{
+        store::{ProjectionEntry, ProjectionEvent},
+        Ean, Inventory, Item, Unit,
+    },
+    Timestamp, Utc,
+};
+use anyhow::Result;
+use chrono::{DateTime, SecondsFormat};
+use libocc::events::CRUD;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use uuid::Uuid;
+
+/// The tables of the database (the event logs and the projections materialized from them)
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS events (
+        inventory_uuid TEXT NOT NULL,
+        seq INTEGER NOT NULL,
+        payload BLOB NOT NULL,
+        PRIMARY KEY (inventory_uuid, seq)
+    );
+
+    CREATE TABLE IF NOT EXISTS inventories (
+        uuid TEXT PRIMARY KEY,
+        name TEXT NOT NULL,
+        owner TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS members (
+        inventory_uuid TEXT NOT NULL,
+        user_uuid TEXT NOT NULL,
+        role TEXT NOT NULL,
+        PRIMARY KEY (inventory_uuid, user_uuid, role)
+    );
+    CREATE INDEX IF NOT EXISTS members_by_user ON members (user_uuid);
+
+    CREATE TABLE IF NOT EXISTS items (
+        uuid TEXT PRIMARY KEY,
+        inventory_uuid TEXT NOT NULL,
+        name TEXT NOT NULL,
+        ean TEXT,
+        created_on TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS items_by_inventory ON items (inventory_uuid);
+    CREATE INDEX IF NOT EXISTS items_by_ean ON items (ean);
+
+    CREATE TABLE IF NOT EXISTS units (
+        uuid TEXT PRIMARY KEY,
+        item_uuid TEXT NOT NULL,
+        inventory_uuid TEXT NOT NULL,
+        name TEXT NOT NULL,
+        percent_left REAL NOT NULL,
+        created_on TEXT NOT NULL,
+        opened_on TEXT,
+        expires_on TEXT
+    );
+    CREATE INDEX IF NOT EXISTS units_by_item ON units (item_uuid);
+    CREATE INDEX IF NOT EXISTS units_by_expiry ON units (expires_on);
+
+    CREATE TABLE IF NOT EXISTS materialized (
+        inventory_uuid TEXT PRIMARY KEY,
+        newest TEXT NOT NULL
+    );
+";
+
+/// A storage which keeps the event logs in a SQLite database, together with tables of the current items and units
+#[derive(Debug)]
+pub struct SqliteStorage {
+    connection: Connection,
+}
+
+/// A unit which expires soon (as found in the materialized tables)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ExpiringUnit {
+    /// The UUID of the inventory containing the unit
+    pub inventory_uuid: Uuid,
+
+    /// The UUID of the item of the unit
+    pub item_uuid: Uuid,
+
+    /// The name of the item of the unit
+    pub item_name: String,
+
+    /// The UUID of the unit
+    pub unit_uuid: Uuid,
+
+    /// The name of the unit
+    pub unit_name: String,
+
+    /// The percentage of how much of the unit is left
+    pub percent_left: f64,
+
+    /// The timestamp after which the unit should be used up (see `Unit::expires_on`)
+    pub expires_on: Timestamp,
+}
+
+impl SqliteStorage {
+    /// Opens a database file (creating it and its tables if needed)
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    /// Opens a new in-memory database
+    pub fn open_in_memory() -> Result<Self> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(connection: Connection) -> Result<Self> {
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(Self { connection })
+    }
+
+    /// Finds the units which expire before a given time, across all inventories a user can read
+    ///
+    /// Units only get an expiry once they are opened (it is their opening plus their `use_up_after`),
+    /// so units which were never opened are not found, no matter how long they have been stored.
+    pub fn expiring_before(
+        &self,
+        user_uuid: &Uuid,
+        before: &Timestamp,
+    ) -> Result<Vec<ExpiringUnit>> {
+        let mut statement = self.connection.prepare(
+            "SELECT u.inventory_uuid, u.item_uuid, i.name, u.uuid, u.name, u.percent_left, u.expires_on
+            FROM units u JOIN items i ON i.uuid = u.item_uuid
+            WHERE u.expires_on IS NOT NULL AND u.expires_on < ?2
+            AND u.inventory_uuid IN (SELECT inventory_uuid FROM members WHERE user_uuid = ?1)
+            ORDER BY u.expires_on",
+        )?;
+
+        let rows = statement
+            .query_map(
+                params![user_uuid.to_string(), format_timestamp(before)],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, String>(3)?,
+                        row.get::<_, String>(4)?,
+                        row.get::<_, f64>(5)?,
+                        row.get::<_, String>(6)?,
+                    ))
+                },
+            )?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut units = vec![];
+
+        for (
+            inventory_uuid,
+            item_uuid,
+            item_name,
+            unit_uuid,
+            unit_name,
+            percent_left,
+            expires_on,
+        ) in rows
+        {
+            units.push(ExpiringUnit {
+                inventory_uuid: Uuid::parse_str(&inventory_uuid)?,
+                item_uuid: Uuid::parse_str(&item_uuid)?,
+                item_name,
+                unit_uuid: Uuid::parse_str(&unit_uuid)?,
+                unit_name,
+                percent_left,
+                expires_on: DateTime::parse_from_rfc3339(&expires_on)?.with_timezone(&Utc),
+            });
+        }
+
+        Ok(units)
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn inventories(&self) -> Result<Vec<Uuid>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT DISTINCT inventory_uuid FROM events ORDER BY inventory_uuid")?;
+
+        let uuids = statement
+            .query_map(params![], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(uuids
+            .iter()
+            .map(|uuid| Uuid::parse_str(uuid))
+            .collect::<Result<_, _>>()?)
+    }
+
+    fn load<'a>(&self, inventory_uuid: &Uuid) -> Result<Vec<ProjectionEvent<'a>>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT payload FROM events WHERE inventory_uuid = ?1 ORDER BY seq")?;
+
+        let payloads = statement
+            .query_map(params![inventory_uuid.to_string()], |row| {
+                row.get::<_, Vec<u8>>(0)
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        payloads
+            .iter()
+            .map(|payload| decode_event(payload))
+            .collect()
+    }
+
+    fn append(&mut self, inventory_uuid: &Uuid, events: &[ProjectionEvent<'_>]) -> Result<()> {
+        // Append the events and update the projections atomically
+        let transaction = self.connection.transaction()?;
+        let inventory = inventory_uuid.to_string();
+
+        let mut seq: i64 = transaction.query_row(
+            "SELECT COALESCE(MAX(seq) + 1, 0) FROM events WHERE inventory_uuid = ?1",
+            params![inventory],
+            |row| row.get(0),
+        )?;
+
+        let mut newest: Option<String> = transaction
+            .query_row(
+                "SELECT newest FROM materialized WHERE inventory_uuid = ?1",
+                params![inventory],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        // A log which was stored before the newest event was kept track of is materialized again
+        let mut rematerialize = newest.is_none() && seq > 0;
+
+        for event in events {
+            transaction.execute(
+                "INSERT INTO events (inventory_uuid, seq, payload) VALUES (?1, ?2, ?3)",
+                params![inventory, seq, encode_event(event)?],
+            )?;
+            seq += 1;
+
+            // An older event (e.g. a merged one) can change what the newer ones did
+            let timestamp = format_timestamp(event.get_timestamp());
+            match &newest {
+                Some(newest) if timestamp < *newest => rematerialize = true,
+                _ => newest = Some(timestamp),
+            }
+
+            if !rematerialize {
+                apply_event(&transaction, &inventory, event)?;
+            }
+        }
+
+        if rematerialize {
+            materialize(&transaction, &inventory)?;
+        } else if let Some(newest) = newest {
+            set_newest(&transaction, &inventory, &newest)?;
+        }
+
+        transaction.commit()?;
+
+        Ok(())
+    }
+}
+
+/// Updates the materialized tables with an event
+fn apply_event(transaction: &Transaction, inventory: &str, event: &ProjectionEvent) -> Result<()> {
+    match (event.get_operation(), event.get_data().as_ref()) {
+        (CRUD::Create, ProjectionEntry::Inventory(i))
+        | (CRUD::Update, ProjectionEntry::Inventory(i)) => upsert_inventory(transaction, i),
+        (CRUD::Delete, ProjectionEntry::Inventory(_)) => clear_inventory(transaction, inventory),
+        (CRUD::Create, ProjectionEntry::Item(i)) | (CRUD::Update, ProjectionEntry::Item(i)) => {
+            upsert_item(transaction, i)
+        }
+        (CRUD::Delete, ProjectionEntry::Item(i)) => {
+            let uuid = i.uuid().to_string();
+            transaction.execute("DELETE FROM units WHERE item_uuid = ?1", params![uuid])?;
+            transaction.execute("DELETE FROM items WHERE uuid = ?1", params![uuid])?;
+
+            Ok(())
+        }
+        (CRUD::Create, ProjectionEntry::Unit(u)) | (CRUD::Update, ProjectionEntry::Unit(u)) => {
+            upsert_unit(transaction, inventory, u)
+        }
+        (CRUD::Delete, ProjectionEntry::Unit(u)) => {
+            transaction.execute(
+                "DELETE FROM units WHERE uuid = ?1",
+                params![u.uuid().to_string()],
+            )?;
+
+            Ok(())
+        }
+        // Shopping lists, recipes and notification rules aren't materialized
+        (_, ProjectionEntry::ShoppingEntry(_))
+        | (_, ProjectionEntry::Recipe(_))
+        | (_, ProjectionEntry::Rule(_))
+        | (_, ProjectionEntry::NotificationReceipt(_)) => Ok(()),
+    }
+}
+
+/// Materializes the whole log of an inventory again, ordered by timestamp (like the projection)
+fn materialize(transaction: &Transaction, inventory: &str) -> Result<()> {
+    let mut statement =
+        transaction.prepare("SELECT payload FROM events WHERE inventory_uuid = ?1 ORDER BY seq")?;
+    let payloads = statement
+        .query_map(params![inventory], |row| row.get::<_, Vec<u8>>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut events = payloads
+        .iter()
+        .map(|payload| decode_event(payload))
+        .collect::<Result<Vec<ProjectionEvent>>>()?;
+
+    // The sort is stable, so events with the same timestamp stay in the order they were appended in
+    events.sort_by(|a, b| a.get_timestamp().cmp(b.get_timestamp()));
+
+    clear_inventory(transaction, inventory)?;
+
+    for event in &events {
+        apply_event(transaction, inventory, event)?;
+    }
+
+    match events.last() {
+        Some(last) => set_newest(
+            transaction,
+            inventory,
+            &format_timestamp(last.get_timestamp()),
+        ),
+        None => Ok(()),
+    }
+}
+
+/// Remembers the timestamp of the newest event applied to the tables of an inventory
+fn set_newest(transaction: &Transaction, inventory: &str, newest: &str) -> Result<()> {
+    transaction.execute(
+        "INSERT OR REPLACE INTO materialized (inventory_uuid, newest) VALUES (?1, ?2)",
+        params![inventory, newest],
+    )?;
+
+    Ok(())
+}
+
+/// Removes an inventory and everything in it from the materialized tables
+fn clear_inventory(transaction: &Transaction, inventory: &str) -> Result<()> {
+    for table in &["units", "items", "members"] {
+        transaction.execute(
+            &format!("DELETE FROM {} WHERE inventory_uuid = ?1", table),
+            params![inventory],
+        )?;
+    }
+    transaction.execute(
+        "DELETE FROM inventories WHERE uuid = ?1",
+        params![inventory],
+    )?;
+
+    Ok(())
+}
+
+fn upsert_inventory(transaction: &Transaction, inventory: &Inventory) -> Result<()> {
+    let uuid = inventory.uuid().to_string();
+
+    transaction.execute(
+        "INSERT OR REPLACE INTO inventories (uuid, name, owner) VALUES (?1, ?2, ?3)",
+        params![uuid, inventory.name(), inventory.owner().to_string()],
+    )?;
+
+    // Replace the members of the inventory
+    transaction.execute(
+        "DELETE FROM members WHERE inventory_uuid = ?1",
+        params![uuid],
+    )?;
+
+    let roles = [
+        ("owner", std::slice::from_ref(inventory.owner())),
+        ("admin", inventory.admins().as_slice()),
+        ("write", inventory.writables().as_slice()),
+        ("read", inventory.readables().as_slice()),
+    ];
+
+    for (role, users) in &roles {
+        for user in users.iter() {
+            transaction.execute(
+                "INSERT OR IGNORE INTO members (inventory_uuid, user_uuid, role) VALUES (?1, ?2, ?3)",
+                params![uuid, user.to_string(), role],
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+fn upsert_item(transaction: &Transaction, item: &Item) -> Result<()> {
+    transaction.execute(
+        "INSERT OR REPLACE INTO items (uuid, inventory_uuid, name, ean, created_on) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            item.uuid().to_string(),
+            item.inventory_uuid().to_string(),
+            item.name(),
+            item.ean().as_ref().map(Ean::as_str),
+            format_timestamp(item.created_on()),
+        ],
+    )?;
+
+    Ok(())
+}
+
+fn upsert_unit(transaction: &Transaction, inventory: &str, unit: &Unit) -> Result<()> {
+    transaction.execute(
+        "INSERT OR REPLACE INTO units (uuid, item_uuid, inventory_uuid, name, percent_left, created_on, opened_on, expires_on)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            unit.uuid().to_string(),
+            unit.item_uuid().to_string(),
+            inventory,
+            unit.name(),
+            unit.percent_left(),
+            format_timestamp(unit.created_on()),
+            unit.opened_on().as_ref().map(format_timestamp),
+            unit.expires_on().as_ref().map(format_timestamp),
+        ],
+    )?;
+
+    Ok(())
+}
+
+/// Formats timestamps uniformly, so they can be compared as strings in queries
+fn format_timestamp(timestamp: &Timestamp) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::store::InventoryHandle;
+    use chrono::Duration as ChronoDuration;
+    use std::{sync::Arc, thread, time::Duration};
+
+    /// Makes an inventory with an opened and an unopened unit (both used up a day after opening)
+    fn pantry(owner: Uuid) -> (InventoryHandle<'static>, Uuid) {
+        let mut handle = InventoryHandle::new("Pantry".to_string(), owner);
+
+        let item = Item::new(&Arc::new((*handle).clone()), "Milk".to_string(), None);
+        handle.create_item(item.clone()).unwrap();
+
+        let item = Arc::new(item);
+        let day = Some(Duration::from_secs(86400));
+        let opened = Unit::new(&item, day, "Opened".to_string(), 100.0);
+        let sealed = Unit::new(&item, day, "Sealed".to_string(), 100.0);
+        handle.create_unit(opened.clone()).unwrap();
+        handle.create_unit(sealed).unwrap();
+        handle.consume_unit(opened.uuid(), 25.0).unwrap();
+
+        (handle, *opened.uuid())
+    }
+
+    #[test]
+    fn appends_and_loads_logs() {
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        let (mut handle, _) = pantry(Uuid::new_v4());
+        handle.flush(&mut storage).unwrap();
+
+        assert_eq!(storage.inventories().unwrap(), vec![*handle.uuid()]);
+
+        let events = storage.load(handle.uuid()).unwrap();
+        assert_eq!(events.len(), handle.get_projector().get_events().len());
+
+        let loaded = InventoryHandle::from_events(events).unwrap();
+        assert_eq!(loaded.items().len(), 1);
+        assert_eq!(loaded.items().iter().next().unwrap().units().len(), 2);
+    }
+
+    #[test]
+    fn finds_opened_units_expiring_soon() {
+        let owner = Uuid::new_v4();
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        let (mut handle, opened) = pantry(owner);
+        handle.flush(&mut storage).unwrap();
+
+        let soon = Utc::now() + ChronoDuration::days(2);
+        let expiring = storage.expiring_before(&owner, &soon).unwrap();
+
+        // The sealed unit has no expiry yet
+        assert_eq!(expiring.len(), 1);
+        assert_eq!(expiring[0].unit_uuid, opened);
+        assert_eq!(expiring[0].percent_left, 75.0);
+
+        // Nothing expires within the hour, and strangers see nothing at all
+        let now = Utc::now() + ChronoDuration::hours(1);
+        assert!(storage.expiring_before(&owner, &now).unwrap().is_empty());
+        assert!(storage
+            .expiring_before(&Uuid::new_v4(), &soon)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn removes_deleted_units_and_items() {
+        let owner = Uuid::new_v4();
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        let (mut handle, _) = pantry(owner);
+        let item = handle.items().iter().next().unwrap().clone();
+        handle.delete_item(item).unwrap();
+        handle.flush(&mut storage).unwrap();
+
+        let soon = Utc::now() + ChronoDuration::days(2);
+        assert!(storage.expiring_before(&owner, &soon).unwrap().is_empty());
+    }
+
+    #[test]
+    fn materializes_merged_events_in_the_order_of_their_timestamps() {
+        let owner = Uuid::new_v4();
+        let mut storage = SqliteStorage::open_in_memory().unwrap();
+        let (mut handle, _) = pantry(owner);
+        handle.flush(&mut storage).unwrap();
+
+        // The older rename arrives after the newer one was stored
+        let item = handle.items().iter().next().unwrap().clone();
+        let older = item.clone().update_name("Oat milk".to_string());
+        thread::sleep(Duration::from_millis(5));
+        handle
+            .merge(item.update_name("Whole milk".to_string()))
+            .unwrap();
+        handle.flush(&mut storage).unwrap();
+        handle.merge(older).unwrap();
+        handle.flush(&mut storage).unwrap();
+
+        let soon = Utc::now() + ChronoDuration::days(2);
+        let expiring = storage.expiring_before(&owner, &soon).unwrap();
+        assert_eq!(expiring.len(), 1);
+        assert_eq!(expiring[0].item_name, "Whole milk");
+        assert_eq!(
+            handle.items().iter().next().unwrap().name(),
+            &expiring[0].item_name
+        );
+    }
+}
@@ -0,0 +1,520 @@
+use crate::{
+    events::{
+        schema::{ENTRY_VERSION, STORE_VERSION},
+        store::{InventoryHandle, ProjectionEvent, Store},
+    },
+    Timestamp,
+};
+use anyhow::{anyhow, bail, Result};
+use libocc::events::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Read, Write},
+};
+use uuid::Uuid;
+
+/// The marker identifying archive files
+const ARCHIVE_FORMAT: &str = "sfi-archive";
+
+/// The current format version of archives
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// What to do with archived inventories which already exist in the store
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImportMode {
+    /// Don't import anything if any inventory exists already
+    Reject,
+
+    /// Add the archived events which are missing from the existing inventory
+    Merge,
+
+    /// Import the archived inventory as a copy, with fresh UUIDs for it and everything in it
+    Rename,
+}
+
+/// The description of the contents of an archive
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Manifest {
+    /// The timestamp the archive was made on
+    pub created_on: Timestamp,
+
+    /// The store format version of the library which made the archive
+    pub store_version: u32,
+
+    /// The event payload format version of the library which made the archive
+    pub entry_version: u32,
+
+    /// The archived inventories
+    pub inventories: Vec<ManifestEntry>,
+}
+
+/// The description of one archived inventory
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ManifestEntry {
+    /// The UUID of the inventory
+    pub uuid: Uuid,
+
+    /// The name of the inventory (when it was archived)
+    pub name: String,
+
+    /// The number of events in the log of the inventory
+    pub events: usize,
+
+    /// The SHA-256 checksum of the event log (hex encoded)
+    pub sha256: String,
+}
+
+/// An archive file
+#[derive(Deserialize, Serialize)]
+struct ArchiveFile {
+    format: String,
+    format_version: u32,
+    manifest: Manifest,
+    inventories: Vec<ArchivedInventory>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ArchivedInventory {
+    uuid: Uuid,
+
+    /// The complete event log
+    events: Value,
+}
+
+/// The result of an import
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImportSummary {
+    /// The inventories which were added to the store
+    pub created: Vec<Uuid>,
+
+    /// The inventories which existed already and got the missing events
+    pub merged: Vec<Uuid>,
+
+    /// The inventories which existed already and were imported as copies (the old and the new UUID)
+    pub renamed: Vec<(Uuid, Uuid)>,
+}
+
+/// A verified archive, ready to be imported
+#[derive(Clone, Debug)]
+pub struct Archive {
+    manifest: Manifest,
+    inventories: Vec<(Uuid, Vec<ProjectionEvent<'static>>)>,
+}
+
+/// Writes every inventory of a store with its complete event log into an archive
+pub fn export_archive(store: &Store<'_>, writer: impl Write) -> Result<()> {
+    let mut manifest = Manifest {
+        created_on: Utc::now(),
+        store_version: STORE_VERSION,
+        entry_version: ENTRY_VERSION,
+        inventories: vec![],
+    };
+    let mut inventories = vec![];
+
+    for handle in store.iter() {
+        let events = handle.get_projector().get_events();
+        let value = serde_json::to_value(events)?;
+
+        manifest.inventories.push(ManifestEntry {
+            uuid: *handle.uuid(),
+            name: handle.name().clone(),
+            events: events.len(),
+            sha256: checksum(&value)?,
+        });
+        inventories.push(ArchivedInventory {
+            uuid: *handle.uuid(),
+            events: value,
+        });
+    }
+
+    serde_json::to_writer(
+        writer,
+        &ArchiveFile {
+            format: ARCHIVE_FORMAT.to_string(),
+            format_version: ARCHIVE_VERSION,
+            manifest,
+            inventories,
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Reads an archive and verifies its integrity
+pub fn read_archive(reader: impl Read) -> Result<Archive> {
+    let file: ArchiveFile = serde_json::from_reader(reader)?;
+
+    if file.format != ARCHIVE_FORMAT {
+        bail!("The file is not an sfi archive");
+    }
+
+    if file.format_version > ARCHIVE_VERSION {
+        bail!(
+            "The archive has format version {}, but only versions up to {} are supported",
+            file.format_version,
+            ARCHIVE_VERSION
+        );
+    }
+
+    if file.manifest.inventories.len() != file.inventories.len() {
+        bail!("The manifest doesn't match the contents of the archive");
+    }
+
+    // A second copy of an inventory would only fail halfway through an import
+    let mut listed = HashSet::new();
+    for entry in &file.manifest.inventories {
+        if !listed.insert(entry.uuid) {
+            bail!("The archive contains the inventory {} twice", entry.uuid);
+        }
+    }
+
+    let mut inventories = vec![];
+
+    for entry in &file.manifest.inventories {
+        let archived = file
+            .inventories
+            .iter()
+            .find(|i| i.uuid == entry.uuid)
+            .ok_or_else(|| anyhow!("The inventory {} is missing from the archive", entry.uuid))?;
+
+        if checksum(&archived.events)? != entry.sha256 {
+            bail!("The checksum of the inventory {} doesn't match", entry.uuid);
+        }
+
+        // Old event payloads get migrated while reading them
+        let events: Vec<ProjectionEvent<'static>> =
+            serde_json::from_value(archived.events.clone())?;
+
+        if events.len() != entry.events {
+            bail!(
+                "The number of events of the inventory {} doesn't match",
+                entry.uuid
+            );
+        }
+
+        // The events must make up a consistent inventory with the expected UUID
+        if InventoryHandle::from_events(events.clone())?.uuid() != &entry.uuid {
+            bail!(
+                "The events of the inventory {} belong to another inventory",
+                entry.uuid
+            );
+        }
+
+        inventories.push((entry.uuid, events));
+    }
+
+    Ok(Archive {
+        manifest: file.manifest,
+        inventories,
+    })
+}
+
+impl Archive {
+    /// The description of the contents of the archive
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// The archived inventories which already exist in a store
+    pub fn collisions(&self, store: &Store<'_>) -> Vec<Uuid> {
+        self.inventories
+            .iter()
+            .map(|(uuid, _)| *uuid)
+            .filter(|uuid| store.inventory(uuid).is_some())
+            .collect()
+    }
+
+    /// Imports the archived inventories into a store
+    ///
+    /// The store is only changed if every inventory can be imported.
+    pub fn import_into(self, store: &mut Store<'_>, mode: ImportMode) -> Result<ImportSummary> {
+        let collisions = self.collisions(store);
+
+        if mode == ImportMode::Reject && !collisions.is_empty() {
+            bail!(
+                "The store already contains {} of the archived inventories",
+                collisions.len()
+            );
+        }
+
+        // Prepare everything first, so a failure leaves the store untouched
+        let mut summary = ImportSummary::default();
+        let mut created = vec![];
+        let mut merged = vec![];
+
+        for (uuid, events) in self.inventories {
+            if !collisions.contains(&uuid) {
+                created.push(InventoryHandle::from_events(events)?);
+                summary.created.push(uuid);
+            } else if mode == ImportMode::Merge {
+                // The copy has no subscribers, so its changes are collected and reported on commit
+                let mut handle = store
+                    .inventory(&uuid)
+                    .ok_or_else(|| anyhow!("Inventory not found"))?
+                    .clone();
+                let (_, changes) = handle.subscribe_channel();
+
+                merge_missing(&mut handle, events)?;
+                merged.push((handle, changes.try_iter().collect::<Vec<_>>()));
+                summary.merged.push(uuid);
+            } else {
+                let handle = InventoryHandle::from_events(renamed(events)?)?;
+                summary.renamed.push((uuid, *handle.uuid()));
+                created.push(handle);
+            }
+        }
+
+        for (handle, changes) in merged {
+            let target = store
+                .inventory_mut(handle.uuid())
+                .ok_or_else(|| anyhow!("Inventory not found"))?;
+            target.commit(handle, &changes);
+        }
+
+        for handle in created {
+            store.add_inventory(handle)?;
+        }
+
+        Ok(summary)
+    }
+}
+
+/// Merges the events which aren't in the log of an inventory yet
+fn merge_missing(
+    handle: &mut InventoryHandle<'_>,
+    events: Vec<ProjectionEvent<'static>>,
+) -> Result<()> {
+    let known = handle
+        .get_projector()
+        .get_events()
+        .iter()
+        .map(serde_json::to_string)
+        .collect::<Result<HashSet<_>, _>>()?;
+
+    for event in events {
+        if !known.contains(&serde_json::to_string(&event)?) {
+            handle.merge(event)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Assigns fresh UUIDs to the inventory and everything in it (references are updated too)
+fn renamed(events: Vec<ProjectionEvent<'static>>) -> Result<Vec<ProjectionEvent<'static>>> {
+    let mut value = serde_json::to_value(events)?;
+
+    let mut uuids = HashMap::new();
+    collect_uuids(&value, &mut uuids);
+    replace_uuids(&mut value, &uuids);
+
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Finds the UUIDs of the entries in serialized events and makes up new ones for them
+fn collect_uuids(value: &Value, uuids: &mut HashMap<String, String>) {
+    match value {
+        Value::Object(map) => {
+            // The versioned envelope of an entry: `{"version": _, "entry": {"Variant": {"uuid": _, ...}}}`
+            if let Some(Value::Object(entry)) = map.get("entry") {
+                for data in entry.values() {
+                    if let Some(uuid) = data.get("uuid").and_then(Value::as_str) {
+                        uuids
+                            .entry(uuid.to_string())
+                            .or_insert_with(|| Uuid::new_v4().to_string());
+                    }
+                }
+            }
+
+            for child in map.values() {
+                collect_uuids(child, uuids);
+            }
+        }
+        Value::Array(values) => {
+            for child in values {
+                collect_uuids(child, uuids);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Replaces every string which is one of the renamed UUIDs
+fn replace_uuids(value: &mut Value, uuids: &HashMap<String, String>) {
+    match value {
+        Value::String(string) => {
+            if let Some(uuid) = uuids.get(string.as_str()) {
+                *string = uuid.clone();
+            }
+        }
+        Value::Object(map) => {
+            for child in map.values_mut() {
+                replace_uuids(child, uuids);
+            }
+        }
+        Value::Array(values) => {
+            for child in values {
+                replace_uuids(child, uuids);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The hex encoded SHA-256 checksum of a JSON value
+fn checksum(value: &Value) -> Result<String> {
+    let digest = Sha256::digest(&serde_json::to_vec(value)?);
+
+    Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{ChangeKind, Item, Unit};
+    use std::sync::Arc;
+
+    /// Makes a store with an inventory containing an item with a unit
+    fn pantry() -> (Store<'static>, Uuid, Item) {
+        let mut store = Store::new();
+        let inventory_uuid = store.make_inventory("Pantry".to_string(), Uuid::new_v4());
+
+        let handle = store.inventory_mut(&inventory_uuid).unwrap();
+        let item = Item::new(&Arc::new((**handle).clone()), "Rice".to_string(), None);
+        handle.create_item(item.clone()).unwrap();
+        let unit = Unit::new(&Arc::new(item.clone()), None, "1 kg".to_string(), 100.0);
+        handle.create_unit(unit).unwrap();
+
+        (store, inventory_uuid, item)
+    }
+
+    /// Exports a store into a serialized archive
+    fn archived(store: &Store<'_>) -> Value {
+        let mut buffer = vec![];
+        export_archive(store, &mut buffer).unwrap();
+
+        serde_json::from_slice(&buffer).unwrap()
+    }
+
+    fn read(archive: &Value) -> Result<Archive> {
+        read_archive(&serde_json::to_vec(archive).unwrap()[..])
+    }
+
+    #[test]
+    fn rejects_inventories_listed_twice() {
+        let (store, _, _) = pantry();
+        let mut archive = archived(&store);
+        read(&archive).unwrap();
+
+        let entry = archive["manifest"]["inventories"][0].clone();
+        archive["manifest"]["inventories"]
+            .as_array_mut()
+            .unwrap()
+            .push(entry);
+        let inventory = archive["inventories"][0].clone();
+        archive["inventories"]
+            .as_array_mut()
+            .unwrap()
+            .push(inventory);
+
+        let error = read(&archive).unwrap_err();
+        assert!(error.to_string().contains("twice"));
+    }
+
+    #[test]
+    fn rejects_damaged_inventories() {
+        let (store, _, _) = pantry();
+        let mut archive = archived(&store);
+        archive["manifest"]["inventories"][0]["sha256"] = Value::String("0".repeat(64));
+
+        let error = read(&archive).unwrap_err();
+        assert!(error.to_string().contains("checksum"));
+    }
+
+    #[test]
+    fn rejects_collisions_without_changing_the_store() {
+        let (mut store, inventory_uuid, _) = pantry();
+        let archive = read(&archived(&store)).unwrap();
+        assert_eq!(archive.collisions(&store), vec![inventory_uuid]);
+
+        let events = store
+            .inventory(&inventory_uuid)
+            .unwrap()
+            .get_projector()
+            .get_events()
+            .len();
+        assert!(archive.import_into(&mut store, ImportMode::Reject).is_err());
+        assert_eq!(store.len(), 1);
+        assert_eq!(
+            store
+                .inventory(&inventory_uuid)
+                .unwrap()
+                .get_projector()
+                .get_events()
+                .len(),
+            events
+        );
+    }
+
+    #[test]
+    fn imports_collisions_as_copies_with_fresh_uuids() {
+        let (mut store, inventory_uuid, item) = pantry();
+        let archive = read(&archived(&store)).unwrap();
+
+        let summary = archive.import_into(&mut store, ImportMode::Rename).unwrap();
+        assert_eq!(summary.renamed.len(), 1);
+        let (old_uuid, new_uuid) = summary.renamed[0];
+        assert_eq!(old_uuid, inventory_uuid);
+        assert_ne!(new_uuid, inventory_uuid);
+        assert_eq!(store.len(), 2);
+
+        // Everything in the copy is renamed, and the references follow
+        let copy = store.inventory(&new_uuid).unwrap();
+        copy.verify_projection().unwrap();
+        let copied_item = copy.items().iter().next().unwrap();
+        assert_eq!(copied_item.name(), item.name());
+        assert_ne!(copied_item.uuid(), item.uuid());
+        assert_eq!(copied_item.inventory_uuid(), &new_uuid);
+
+        let copied_unit = copied_item.units().iter().next().unwrap();
+        assert_eq!(copied_unit.item_uuid(), copied_item.uuid());
+        assert!(store
+            .inventory(&inventory_uuid)
+            .unwrap()
+            .unit(copied_unit.uuid())
+            .is_none());
+    }
+
+    #[test]
+    fn reports_merged_changes_to_the_subscribers_of_the_store() {
+        let mut store = Store::new();
+        let inventory_uuid = store.make_inventory("Pantry".to_string(), Uuid::new_v4());
+        let mut earlier = store.clone();
+
+        let handle = store.inventory_mut(&inventory_uuid).unwrap();
+        let item = Item::new(&Arc::new((**handle).clone()), "Rice".to_string(), None);
+        handle.create_item(item.clone()).unwrap();
+
+        let mut buffer = vec![];
+        export_archive(&store, &mut buffer).unwrap();
+        let archive = read_archive(&buffer[..]).unwrap();
+
+        let (_, changes) = earlier.subscribe_channel();
+        archive
+            .import_into(&mut earlier, ImportMode::Merge)
+            .unwrap();
+
+        assert!(earlier
+            .inventory(&inventory_uuid)
+            .unwrap()
+            .item(item.uuid())
+            .is_some());
+        assert!(changes.try_iter().any(|c| c.kind
+            == ChangeKind::ItemAdded {
+                item_uuid: *item.uuid()
+            }));
+    }
+}
@@ -0,0 +1,407 @@
+use crate::{
+    events::{store::InventoryHandle, Ean, Inventory, Item, Unit},
+    Timestamp,
+};
+use anyhow::{anyhow, bail, Result};
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{Read, Write},
+    sync::Arc,
+    time::Duration,
+};
+use uuid::Uuid;
+
+/// One row of a CSV file (one unit)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+struct Row {
+    item_name: String,
+    ean: Option<String>,
+    unit_name: String,
+    percent_left: f64,
+
+    /// RFC 3339, empty if the unit wasn't opened yet
+    opened_on: Option<String>,
+
+    use_up_after_days: Option<f64>,
+}
+
+/// A row which couldn't be imported
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct RowError {
+    /// The line of the row in the file (the header is line 1)
+    pub line: u64,
+
+    /// What is wrong with the row
+    pub message: String,
+}
+
+/// The result of an import (or of a dry run, in which case nothing was changed)
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+pub struct ImportReport {
+    /// The number of new items
+    pub items_created: usize,
+
+    /// The number of units added to items which already existed
+    pub units_merged: usize,
+
+    /// The number of new units
+    pub units_created: usize,
+
+    /// The rows which were skipped
+    pub errors: Vec<RowError>,
+}
+
+/// A row which passed validation
+struct ValidRow {
+    item_name: String,
+    ean: Option<Ean>,
+    unit_name: String,
+    percent_left: f64,
+    opened_on: Option<Timestamp>,
+    use_up_after: Option<Duration>,
+}
+
+/// Writes the units of an inventory as CSV (one row per unit, with a header line)
+pub fn export_csv(inventory: &Inventory, writer: impl Write) -> Result<()> {
+    let mut writer = ::csv::Writer::from_writer(writer);
+
+    for item in inventory.items() {
+        for unit in item.units() {
+            writer.serialize(Row {
+                item_name: item.name().clone(),
+                ean: item.ean().as_ref().map(|ean| ean.short_form().to_string()),
+                unit_name: unit.name().clone(),
+                percent_left: *unit.percent_left(),
+                opened_on: unit
+                    .opened_on()
+                    .as_ref()
+                    .map(|opened_on| opened_on.to_rfc3339()),
+                use_up_after_days: unit
+                    .use_up_after()
+                    .as_ref()
+                    .map(|d| d.as_secs_f64() / (24.0 * 60.0 * 60.0)),
+            })?;
+        }
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Imports units from CSV, creating the items which don't exist yet
+///
+/// Rows are merged into existing items by EAN, or else by name (unless the EANs differ).
+/// Invalid rows are skipped and reported, the valid ones are imported (unless `dry_run` is set).
+pub fn import_csv(
+    handle: &mut InventoryHandle<'_>,
+    reader: impl Read,
+    dry_run: bool,
+) -> Result<ImportReport> {
+    let mut reader = ::csv::Reader::from_reader(reader);
+    let headers = reader.headers()?.clone();
+
+    let mut report = ImportReport::default();
+    let inventory = Arc::new((**handle).clone());
+
+    // The items created by this import (in a dry run, only their UUIDs are made up)
+    let mut created: Vec<(Uuid, String, Option<Ean>)> = vec![];
+
+    for record in reader.records() {
+        let record = record?;
+        let line = record.position().map_or(0, |p| p.line());
+
+        let row = match record
+            .deserialize::<Row>(Some(&headers))
+            .map_err(anyhow::Error::from)
+            .and_then(validate)
+        {
+            Ok(row) => row,
+            Err(e) => {
+                report.errors.push(RowError {
+                    line,
+                    message: e.to_string(),
+                });
+                continue;
+            }
+        };
+
+        // Find the item to add the unit to: EAN matches come before name matches (no matter where
+        // the item comes from), and items created by this import before the ones which existed
+        let found = created
+            .iter()
+            .map(|(uuid, name, ean)| (false, *uuid, name, ean))
+            .chain(
+                handle
+                    .items()
+                    .iter()
+                    .map(|i| (true, *i.uuid(), i.name(), i.ean())),
+            )
+            .filter_map(|(existed, uuid, name, ean)| {
+                fit(name, ean, &row).map(|fit| ((fit, existed), uuid))
+            })
+            .min_by_key(|(rank, _)| *rank);
+
+        let item_uuid = match found {
+            Some(((_, false), item_uuid)) => item_uuid,
+            Some(((_, true), item_uuid)) => {
+                report.units_merged += 1;
+                item_uuid
+            }
+            None => {
+                let item = Item::new(&inventory, row.item_name.clone(), row.ean.clone());
+                let item_uuid = *item.uuid();
+
+                if !dry_run {
+                    handle.create_item(item)?;
+                }
+
+                report.items_created += 1;
+                created.push((item_uuid, row.item_name.clone(), row.ean.clone()));
+                item_uuid
+            }
+        };
+
+        report.units_created += 1;
+
+        if dry_run {
+            continue;
+        }
+
+        let item = handle.item(&item_uuid).cloned().map(Arc::new);
+
+        if let Some(item) = item {
+            let mut unit = Unit::new(&item, row.use_up_after, row.unit_name, row.percent_left);
+            *unit.opened_on_mut() = row.opened_on;
+
+            handle.create_unit(unit)?;
+        }
+    }
+
+    Ok(report)
+}
+
+/// Checks the values of a row
+fn validate(row: Row) -> Result<ValidRow> {
+    if row.item_name.trim().is_empty() {
+        bail!("The item name is missing");
+    }
+
+    if !(0.0..=100.0).contains(&row.percent_left) {
+        bail!("The percentage left must be between 0 and 100");
+    }
+
+    let ean = match row.ean.as_deref().map(str::trim) {
+        None | Some("") => None,
+        Some(code) => Some(Ean::parse(code)?),
+    };
+
+    let opened_on = match row.opened_on.as_deref().map(str::trim) {
+        None | Some("") => None,
+        Some(opened_on) => Some(DateTime::parse_from_rfc3339(opened_on)?.into()),
+    };
+
+    let use_up_after = row.use_up_after_days.map(duration_from_days).transpose()?;
+
+    let unit_name = if row.unit_name.trim().is_empty() {
+        row.item_name.trim().to_string()
+    } else {
+        row.unit_name.trim().to_string()
+    };
+
+    Ok(ValidRow {
+        item_name: row.item_name.trim().to_string(),
+        ean,
+        unit_name,
+        percent_left: row.percent_left,
+        opened_on,
+        use_up_after,
+    })
+}
+
+/// Converts a number of days into a duration (failing for negative and too long durations)
+pub fn duration_from_days(days: f64) -> Result<Duration> {
+    if days < 0.0 {
+        bail!("The use-up-after duration can't be negative");
+    }
+
+    Duration::try_from_secs_f64(days * 24.0 * 60.0 * 60.0)
+        .map_err(|_| anyhow!("The use-up-after duration is out of range: {} days", days))
+}
+
+/// How well a row fits an item: the EANs match (0), or the names match and the EANs don't contradict each other (1)
+fn fit(name: &str, ean: &Option<Ean>, row: &ValidRow) -> Option<u8> {
+    match (ean, &row.ean) {
+        (Some(ean), Some(row_ean)) if ean == row_ean => Some(0),
+        (Some(_), Some(_)) => None,
+        _ if name.trim().eq_ignore_ascii_case(&row.item_name) => Some(1),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Utc;
+
+    const DAY: u64 = 24 * 60 * 60;
+    const HEADER: &str = "item_name,ean,unit_name,percent_left,opened_on,use_up_after_days\n";
+
+    /// Adds an item (without units) to an inventory
+    fn add_item(handle: &mut InventoryHandle<'_>, name: &str, ean: Option<&str>) -> Arc<Item> {
+        let ean = ean.map(|ean| Ean::parse(ean).unwrap());
+        let item = Item::new(&Arc::new((**handle).clone()), name.to_string(), ean);
+        handle.create_item(item.clone()).unwrap();
+
+        Arc::new(item)
+    }
+
+    /// The units of an inventory by item, without their UUIDs (to compare them across inventories)
+    fn contents(handle: &InventoryHandle<'_>) -> Vec<(String, Option<Ean>, Vec<String>)> {
+        let mut contents: Vec<_> = handle
+            .items()
+            .iter()
+            .map(|item| {
+                let mut units: Vec<_> = item
+                    .units()
+                    .iter()
+                    .map(|unit| {
+                        format!(
+                            "{} {} {:?} {:?}",
+                            unit.name(),
+                            unit.percent_left(),
+                            unit.opened_on(),
+                            unit.use_up_after()
+                        )
+                    })
+                    .collect();
+                units.sort();
+
+                (item.name().clone(), item.ean().clone(), units)
+            })
+            .collect();
+        contents.sort_by(|a, b| a.0.cmp(&b.0));
+
+        contents
+    }
+
+    #[test]
+    fn round_trips_exports() {
+        let mut handle = InventoryHandle::new("Pantry".to_string(), Uuid::new_v4());
+        let rice = add_item(&mut handle, "Rice", Some("4006381333931"));
+        let salt = add_item(&mut handle, "Salt", None);
+
+        let mut opened = Unit::new(
+            &rice,
+            Some(Duration::from_secs(30 * DAY)),
+            "1 kg bag".to_string(),
+            40.0,
+        );
+        *opened.opened_on_mut() = Some(Utc::now());
+        handle.create_unit(opened).unwrap();
+        handle
+            .create_unit(Unit::new(&rice, None, "1 kg bag".to_string(), 100.0))
+            .unwrap();
+        handle
+            .create_unit(Unit::new(&salt, None, "500 g".to_string(), 100.0))
+            .unwrap();
+
+        let mut exported = vec![];
+        export_csv(&handle, &mut exported).unwrap();
+
+        let mut copy = InventoryHandle::new("Copy".to_string(), Uuid::new_v4());
+        let report = import_csv(&mut copy, &exported[..], false).unwrap();
+
+        assert_eq!(
+            report,
+            ImportReport {
+                items_created: 2,
+                units_merged: 0,
+                units_created: 3,
+                errors: vec![],
+            }
+        );
+        assert_eq!(contents(&copy), contents(&handle));
+    }
+
+    #[test]
+    fn leaves_the_inventory_alone_in_dry_runs() {
+        let mut handle = InventoryHandle::new("Pantry".to_string(), Uuid::new_v4());
+        add_item(&mut handle, "Rice", None);
+        let csv = format!(
+            "{}Rice,,1 kg bag,100,,\nFlour,,1 kg bag,100,,\nFlour,,1 kg bag,50,,\n",
+            HEADER
+        );
+
+        let events = handle.get_projector().get_events().len();
+        let before = contents(&handle);
+
+        let dry_run = import_csv(&mut handle, csv.as_bytes(), true).unwrap();
+        assert_eq!(handle.get_projector().get_events().len(), events);
+        assert_eq!(contents(&handle), before);
+
+        // The dry run reports what the import does
+        let report = import_csv(&mut handle, csv.as_bytes(), false).unwrap();
+        assert_eq!(dry_run, report);
+        assert_eq!(report.items_created, 1);
+        assert_eq!(report.units_merged, 1);
+        assert_eq!(report.units_created, 3);
+    }
+
+    #[test]
+    fn merges_by_ean_before_name() {
+        let mut handle = InventoryHandle::new("Pantry".to_string(), Uuid::new_v4());
+        let drink = add_item(&mut handle, "Oat drink", Some("4006381333931"));
+
+        // The first row creates an item, which the second one matches by name only
+        let csv = format!(
+            "{}Oat milk,,1 l carton,100,,\nOat milk,4006381333931,1 l carton,100,,\n",
+            HEADER
+        );
+        let report = import_csv(&mut handle, csv.as_bytes(), false).unwrap();
+
+        assert_eq!(report.items_created, 1);
+        assert_eq!(report.units_merged, 1);
+        assert_eq!(handle.item(drink.uuid()).unwrap().units().len(), 1);
+
+        let milk = handle
+            .items()
+            .iter()
+            .find(|i| *i.name() == "Oat milk")
+            .unwrap();
+        assert_eq!(milk.units().len(), 1);
+
+        // Without an EAN match, the name decides (but differing EANs never match)
+        let csv = format!(
+            "{}oat milk,,1 l carton,100,,\nOat milk,96385074,1 l carton,100,,\n",
+            HEADER
+        );
+        let report = import_csv(&mut handle, csv.as_bytes(), false).unwrap();
+
+        assert_eq!(report.items_created, 0);
+        assert_eq!(report.units_merged, 2);
+        let milk = handle
+            .items()
+            .iter()
+            .find(|i| *i.name() == "Oat milk")
+            .unwrap();
+        assert_eq!(milk.units().len(), 3);
+    }
+
+    #[test]
+    fn reports_durations_out_of_range() {
+        let mut handle = InventoryHandle::new("Pantry".to_string(), Uuid::new_v4());
+        let csv = "item_name,ean,unit_name,percent_left,opened_on,use_up_after_days\n\
+                   Rice,,1 kg bag,100,,1e300\n\
+                   Rice,,1 kg bag,100,,-1\n\
+                   Rice,,1 kg bag,100,,30\n";
+
+        let report = import_csv(&mut handle, csv.as_bytes(), false).unwrap();
+        assert_eq!(report.units_created, 1);
+        assert_eq!(
+            report.errors.iter().map(|e| e.line).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+    }
+}
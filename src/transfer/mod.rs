@@ -0,0 +1,3 @@
+pub mod archive;
+#[cfg(feature = "csv")]
+pub mod csv;
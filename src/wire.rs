@@ -0,0 +1,473 @@
+use crate::events::store::{ProjectionEvent, Store};
+use anyhow::{anyhow, bail, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_cbor::Value as CborValue;
+use serde_json::Value;
+use std::{
+    convert::TryFrom,
+    io::{ErrorKind, Read, Write},
+};
+use uuid::Uuid;
+
+/// The maximum length of a single frame (protects against allocating huge buffers for damaged streams)
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+/// Encodes an event in the binary wire format
+///
+/// Payloads are CBOR encodings of the same data model as the JSON representation, with UUIDs
+/// as 16-byte strings. Field names are kept, so the format versions and migrations of the
+/// `schema` module apply to them unchanged.
+pub fn encode_event(event: &ProjectionEvent<'_>) -> Result<Vec<u8>> {
+    encode(event)
+}
+
+/// Decodes an event from the binary wire format
+pub fn decode_event<'a>(bytes: &[u8]) -> Result<ProjectionEvent<'a>> {
+    decode(bytes)
+}
+
+/// Encodes a whole store in the binary wire format
+pub fn encode_store(store: &Store<'_>) -> Result<Vec<u8>> {
+    encode(store)
+}
+
+/// Decodes a whole store from the binary wire format
+pub fn decode_store<'a>(bytes: &[u8]) -> Result<Store<'a>> {
+    decode(bytes)
+}
+
+fn encode(payload: &impl Serialize) -> Result<Vec<u8>> {
+    Ok(serde_cbor::to_vec(payload)?)
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    // Payloads are migrated as JSON values (see `schema`), so they are read through one
+    let value: CborValue = serde_cbor::from_slice(bytes)?;
+
+    Ok(serde_json::from_value(to_json(value)?)?)
+}
+
+/// Converts a decoded CBOR value into the JSON value of the same data model
+fn to_json(value: CborValue) -> Result<Value> {
+    Ok(match value {
+        CborValue::Null => Value::Null,
+        CborValue::Bool(b) => Value::Bool(b),
+        CborValue::Integer(i) => {
+            if let Ok(i) = i64::try_from(i) {
+                Value::from(i)
+            } else {
+                Value::from(
+                    u64::try_from(i).map_err(|_| anyhow!("The integer {} is too large", i))?,
+                )
+            }
+        }
+        CborValue::Float(f) => Value::from(f),
+        // Only UUIDs are serialized as byte strings (they are strings in JSON)
+        CborValue::Bytes(bytes) if bytes.len() == 16 => {
+            Value::String(Uuid::from_slice(&bytes)?.to_string())
+        }
+        CborValue::Bytes(bytes) => bail!(
+            "A byte string of {} bytes is not a UUID (which has 16 bytes)",
+            bytes.len()
+        ),
+        CborValue::Text(text) => Value::String(text),
+        CborValue::Array(values) => {
+            Value::Array(values.into_iter().map(to_json).collect::<Result<_>>()?)
+        }
+        CborValue::Map(entries) => Value::Object(
+            entries
+                .into_iter()
+                .map(|(key, value)| match key {
+                    CborValue::Text(key) => Ok((key, to_json(value)?)),
+                    key => Err(anyhow!("Invalid map key: {:?}", key)),
+                })
+                .collect::<Result<_>>()?,
+        ),
+        CborValue::Tag(_, value) => to_json(*value)?,
+        value => bail!("Unsupported CBOR value: {:?}", value),
+    })
+}
+
+/// Prefixes a payload with its length (as an unsigned LEB128 varint)
+pub fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(payload.len() + 4);
+    let mut len = payload.len();
+
+    // Write the length as an unsigned LEB128 varint
+    loop {
+        let byte = (len & 0x7f) as u8;
+        len >>= 7;
+
+        if len == 0 {
+            framed.push(byte);
+            break;
+        }
+
+        framed.push(byte | 0x80);
+    }
+
+    framed.extend_from_slice(payload);
+    framed
+}
+
+/// Writes framed events to a stream
+#[derive(Debug)]
+pub struct FrameWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> FrameWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    /// Writes an event as one frame
+    pub fn write_event(&mut self, event: &ProjectionEvent<'_>) -> Result<()> {
+        self.inner.write_all(&frame(&encode_event(event)?))?;
+
+        Ok(())
+    }
+
+    /// Flushes the underlying stream
+    pub fn flush(&mut self) -> Result<()> {
+        self.inner.flush()?;
+
+        Ok(())
+    }
+
+    /// Returns the underlying stream
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// Splits incoming bytes into frames, no matter how they are chunked on arrival
+#[derive(Clone, Debug, Default)]
+pub struct FrameDecoder {
+    /// The bytes received but not decoded yet
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds received bytes to the decoder
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Takes the next complete frame (if one has been received completely)
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>> {
+        let (len, header_len) = match read_varint(&self.buffer)? {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+
+        if self.buffer.len() - header_len < len {
+            return Ok(None);
+        }
+
+        let frame = self.buffer[header_len..header_len + len].to_vec();
+        self.buffer.drain(..header_len + len);
+
+        Ok(Some(frame))
+    }
+
+    /// Takes the next complete event (if one has been received completely)
+    pub fn next_event<'a>(&mut self) -> Result<Option<ProjectionEvent<'a>>> {
+        self.next_frame()?
+            .map(|frame| decode_event(&frame))
+            .transpose()
+    }
+
+    /// If there are bytes of an incomplete frame left over
+    pub fn has_partial_frame(&self) -> bool {
+        !self.buffer.is_empty()
+    }
+}
+
+/// Reads framed events from a blocking stream
+#[derive(Debug)]
+pub struct FrameReader<R: Read> {
+    inner: R,
+    decoder: FrameDecoder,
+}
+
+impl<R: Read> FrameReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            decoder: FrameDecoder::new(),
+        }
+    }
+
+    /// Reads the next event (or `None` at the end of the stream)
+    pub fn read_event<'a>(&mut self) -> Result<Option<ProjectionEvent<'a>>> {
+        let mut chunk = [0; 4096];
+
+        loop {
+            if let Some(event) = self.decoder.next_event()? {
+                return Ok(Some(event));
+            }
+
+            let read = match self.inner.read(&mut chunk) {
+                Ok(read) => read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            if read == 0 {
+                if self.decoder.has_partial_frame() {
+                    bail!("The stream ended in the middle of a frame");
+                }
+
+                return Ok(None);
+            }
+
+            self.decoder.feed(&chunk[..read]);
+        }
+    }
+}
+
+impl<R: Read> Iterator for FrameReader<R> {
+    type Item = Result<ProjectionEvent<'static>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_event().transpose()
+    }
+}
+
+/// Reads a varint length prefix (and returns it together with its own length)
+fn read_varint(bytes: &[u8]) -> Result<Option<(usize, usize)>> {
+    let mut len: usize = 0;
+
+    for (i, byte) in bytes.iter().enumerate() {
+        len |= usize::from(byte & 0x7f)
+            .checked_shl(7 * i as u32)
+            .filter(|_| i < 4)
+            .ok_or_else(|| anyhow!("The frame length prefix is too long"))?;
+
+        if byte & 0x80 == 0 {
+            if len > MAX_FRAME_LEN {
+                bail!("The frame is too large ({} bytes)", len);
+            }
+
+            return Ok(Some((len, i + 1)));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{store::InventoryHandle, Ean, Item, Unit};
+    use std::{io, sync::Arc};
+
+    /// Makes an inventory with an item and a unit
+    fn pantry() -> InventoryHandle<'static> {
+        let mut handle = InventoryHandle::new("Pantry".to_string(), Uuid::new_v4());
+        let ean = Some(Ean::parse("4006381333931").unwrap());
+        let item = Item::new(&Arc::new((*handle).clone()), "Oat milk".to_string(), ean);
+        handle.create_item(item.clone()).unwrap();
+
+        let unit = Unit::new(&Arc::new(item), None, "1 l carton".to_string(), 100.0);
+        handle.create_unit(unit).unwrap();
+
+        handle
+    }
+
+    /// The events of an inventory (as JSON, to compare them)
+    fn events_of(handle: &InventoryHandle<'_>) -> Vec<Value> {
+        handle
+            .get_projector()
+            .get_events()
+            .iter()
+            .map(|e| serde_json::to_value(e).unwrap())
+            .collect()
+    }
+
+    /// Frames all events of an inventory into one stream
+    fn stream_of(handle: &InventoryHandle<'_>) -> Vec<u8> {
+        let mut writer = FrameWriter::new(vec![]);
+        for event in handle.get_projector().get_events() {
+            writer.write_event(event).unwrap();
+        }
+
+        writer.into_inner()
+    }
+
+    /// A stream which hands out its bytes in chunks of varying sizes
+    struct Trickle {
+        bytes: Vec<u8>,
+        position: usize,
+        reads: usize,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            self.reads += 1;
+
+            // Every few reads are interrupted, like reads of a signalled process
+            if self.reads % 5 == 0 {
+                return Err(io::Error::new(ErrorKind::Interrupted, "interrupted"));
+            }
+
+            let len = (self.reads % 7 + 1)
+                .min(buffer.len())
+                .min(self.bytes.len() - self.position);
+            buffer[..len].copy_from_slice(&self.bytes[self.position..self.position + len]);
+            self.position += len;
+
+            Ok(len)
+        }
+    }
+
+    /// Feeds a stream into a decoder in chunks of the given sizes (repeated until it ends)
+    fn decode_in_chunks(stream: &[u8], sizes: &[usize]) -> Vec<Value> {
+        let mut decoder = FrameDecoder::new();
+        let mut decoded = vec![];
+        let mut rest = stream;
+
+        for size in sizes.iter().cycle() {
+            if rest.is_empty() {
+                break;
+            }
+
+            let (chunk, remaining) = rest.split_at((*size).min(rest.len()));
+            decoder.feed(chunk);
+            rest = remaining;
+
+            while let Some(event) = decoder.next_event().unwrap() {
+                decoded.push(serde_json::to_value(event).unwrap());
+            }
+        }
+
+        assert!(!decoder.has_partial_frame());
+        decoded
+    }
+
+    #[test]
+    fn decodes_frames_fed_byte_by_byte() {
+        let handle = pantry();
+        let stream = stream_of(&handle);
+
+        assert_eq!(decode_in_chunks(&stream, &[1]), events_of(&handle));
+    }
+
+    #[test]
+    fn decodes_frames_fed_in_uneven_chunks() {
+        let handle = pantry();
+        let stream = stream_of(&handle);
+
+        assert_eq!(
+            decode_in_chunks(&stream, &[3, 1, 7, 2, 11]),
+            events_of(&handle)
+        );
+        assert_eq!(
+            decode_in_chunks(&stream, &[stream.len()]),
+            events_of(&handle)
+        );
+    }
+
+    #[test]
+    fn reads_frames_from_a_trickling_stream() {
+        let handle = pantry();
+        let reader = FrameReader::new(Trickle {
+            bytes: stream_of(&handle),
+            position: 0,
+            reads: 0,
+        });
+
+        let read: Vec<Value> = reader
+            .map(|e| serde_json::to_value(e.unwrap()).unwrap())
+            .collect();
+        assert_eq!(read, events_of(&handle));
+    }
+
+    #[test]
+    fn fails_when_the_stream_ends_in_a_frame() {
+        let handle = pantry();
+        let mut stream = stream_of(&handle);
+        stream.truncate(stream.len() - 1);
+
+        let mut reader = FrameReader::new(&stream[..]);
+        let events = events_of(&handle);
+        for event in &events[..events.len() - 1] {
+            let read = reader.read_event().unwrap().unwrap();
+            assert_eq!(&serde_json::to_value(read).unwrap(), event);
+        }
+
+        assert!(reader.read_event().is_err());
+
+        // Without any bytes, the stream simply ends
+        assert!(FrameReader::new(&[][..]).read_event().unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_oversized_and_overlong_lengths() {
+        // MAX_FRAME_LEN + 1 as a varint
+        let mut decoder = FrameDecoder::new();
+        decoder.feed(&[0x81, 0x80, 0x80, 0x08]);
+        assert!(decoder.next_frame().is_err());
+
+        // A varint which goes on for more than four bytes
+        let mut decoder = FrameDecoder::new();
+        decoder.feed(&[0x80, 0x80, 0x80, 0x80, 0x01]);
+        assert!(decoder.next_frame().is_err());
+
+        // An incomplete (but valid) varint just needs more bytes
+        let mut decoder = FrameDecoder::new();
+        decoder.feed(&[0x80, 0x80]);
+        assert!(decoder.next_frame().unwrap().is_none());
+        decoder.feed(&[0x00]);
+        assert_eq!(decoder.next_frame().unwrap(), Some(vec![]));
+    }
+
+    #[test]
+    fn round_trips_stores() {
+        let mut store = Store::new();
+        store.add_inventory(pantry()).unwrap();
+        store.make_inventory("Freezer".to_string(), Uuid::new_v4());
+
+        let decoded = decode_store(&encode_store(&store).unwrap()).unwrap();
+
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(
+            serde_json::to_value(&decoded).unwrap(),
+            serde_json::to_value(&store).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_byte_strings_which_are_not_uuids() {
+        let uuid = Uuid::new_v4();
+        assert_eq!(
+            to_json(CborValue::Bytes(uuid.as_bytes().to_vec())).unwrap(),
+            Value::String(uuid.to_string())
+        );
+
+        assert!(to_json(CborValue::Bytes(vec![1, 2, 3])).is_err());
+
+        let encoded = serde_cbor::to_vec(&CborValue::Bytes(vec![0; 32])).unwrap();
+        assert!(decode::<Value>(&encoded).is_err());
+    }
+
+    #[test]
+    fn round_trips_events() {
+        let handle = pantry();
+
+        for event in handle.get_projector().get_events() {
+            let encoded = encode_event(event).unwrap();
+
+            assert!(encoded.len() < serde_json::to_vec(event).unwrap().len());
+            assert_eq!(
+                serde_json::to_value(decode_event(&encoded).unwrap()).unwrap(),
+                serde_json::to_value(event).unwrap()
+            );
+        }
+    }
+}
@@ -0,0 +1,205 @@
+#![cfg(feature = "cli")]
+
+use serde_json::{json, Value};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+use uuid::Uuid;
+
+/// A temporary directory with a store file, which is removed again at the end of a test
+struct Sandbox {
+    directory: PathBuf,
+}
+
+impl Sandbox {
+    fn new() -> Self {
+        let directory = std::env::temp_dir().join(format!("sfi-cli-{}", Uuid::new_v4()));
+        fs::create_dir_all(&directory).unwrap();
+
+        Self { directory }
+    }
+
+    fn path(&self, file_name: &str) -> PathBuf {
+        self.directory.join(file_name)
+    }
+
+    fn store(&self) -> PathBuf {
+        self.path("store.json")
+    }
+
+    /// Runs the binary on the store of the sandbox (with JSON output)
+    fn output(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_sfi"))
+            .arg("--store")
+            .arg(self.store())
+            .arg("--json")
+            .args(args)
+            .output()
+            .unwrap()
+    }
+
+    /// Runs a command which must succeed and returns its JSON output
+    fn run(&self, args: &[&str]) -> Value {
+        let output = self.output(args);
+        assert!(
+            output.status.success(),
+            "sfi {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stdout)
+        );
+
+        serde_json::from_slice(&output.stdout).unwrap()
+    }
+
+    /// Creates an inventory with an item and returns their UUIDs
+    fn pantry(&self) -> (String, String) {
+        let owner = Uuid::new_v4().to_string();
+        let inventory = self.run(&["create-inventory", "--name", "Pantry", "--owner", &owner]);
+        let inventory = inventory["uuid"].as_str().unwrap().to_string();
+
+        let item = self.run(&["add-item", "--inventory", &inventory, "--name", "Milk"]);
+        let item = item["uuid"].as_str().unwrap().to_string();
+
+        (inventory, item)
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.directory);
+    }
+}
+
+fn read_json(path: &Path) -> Value {
+    serde_json::from_slice(&fs::read(path).unwrap()).unwrap()
+}
+
+#[test]
+fn tracks_units_until_they_are_used_up() {
+    let sandbox = Sandbox::new();
+    let (inventory, item) = sandbox.pantry();
+
+    let unit = sandbox.run(&[
+        "add-unit",
+        "--inventory",
+        &inventory,
+        "--item",
+        &item,
+        "--use-up-after-days",
+        "2",
+    ]);
+    let unit = unit["uuid"].as_str().unwrap().to_string();
+
+    // A sealed unit doesn't expire yet
+    assert_eq!(sandbox.run(&["expiring"]), json!([]));
+
+    let consumed = sandbox.run(&[
+        "consume",
+        "--inventory",
+        &inventory,
+        "--unit",
+        &unit,
+        "--percent",
+        "25",
+    ]);
+    assert_eq!(consumed, json!({ "consumed": unit }));
+
+    let expiring = sandbox.run(&["expiring", "--inventory", &inventory, "--days", "3"]);
+    let expiring = expiring.as_array().unwrap();
+    assert_eq!(expiring.len(), 1);
+    assert_eq!(expiring[0]["inventory"], json!(inventory));
+    assert_eq!(expiring[0]["unit"], json!(unit));
+    assert_eq!(expiring[0]["name"], json!("Milk"));
+    assert_eq!(expiring[0]["percent_left"], json!(75.0));
+    assert!(expiring[0]["expires_on"].is_string());
+
+    // Nothing expires within the next day
+    assert_eq!(sandbox.run(&["expiring", "--days", "1"]), json!([]));
+
+    sandbox.run(&["consume", "--inventory", &inventory, "--unit", &unit]);
+    assert_eq!(sandbox.run(&["expiring", "--days", "3"]), json!([]));
+}
+
+#[test]
+fn prints_json_results_and_errors() {
+    let sandbox = Sandbox::new();
+    let (inventory, item) = sandbox.pantry();
+
+    let inventories = sandbox.run(&["inventories"]);
+    assert_eq!(inventories.as_array().unwrap().len(), 1);
+    assert_eq!(inventories[0]["uuid"], json!(inventory));
+    assert_eq!(inventories[0]["name"], json!("Pantry"));
+    assert!(inventories[0]["owner"].is_string());
+
+    let items = sandbox.run(&["items", "--inventory", &inventory]);
+    assert_eq!(items.as_array().unwrap().len(), 1);
+    assert_eq!(items[0]["uuid"], json!(item));
+    assert_eq!(items[0]["name"], json!("Milk"));
+
+    let output = sandbox.output(&["items", "--inventory", &Uuid::new_v4().to_string()]);
+    assert!(!output.status.success());
+    let error: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(error, json!({ "error": "Inventory not found" }));
+}
+
+#[test]
+fn exports_and_imports_archives() {
+    let sandbox = Sandbox::new();
+    let (inventory, item) = sandbox.pantry();
+
+    let archive = sandbox.path("archive.json");
+    let written = sandbox.run(&["export", "--output", archive.to_str().unwrap()]);
+    assert_eq!(written, json!({ "written": archive }));
+
+    // Import into another store
+    let other = Sandbox::new();
+    let imported = other.run(&["import", archive.to_str().unwrap()]);
+    assert_eq!(
+        imported,
+        json!({ "created": [inventory], "merged": [], "renamed": [] })
+    );
+    let items = other.run(&["items", "--inventory", &inventory]);
+    assert_eq!(items[0]["uuid"], json!(item));
+
+    // The inventory exists now
+    assert!(!other
+        .output(&["import", archive.to_str().unwrap()])
+        .status
+        .success());
+    let merged = other.run(&["import", "--mode", "merge", archive.to_str().unwrap()]);
+    assert_eq!(merged["merged"], json!([inventory]));
+    let renamed = other.run(&["import", "--mode", "rename", archive.to_str().unwrap()]);
+    assert_eq!(renamed["renamed"].as_array().unwrap().len(), 1);
+    assert_eq!(other.run(&["inventories"]).as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn repairs_damaged_stores() {
+    let sandbox = Sandbox::new();
+    let (inventory, _) = sandbox.pantry();
+
+    // Damage an event in the middle of the log
+    let mut store = read_json(&sandbox.store());
+    store["inventory_projectors"][0]["events"]
+        .as_array_mut()
+        .unwrap()
+        .insert(1, json!({ "garbage": true }));
+    fs::write(sandbox.store(), serde_json::to_vec(&store).unwrap()).unwrap();
+
+    let inspection = sandbox.run(&["repair"]);
+    assert_eq!(inspection["repaired"], json!(false));
+    assert_eq!(inspection["issues"].as_array().unwrap().len(), 1);
+    assert_eq!(read_json(&sandbox.store()), store);
+
+    let repair = sandbox.run(&["repair", "--write"]);
+    assert_eq!(repair["repaired"], json!(true));
+    assert!(sandbox.path("store.quarantine.json").exists());
+
+    let check = sandbox.run(&["check"]);
+    assert_eq!(check["broken"], json!([]));
+    assert_eq!(check["inventories"][0]["uuid"], json!(inventory));
+    assert_eq!(check["inventories"][0]["items"], json!(1));
+    assert_eq!(sandbox.run(&["repair"])["issues"], json!([]));
+}